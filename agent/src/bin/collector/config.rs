@@ -0,0 +1,81 @@
+use crate::multiline::MultilineConfig;
+use agent::parse::ParserConfig;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// The collector's configuration file, JSON.
+///
+/// ```json
+/// {
+///   "inputs": [
+///     {"app_name": "web", "paths": ["/var/log/web/*.log"], "attributes": {"env": "prod"},
+///      "multiline": {"preset": "java"}}
+///   ],
+///   "wal_dir": "/var/lib/collector/wal"
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct CollectorConfig {
+	#[serde(default = "default_ingestion_url")]
+	pub ingestion_url: String,
+	#[serde(default = "default_batch_size")]
+	pub batch_size: usize,
+	/// Where the read offset of every file is kept between runs.
+	#[serde(default = "default_offsets_path")]
+	pub offsets_path: PathBuf,
+	/// Write-ahead log directory. Without one, lines read shortly before a
+	/// crash are lost: their offsets are saved once they are buffered.
+	#[serde(default)]
+	pub wal_dir: Option<PathBuf>,
+	#[serde(default = "default_poll_interval_ms")]
+	pub poll_interval_ms: u64,
+	pub inputs: Vec<InputConfig>,
+}
+
+fn default_ingestion_url() -> String {
+	"http://localhost:8001".to_string()
+}
+
+fn default_batch_size() -> usize {
+	100
+}
+
+fn default_offsets_path() -> PathBuf {
+	PathBuf::from("collector_offsets.json")
+}
+
+fn default_poll_interval_ms() -> u64 {
+	500
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InputConfig {
+	pub app_name: String,
+	/// Globs of the files to tail, see [`crate::glob`].
+	pub paths: Vec<String>,
+	/// Where files found at the first start, without a saved offset, are read from.
+	#[serde(default)]
+	pub start_at: StartAt,
+	/// Added to every entry of this input.
+	#[serde(default)]
+	pub attributes: HashMap<String, String>,
+	/// Reads level, timestamp and fields from each line. Without one, the line
+	/// is the message of an `Info` entry.
+	#[serde(default)]
+	pub parser: Option<ParserConfig>,
+	/// Joins the lines of events such as stack traces into one entry, e.g.
+	/// `{"preset": "java"}` or `{"start": "^\\d{4}-"}`.
+	#[serde(default)]
+	pub multiline: Option<MultilineConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartAt {
+	Beginning,
+	/// Skip what the file holds already. Files that appear later are always
+	/// read from the beginning.
+	#[default]
+	End,
+}
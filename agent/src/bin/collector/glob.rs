@@ -0,0 +1,163 @@
+//! Path globs: `*` and `?` within a path component, `**` for any number of
+//! directories. `**` does not descend into symlinked directories, so a link
+//! back up the tree cannot make it recurse forever.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Files matching `pattern`, sorted. Unreadable directories are skipped.
+pub fn expand(pattern: &str) -> Vec<PathBuf> {
+	let path = Path::new(pattern);
+	let mut base = PathBuf::new();
+	let mut components: Vec<String> = Vec::new();
+	for component in path.components() {
+		match component {
+			Component::Normal(name) => {
+				let name = name.to_string_lossy();
+				if components.is_empty() && !has_wildcard(&name) {
+					base.push(name.as_ref());
+				} else {
+					components.push(name.into_owned());
+				}
+			}
+			other => {
+				if components.is_empty() {
+					base.push(other.as_os_str());
+				} else {
+					components.push(other.as_os_str().to_string_lossy().into_owned());
+				}
+			}
+		}
+	}
+
+	let mut matches = Vec::new();
+	if components.is_empty() {
+		if base.is_file() {
+			matches.push(base);
+		}
+		return matches;
+	}
+	let base = if base.as_os_str().is_empty() { PathBuf::from(".") } else { base };
+	walk(&base, &components, &mut matches);
+	matches.sort();
+	matches.dedup();
+	matches
+}
+
+fn walk(dir: &Path, components: &[String], matches: &mut Vec<PathBuf>) {
+	let Some((component, rest)) = components.split_first() else {
+		return;
+	};
+
+	if component == "**" {
+		// Zero directories, then one more level down.
+		walk(dir, rest, matches);
+		for entry in read_dir(dir) {
+			if fs::symlink_metadata(&entry).is_ok_and(|metadata| metadata.is_dir()) {
+				walk(&entry, components, matches);
+			}
+		}
+		return;
+	}
+
+	for entry in read_dir(dir) {
+		let Some(name) = entry.file_name().map(|name| name.to_string_lossy().into_owned()) else {
+			continue;
+		};
+		if !matches_component(component, &name) {
+			continue;
+		}
+		if rest.is_empty() {
+			if entry.is_file() {
+				matches.push(entry);
+			}
+		} else if entry.is_dir() {
+			walk(&entry, rest, matches);
+		}
+	}
+}
+
+fn read_dir(dir: &Path) -> Vec<PathBuf> {
+	match fs::read_dir(dir) {
+		Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect(),
+		Err(_) => Vec::new(),
+	}
+}
+
+fn has_wildcard(component: &str) -> bool {
+	component.contains(['*', '?'])
+}
+
+/// Matches one path component against a pattern with `*` and `?`.
+pub fn matches_component(pattern: &str, name: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().collect();
+	let name: Vec<char> = name.chars().collect();
+	let (mut p, mut n) = (0, 0);
+	// Position after the last `*` and the name position it was tried at.
+	let mut backtrack: Option<(usize, usize)> = None;
+
+	while n < name.len() {
+		match pattern.get(p) {
+			Some('*') => {
+				backtrack = Some((p + 1, n));
+				p += 1;
+			}
+			Some(c) if *c == '?' || *c == name[n] => {
+				p += 1;
+				n += 1;
+			}
+			_ => match backtrack {
+				Some((star_p, star_n)) => {
+					p = star_p;
+					n = star_n + 1;
+					backtrack = Some((star_p, star_n + 1));
+				}
+				None => return false,
+			},
+		}
+	}
+	pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_matches_component() {
+		assert!(matches_component("*.log", "app.log"));
+		assert!(matches_component("app-?.log", "app-1.log"));
+		assert!(matches_component("*-*.log", "app-web-1.log"));
+		assert!(matches_component("*", ""));
+		assert!(!matches_component("*.log", "app.log.1"));
+		assert!(!matches_component("app-?.log", "app-10.log"));
+	}
+
+	#[test]
+	fn test_expand() {
+		let dir = std::env::temp_dir().join(format!("collector-glob-{}", uuid::Uuid::new_v4()));
+		for file in ["a.log", "b.txt", "web/c.log", "web/1/d.log"] {
+			let path = dir.join(file);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, "").unwrap();
+		}
+		let expand = |pattern: &str| -> Vec<String> {
+			expand(dir.join(pattern).to_str().unwrap())
+				.into_iter()
+				.map(|path| path.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+				.collect()
+		};
+
+		assert_eq!(expand("*.log"), vec!["a.log"]);
+		assert_eq!(expand("*/*.log"), vec!["web/c.log"]);
+		assert_eq!(expand("**/*.log"), vec!["a.log", "web/1/d.log", "web/c.log"]);
+		assert_eq!(expand("b.txt"), vec!["b.txt"]);
+		assert!(expand("missing/*.log").is_empty());
+
+		// A link back up the tree is not followed by `**`.
+		std::os::unix::fs::symlink(&dir, dir.join("web/loop")).unwrap();
+		assert_eq!(expand("**/*.log"), vec!["a.log", "web/1/d.log", "web/c.log"]);
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,160 @@
+//! Tails log files into the ingestion service.
+//!
+//! Reads its configuration from the JSON file named by `COLLECTOR_CONFIG`
+//! (`collector.json` by default), see [`config::CollectorConfig`].
+
+mod config;
+mod glob;
+mod multiline;
+mod tailer;
+
+use agent::parse::LineParser;
+use agent::{detect_resource_with_version, LogAgent, WalConfig};
+use common::{AttributeValue, LogEntry, LogLevel};
+use config::CollectorConfig;
+use std::collections::HashMap;
+use multiline::Multiline;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tailer::{Line, Tailer};
+use tracing::{error, info};
+
+#[tokio::main]
+async fn main() {
+	tracing_subscriber::fmt::init();
+
+	let config_path = std::env::var("COLLECTOR_CONFIG").unwrap_or_else(|_| "collector.json".to_string());
+	let config: CollectorConfig = match std::fs::read(&config_path)
+		.map_err(|e| e.to_string())
+		.and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+	{
+		Ok(config) => config,
+		Err(e) => {
+			error!("Failed to load collector config {}: {}", config_path, e);
+			std::process::exit(1);
+		}
+	};
+
+	let parsers: Vec<Option<LineParser>> = match config
+		.inputs
+		.iter()
+		.map(|input| input.parser.as_ref().map(LineParser::new).transpose())
+		.collect()
+	{
+		Ok(parsers) => parsers,
+		Err(e) => {
+			error!("Invalid parser in collector config {}: {}", config_path, e);
+			std::process::exit(1);
+		}
+	};
+
+	let mut multilines: Vec<Option<Multiline>> = match config
+		.inputs
+		.iter()
+		.map(|input| input.multiline.as_ref().map(Multiline::new).transpose())
+		.collect()
+	{
+		Ok(multilines) => multilines,
+		Err(e) => {
+			error!("Invalid multiline settings in collector config {}: {}", config_path, e);
+			std::process::exit(1);
+		}
+	};
+
+	let mut agent = LogAgent::new(config.ingestion_url.clone(), config.batch_size)
+		.with_resource(detect_resource_with_version(env!("CARGO_PKG_VERSION")));
+	if let Some(wal_dir) = &config.wal_dir {
+		agent = match agent.with_wal(WalConfig::new(wal_dir)) {
+			Ok(agent) => agent,
+			Err(e) => {
+				error!("Failed to open write-ahead log in {}: {}", wal_dir.display(), e);
+				std::process::exit(1);
+			}
+		};
+	}
+	agent.start_flush_loop().await;
+
+	let mut tailer = match Tailer::new(config.inputs.clone(), config.offsets_path.clone()) {
+		Ok(tailer) => tailer,
+		Err(e) => {
+			error!("Failed to load offsets from {}: {}", config.offsets_path.display(), e);
+			std::process::exit(1);
+		}
+	};
+
+	info!("Collector tailing {} inputs", config.inputs.len());
+	let mut interval = tokio::time::interval(Duration::from_millis(config.poll_interval_ms));
+	loop {
+		tokio::select! {
+			_ = interval.tick() => {}
+			_ = tokio::signal::ctrl_c() => break,
+		}
+
+		let now = Instant::now();
+		let mut lines = Vec::new();
+		for line in tailer.poll() {
+			match &mut multilines[line.input] {
+				Some(multiline) => lines.extend(multiline.push(line, now)),
+				None => lines.push(line),
+			}
+		}
+		for multiline in multilines.iter_mut().flatten() {
+			lines.extend(multiline.expire(now));
+		}
+		for line in lines {
+			agent.log(to_entry(&config, &parsers, line)).await;
+		}
+		// Lines of unfinished events are not in the agent yet, so their offsets
+		// must not be saved.
+		let held: HashMap<PathBuf, u64> = multilines
+			.iter()
+			.flatten()
+			.flat_map(Multiline::held_offsets)
+			.map(|(path, offset)| (path.clone(), offset))
+			.collect();
+		if let Err(e) = tailer.save_offsets(&held) {
+			error!("Failed to save offsets to {}: {}", config.offsets_path.display(), e);
+		}
+	}
+
+	info!("Collector shutting down");
+	for line in multilines.iter_mut().flatten().flat_map(Multiline::drain) {
+		agent.log(to_entry(&config, &parsers, line)).await;
+	}
+	agent.shutdown(Duration::from_secs(10)).await;
+	if let Err(e) = tailer.save_offsets(&HashMap::new()) {
+		error!("Failed to save offsets to {}: {}", config.offsets_path.display(), e);
+	}
+}
+
+fn to_entry(config: &CollectorConfig, parsers: &[Option<LineParser>], line: Line) -> LogEntry {
+	let input = &config.inputs[line.input];
+	let mut attributes: HashMap<String, AttributeValue> = input
+		.attributes
+		.iter()
+		.map(|(key, value)| (key.clone(), value.as_str().into()))
+		.collect();
+	attributes.insert("log.file.path".to_string(), line.path.to_string_lossy().as_ref().into());
+	if let Some(name) = line.path.file_name() {
+		attributes.insert("log.file.name".to_string(), name.to_string_lossy().as_ref().into());
+	}
+
+	match &parsers[line.input] {
+		Some(parser) => {
+			// Only the first line of a multi-line event is in the parsed format;
+			// the rest, such as a stack trace, is added to the message.
+			let (first, rest) = match line.text.split_once('\n') {
+				Some((first, rest)) => (first, Some(rest)),
+				None => (line.text.as_str(), None),
+			};
+			let mut entry = LogEntry::new(input.app_name.clone(), LogLevel::Info, String::new(), attributes);
+			parser.apply(first, &mut entry);
+			if let Some(rest) = rest {
+				entry.message.push('\n');
+				entry.message.push_str(rest);
+			}
+			entry
+		}
+		None => LogEntry::new(input.app_name.clone(), LogLevel::Info, line.text, attributes),
+	}
+}
@@ -0,0 +1,350 @@
+//! Joins the lines of one event, such as a stack trace, into a single line.
+//!
+//! A line matching the `start` pattern always starts a new event. Otherwise it
+//! continues the current event of its file if it matches a `continuation`
+//! pattern, follows a line matching a `continues_next` pattern, or, when there
+//! are no continuation patterns but a `start` pattern, does not match it. Any
+//! other line starts a new event.
+
+use crate::tailer::Line;
+use common::LogSystemError;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MultilineConfig {
+	/// Patterns for common formats, used in addition to the ones below.
+	#[serde(default)]
+	pub preset: Option<MultilinePreset>,
+	/// Matches the first line of an event, also when other patterns would
+	/// continue the event before it.
+	#[serde(default)]
+	pub start: Option<String>,
+	/// Match lines that belong to the event before them.
+	#[serde(default)]
+	pub continuation: Vec<String>,
+	/// Match lines whose next line belongs to the same event.
+	#[serde(default)]
+	pub continues_next: Vec<String>,
+	/// An event is cut off after this many lines; the rest starts a new one.
+	#[serde(default = "default_max_lines")]
+	pub max_lines: usize,
+	/// An event ends when its file has no new line for this long.
+	#[serde(default = "default_timeout_ms")]
+	pub timeout_ms: u64,
+}
+
+fn default_max_lines() -> usize {
+	500
+}
+
+fn default_timeout_ms() -> u64 {
+	1000
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultilinePreset {
+	/// Exceptions with `at` frames, `Caused by:` and `... n more`.
+	Java,
+	/// Tracebacks, including chained exceptions.
+	Python,
+	/// `panicked at` messages and their backtraces.
+	RustPanic,
+}
+
+impl MultilinePreset {
+	/// Continuation and continues-next patterns.
+	fn patterns(self) -> (&'static [&'static str], &'static [&'static str]) {
+		match self {
+			MultilinePreset::Java => (
+				&[
+					r"^\s+at\s",
+					r"^\s+\.\.\. \d+ (?:more|common frames omitted)",
+					r"^\s*(?:Caused by|Suppressed):",
+					r"^(?:[a-zA-Z_$][\w$]*\.)+[\w$]*(?:Exception|Error|Throwable)\b",
+				],
+				&[],
+			),
+			MultilinePreset::Python => (
+				&[
+					r"^\s+\S",
+					r"^Traceback \(most recent call last\):",
+					r"^\s*$",
+					r"^During handling of the above exception",
+					r"^The above exception was the direct cause",
+					r"^(?:[a-zA-Z_]\w*\.)*[a-zA-Z_]\w*(?:Error|Exception|Exit|Interrupt|Warning)\b",
+				],
+				&[],
+			),
+			MultilinePreset::RustPanic => (
+				&[r"^\s+\d+: ", r"^\s+at ", r"^stack backtrace:", r"^note: .*backtrace"],
+				&[r"panicked at .+:\d+:\d+:$"],
+			),
+		}
+	}
+}
+
+struct Pending {
+	line: Line,
+	lines: usize,
+	continues_next: bool,
+	updated: Instant,
+}
+
+pub struct Multiline {
+	start: Option<Regex>,
+	continuation: Vec<Regex>,
+	continues_next: Vec<Regex>,
+	max_lines: usize,
+	timeout: Duration,
+	/// The event being read from each file.
+	pending: HashMap<PathBuf, Pending>,
+}
+
+impl Multiline {
+	pub fn new(config: &MultilineConfig) -> Result<Self, LogSystemError> {
+		let (mut continuation, mut continues_next) = (config.continuation.clone(), config.continues_next.clone());
+		if let Some(preset) = config.preset {
+			let (preset_continuation, preset_continues_next) = preset.patterns();
+			continuation.extend(preset_continuation.iter().map(|pattern| pattern.to_string()));
+			continues_next.extend(preset_continues_next.iter().map(|pattern| pattern.to_string()));
+		}
+		if config.start.is_none() && continuation.is_empty() && continues_next.is_empty() {
+			return Err(LogSystemError::InvalidConfig(
+				"multiline needs a preset, a start pattern or continuation or continues_next patterns".to_string(),
+			));
+		}
+
+		Ok(Self {
+			start: config.start.as_deref().map(compile).transpose()?,
+			continuation: continuation.iter().map(|pattern| compile(pattern)).collect::<Result<_, _>>()?,
+			continues_next: continues_next.iter().map(|pattern| compile(pattern)).collect::<Result<_, _>>()?,
+			max_lines: config.max_lines.max(1),
+			timeout: Duration::from_millis(config.timeout_ms),
+			pending: HashMap::new(),
+		})
+	}
+
+	/// Adds a line, returning the event it completes, if any. Lines of an event
+	/// are joined with `\n`.
+	pub fn push(&mut self, line: Line, now: Instant) -> Option<Line> {
+		let starts = self.start.as_ref().is_some_and(|start| start.is_match(&line.text));
+		let continues = !starts && self.continues(&line.text);
+		let continues_next = self.continues_next.iter().any(|pattern| pattern.is_match(&line.text));
+		if let Some(pending) = self.pending.get_mut(&line.path) {
+			if pending.lines < self.max_lines && !starts && (pending.continues_next || continues) {
+				pending.line.text.push('\n');
+				pending.line.text.push_str(&line.text);
+				pending.lines += 1;
+				pending.continues_next = continues_next;
+				pending.updated = now;
+				return None;
+			}
+		}
+
+		let pending = Pending {
+			line,
+			lines: 1,
+			continues_next,
+			updated: now,
+		};
+		self.pending
+			.insert(pending.line.path.clone(), pending)
+			.map(|previous| finish(previous.line))
+	}
+
+	/// Ends the events whose files had no new line for the timeout.
+	pub fn expire(&mut self, now: Instant) -> Vec<Line> {
+		let expired: Vec<PathBuf> = self
+			.pending
+			.iter()
+			.filter(|(_, pending)| now.duration_since(pending.updated) >= self.timeout)
+			.map(|(path, _)| path.clone())
+			.collect();
+		expired
+			.into_iter()
+			.filter_map(|path| self.pending.remove(&path))
+			.map(|pending| finish(pending.line))
+			.collect()
+	}
+
+	/// The offset where the unfinished event of each file starts.
+	pub fn held_offsets(&self) -> impl Iterator<Item = (&PathBuf, u64)> {
+		self.pending.iter().map(|(path, pending)| (path, pending.line.offset))
+	}
+
+	/// Ends every event, at shutdown.
+	pub fn drain(&mut self) -> Vec<Line> {
+		self.pending.drain().map(|(_, pending)| finish(pending.line)).collect()
+	}
+
+	/// Whether a line that does not match `start` continues the event before it.
+	fn continues(&self, text: &str) -> bool {
+		if self.continuation.is_empty() {
+			return self.start.is_some();
+		}
+		self.continuation.iter().any(|pattern| pattern.is_match(text))
+	}
+}
+
+/// Drops the blank lines a continuation pattern took in at the end of the event.
+fn finish(mut line: Line) -> Line {
+	while let Some((rest, last)) = line.text.rsplit_once('\n') {
+		if !last.trim().is_empty() {
+			break;
+		}
+		let len = rest.len();
+		line.text.truncate(len);
+	}
+	line
+}
+
+fn compile(pattern: &str) -> Result<Regex, LogSystemError> {
+	Regex::new(pattern).map_err(|e| LogSystemError::InvalidConfig(format!("multiline pattern: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(preset: Option<MultilinePreset>, start: Option<&str>) -> MultilineConfig {
+		MultilineConfig {
+			preset,
+			start: start.map(str::to_string),
+			continuation: Vec::new(),
+			continues_next: Vec::new(),
+			max_lines: default_max_lines(),
+			timeout_ms: default_timeout_ms(),
+		}
+	}
+
+	fn events(multiline: &mut Multiline, text: &str) -> Vec<String> {
+		let now = Instant::now();
+		let mut events: Vec<String> = text
+			.lines()
+			.filter_map(|text| {
+				let line = Line {
+					input: 0,
+					path: PathBuf::from("app.log"),
+					offset: 0,
+					text: text.to_string(),
+				};
+				multiline.push(line, now)
+			})
+			.map(|line| line.text)
+			.collect();
+		events.extend(multiline.drain().into_iter().map(|line| line.text));
+		events
+	}
+
+	#[test]
+	fn test_presets() {
+		let java = "\
+2024-03-01 12:00:00 ERROR Request failed
+java.lang.IllegalStateException: boom
+\tat com.example.Service.handle(Service.java:42)
+Caused by: java.io.IOException: closed
+\tat com.example.Io.read(Io.java:7)
+\t... 12 more
+2024-03-01 12:00:01 INFO Next request";
+		let mut multiline = Multiline::new(&config(Some(MultilinePreset::Java), None)).unwrap();
+		let events_read = events(&mut multiline, java);
+		assert_eq!(events_read.len(), 2);
+		assert_eq!(events_read[0].lines().count(), 6);
+
+		let python = "\
+ERROR:root:Handler failed
+Traceback (most recent call last):
+  File \"app.py\", line 3, in <module>
+    handle()
+KeyError: 'user'
+
+During handling of the above exception, another exception occurred:
+
+Traceback (most recent call last):
+  File \"app.py\", line 5, in <module>
+ValueError: bad user
+INFO:root:Next request";
+		let mut multiline = Multiline::new(&config(Some(MultilinePreset::Python), None)).unwrap();
+		let events_read = events(&mut multiline, python);
+		assert_eq!(events_read.len(), 2);
+		assert!(events_read[0].ends_with("ValueError: bad user"));
+
+		let rust = "\
+thread 'main' panicked at src/main.rs:2:5:
+index out of bounds
+stack backtrace:
+   0: rust_begin_unwind
+             at /rustc/library/std/src/panicking.rs:645:5
+note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.
+Server restarting";
+		let mut multiline = Multiline::new(&config(Some(MultilinePreset::RustPanic), None)).unwrap();
+		let events_read = events(&mut multiline, rust);
+		assert_eq!(events_read.len(), 2);
+		assert_eq!(events_read[0].lines().nth(1), Some("index out of bounds"));
+		assert_eq!(events_read[1], "Server restarting");
+	}
+
+	#[test]
+	fn test_start_pattern_with_preset() {
+		// Both exception lines match a Java continuation pattern.
+		let java = "\
+java.lang.IllegalStateException: boom
+\tat com.example.Service.handle(Service.java:42)
+java.lang.IllegalStateException: again
+\tat com.example.Service.retry(Service.java:50)";
+		let mut multiline = Multiline::new(&config(Some(MultilinePreset::Java), None)).unwrap();
+		assert_eq!(events(&mut multiline, java).len(), 1);
+		let mut multiline = Multiline::new(&config(Some(MultilinePreset::Java), Some(r"^java\."))).unwrap();
+		let events_read = events(&mut multiline, java);
+		assert_eq!(events_read.len(), 2);
+		assert!(events_read[1].starts_with("java.lang.IllegalStateException: again\n"));
+
+		// A start line also ends an event whose last line continues into the next.
+		let rust = "2024-03-01 thread 'main' panicked at src/main.rs:2:5:\n2024-03-01 Server restarting";
+		let mut multiline = Multiline::new(&config(Some(MultilinePreset::RustPanic), None)).unwrap();
+		assert_eq!(events(&mut multiline, rust).len(), 1);
+		let mut multiline = Multiline::new(&config(Some(MultilinePreset::RustPanic), Some(r"^\d{4}-"))).unwrap();
+		assert_eq!(events(&mut multiline, rust).len(), 2);
+	}
+
+	#[test]
+	fn test_continues_next_alone() {
+		let mut config = config(None, None);
+		assert!(Multiline::new(&config).is_err());
+		config.continues_next = vec![r"\\$".to_string()];
+		let mut multiline = Multiline::new(&config).unwrap();
+		assert_eq!(
+			events(&mut multiline, "one \\\n two\nthree\nfour \\\n five \\\n six"),
+			vec!["one \\\n two", "three", "four \\\n five \\\n six"]
+		);
+	}
+
+	#[test]
+	fn test_start_pattern_max_lines_and_timeout() {
+		let mut config = config(None, Some(r"^\d{4}-"));
+		config.max_lines = 3;
+		let mut multiline = Multiline::new(&config).unwrap();
+		assert_eq!(
+			events(&mut multiline, "2024-03-01 one\n a\n b\n c\n2024-03-01 two"),
+			vec!["2024-03-01 one\n a\n b", " c", "2024-03-01 two"]
+		);
+
+		let start = Instant::now();
+		let line = |text: &str| Line {
+			input: 0,
+			path: PathBuf::from("app.log"),
+			offset: 0,
+			text: text.to_string(),
+		};
+		assert!(multiline.push(line("2024-03-01 three"), start).is_none());
+		assert!(multiline.push(line(" detail"), start).is_none());
+		assert!(multiline.expire(start + Duration::from_millis(999)).is_empty());
+		let expired = multiline.expire(start + Duration::from_secs(1));
+		assert_eq!(expired[0].text, "2024-03-01 three\n detail");
+	}
+}
@@ -0,0 +1,456 @@
+//! Follows the files matching the configured globs, across rotations.
+//!
+//! Files are identified by device and inode, so a file keeps its offset when
+//! it is renamed to another matching path. When a file is renamed away or
+//! deleted, the open handle is read for [`ROTATED_GRACE`] longer, for lines the
+//! writer appends before it reopens the path. A file that shrinks below the
+//! read offset was truncated in place (copytruncate) and is read again from the
+//! start.
+//!
+//! Offsets are saved per path and only count complete lines that the caller no
+//! longer holds; a saved offset is used again only if the path still holds the
+//! same file.
+
+use crate::config::{InputConfig, StartAt};
+use crate::glob;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long a rotated file is still read through its open handle.
+pub const ROTATED_GRACE: Duration = Duration::from_secs(5);
+/// Longer lines are split.
+pub const MAX_LINE_BYTES: usize = 256 * 1024;
+/// Read from one file per poll, so one busy file cannot starve the others.
+const MAX_READ_PER_POLL: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileId {
+	pub dev: u64,
+	pub ino: u64,
+}
+
+impl FileId {
+	fn of(metadata: &Metadata) -> Self {
+		Self {
+			dev: metadata.dev(),
+			ino: metadata.ino(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SavedOffset {
+	#[serde(flatten)]
+	id: FileId,
+	offset: u64,
+}
+
+/// A line read from a file, without its line ending.
+#[derive(Debug)]
+pub struct Line {
+	/// Index of the input whose glob matched the file.
+	pub input: usize,
+	pub path: PathBuf,
+	/// Offset of the first byte of the line in the file.
+	pub offset: u64,
+	pub text: String,
+}
+
+struct TailedFile {
+	input: usize,
+	path: PathBuf,
+	id: FileId,
+	file: File,
+	/// Position of the handle.
+	offset: u64,
+	/// Bytes after the last line ending.
+	partial: Vec<u8>,
+}
+
+impl TailedFile {
+	fn open(input: usize, path: PathBuf, offset: u64) -> io::Result<Self> {
+		let mut file = File::open(&path)?;
+		let id = FileId::of(&file.metadata()?);
+		file.seek(SeekFrom::Start(offset))?;
+		Ok(Self {
+			input,
+			path,
+			id,
+			file,
+			offset,
+			partial: Vec::new(),
+		})
+	}
+
+	/// Offset after the last complete line.
+	fn committed_offset(&self) -> u64 {
+		self.offset - self.partial.len() as u64
+	}
+
+	/// Starts over if the file is now shorter than what was read.
+	fn check_truncated(&mut self) -> io::Result<()> {
+		let len = self.file.metadata()?.len();
+		if len < self.offset {
+			info!("{} was truncated, reading it from the start", self.path.display());
+			self.file.seek(SeekFrom::Start(0))?;
+			self.offset = 0;
+			self.partial.clear();
+		}
+		Ok(())
+	}
+
+	/// Reads the complete lines appended since the last call.
+	fn read_lines(&mut self, lines: &mut Vec<Line>) -> io::Result<()> {
+		let mut chunk = vec![0; 64 * 1024];
+		let mut read_total = 0;
+		while read_total < MAX_READ_PER_POLL {
+			let read = self.file.read(&mut chunk)?;
+			if read == 0 {
+				break;
+			}
+			read_total += read as u64;
+			let chunk_start = self.offset;
+			self.offset += read as u64;
+
+			for (position, byte) in (chunk_start..).zip(&chunk[..read]) {
+				if *byte == b'\n' {
+					self.emit(lines, position);
+				} else {
+					self.partial.push(*byte);
+					if self.partial.len() >= MAX_LINE_BYTES {
+						self.emit(lines, position + 1);
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Emits what is left after the last line ending, once nothing more will be written.
+	fn finish(mut self, lines: &mut Vec<Line>) {
+		if !self.partial.is_empty() {
+			let end = self.offset;
+			self.emit(lines, end);
+		}
+	}
+
+	/// Emits the buffered bytes as a line ending before `end`.
+	fn emit(&mut self, lines: &mut Vec<Line>, end: u64) {
+		let offset = end - self.partial.len() as u64;
+		let mut bytes = std::mem::take(&mut self.partial);
+		if bytes.last() == Some(&b'\r') {
+			bytes.pop();
+		}
+		lines.push(Line {
+			input: self.input,
+			path: self.path.clone(),
+			offset,
+			text: String::from_utf8_lossy(&bytes).into_owned(),
+		});
+	}
+}
+
+pub struct Tailer {
+	inputs: Vec<InputConfig>,
+	files: BTreeMap<PathBuf, TailedFile>,
+	/// Files renamed away or deleted, read until the deadline.
+	rotated: Vec<(TailedFile, Instant)>,
+	offsets_path: PathBuf,
+	saved: HashMap<PathBuf, SavedOffset>,
+	/// Whether files have been discovered before, since the start of this process.
+	started: bool,
+}
+
+impl Tailer {
+	/// Loads the offsets saved at `offsets_path`, if there are any.
+	pub fn new(inputs: Vec<InputConfig>, offsets_path: PathBuf) -> io::Result<Self> {
+		let saved = match fs::read(&offsets_path) {
+			Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+				warn!("Ignoring unreadable offsets in {}: {}", offsets_path.display(), e);
+				HashMap::new()
+			}),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+			Err(e) => return Err(e),
+		};
+		Ok(Self {
+			inputs,
+			files: BTreeMap::new(),
+			rotated: Vec::new(),
+			offsets_path,
+			saved,
+			started: false,
+		})
+	}
+
+	/// Reads the lines written since the last poll, picking up rotations and new files.
+	pub fn poll(&mut self) -> Vec<Line> {
+		let mut lines = Vec::new();
+		let discovered = self.discover();
+		let discovered_ids: HashMap<FileId, &(usize, PathBuf, Metadata)> =
+			discovered.iter().map(|found| (FileId::of(&found.2), found)).collect();
+
+		// Rebuilt from scratch, so a file renamed onto the path of another one
+		// does not displace it before that one is looked at.
+		let mut files = BTreeMap::new();
+		for (path, mut file) in std::mem::take(&mut self.files) {
+			let current = fs::metadata(&path).ok().map(|metadata| FileId::of(&metadata));
+
+			if current == Some(file.id) {
+				if let Err(e) = file.check_truncated() {
+					warn!("Failed to check {}: {}", path.display(), e);
+				}
+			} else if let Some((_, new_path, _)) = discovered_ids.get(&file.id) {
+				// Renamed to another path that is tailed as well.
+				file.path = new_path.clone();
+			} else {
+				self.read(&mut file, &mut lines);
+				info!("{} was rotated", path.display());
+				self.rotated.push((file, Instant::now() + ROTATED_GRACE));
+				continue;
+			}
+			self.read(&mut file, &mut lines);
+			files.insert(file.path.clone(), file);
+		}
+		self.files = files;
+
+		let now = Instant::now();
+		for (mut file, deadline) in std::mem::take(&mut self.rotated) {
+			self.read(&mut file, &mut lines);
+			if now < deadline {
+				self.rotated.push((file, deadline));
+			} else {
+				file.finish(&mut lines);
+			}
+		}
+
+		let tailed: HashSet<FileId> = self.files.values().map(|file| file.id).collect();
+		for (input, path, metadata) in discovered {
+			let id = FileId::of(&metadata);
+			if self.files.contains_key(&path) || tailed.contains(&id) {
+				continue;
+			}
+			let offset = self.start_offset(input, &path, id, metadata.len());
+			match TailedFile::open(input, path.clone(), offset) {
+				Ok(mut file) => {
+					info!("Tailing {} from offset {}", path.display(), offset);
+					self.read(&mut file, &mut lines);
+					self.files.insert(path, file);
+				}
+				Err(e) => warn!("Failed to open {}: {}", path.display(), e),
+			}
+		}
+
+		self.started = true;
+		lines
+	}
+
+	/// Writes the offsets of the tailed files, if they changed. `held` has the
+	/// offset of the first line the caller still holds for a path, such as the
+	/// start of an unfinished multi-line event; it is read again after a restart.
+	pub fn save_offsets(&mut self, held: &HashMap<PathBuf, u64>) -> io::Result<()> {
+		let saved: HashMap<PathBuf, SavedOffset> = self
+			.files
+			.values()
+			.map(|file| {
+				let committed = file.committed_offset();
+				let saved = SavedOffset {
+					id: file.id,
+					offset: held.get(&file.path).map_or(committed, |held| committed.min(*held)),
+				};
+				(file.path.clone(), saved)
+			})
+			.collect();
+		if saved == self.saved {
+			return Ok(());
+		}
+		self.saved = saved;
+
+		let temp_path = self.offsets_path.with_extension("tmp");
+		fs::write(&temp_path, serde_json::to_vec_pretty(&self.saved)?)?;
+		fs::rename(&temp_path, &self.offsets_path)?;
+		Ok(())
+	}
+
+	/// Files matching the globs, each with the first input that matches it.
+	fn discover(&self) -> Vec<(usize, PathBuf, Metadata)> {
+		let mut seen = HashSet::new();
+		let mut discovered = Vec::new();
+		for (input, config) in self.inputs.iter().enumerate() {
+			for pattern in &config.paths {
+				for path in glob::expand(pattern) {
+					if !seen.insert(path.clone()) {
+						continue;
+					}
+					if let Ok(metadata) = fs::metadata(&path) {
+						discovered.push((input, path, metadata));
+					}
+				}
+			}
+		}
+		discovered
+	}
+
+	fn start_offset(&self, input: usize, path: &Path, id: FileId, len: u64) -> u64 {
+		match self.saved.get(path) {
+			Some(saved) if saved.id == id && saved.offset <= len => saved.offset,
+			// Rotated or truncated while the collector was down.
+			Some(_) => 0,
+			None if !self.started && self.inputs[input].start_at == StartAt::End => len,
+			None => 0,
+		}
+	}
+
+	fn read(&mut self, file: &mut TailedFile, lines: &mut Vec<Line>) {
+		if let Err(e) = file.read_lines(lines) {
+			warn!("Failed to read {}: {}", file.path.display(), e);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Write;
+
+	struct Fixture {
+		dir: PathBuf,
+	}
+
+	impl Fixture {
+		fn new() -> Self {
+			let dir = std::env::temp_dir().join(format!("collector-tailer-{}", uuid::Uuid::new_v4()));
+			fs::create_dir_all(&dir).unwrap();
+			Self { dir }
+		}
+
+		fn tailer(&self, start_at: StartAt) -> Tailer {
+			let input = InputConfig {
+				app_name: "web".to_string(),
+				paths: vec![self.dir.join("*.log").to_string_lossy().into_owned()],
+				start_at,
+				attributes: HashMap::new(),
+				parser: None,
+				multiline: None,
+			};
+			Tailer::new(vec![input], self.dir.join("offsets.json")).unwrap()
+		}
+
+		fn append(&self, name: &str, text: &str) {
+			let mut file = fs::OpenOptions::new().create(true).append(true).open(self.dir.join(name)).unwrap();
+			file.write_all(text.as_bytes()).unwrap();
+		}
+	}
+
+	impl Drop for Fixture {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.dir);
+		}
+	}
+
+	fn texts(lines: Vec<Line>) -> Vec<String> {
+		lines.into_iter().map(|line| line.text).collect()
+	}
+
+	#[test]
+	fn test_partial_lines_and_new_files() {
+		let fixture = Fixture::new();
+		fixture.append("app.log", "old\n");
+		let mut tailer = fixture.tailer(StartAt::End);
+		assert!(tailer.poll().is_empty());
+
+		fixture.append("app.log", "first\r\nsec");
+		assert_eq!(texts(tailer.poll()), vec!["first"]);
+		fixture.append("app.log", "ond\n");
+		assert_eq!(texts(tailer.poll()), vec!["second"]);
+
+		// Files appearing after the start are read from the beginning.
+		fixture.append("new.log", "a\nb\n");
+		assert_eq!(texts(tailer.poll()), vec!["a", "b"]);
+	}
+
+	#[test]
+	fn test_rename_rotation() {
+		let fixture = Fixture::new();
+		let mut tailer = fixture.tailer(StartAt::Beginning);
+		fixture.append("app.log", "one\n");
+		assert_eq!(texts(tailer.poll()), vec!["one"]);
+
+		// The writer appends once more before it reopens the path.
+		fixture.append("app.log", "two\n");
+		fs::rename(fixture.dir.join("app.log"), fixture.dir.join("app.log.1")).unwrap();
+		fixture.append("app.log.1", "three");
+		fixture.append("app.log", "four\n");
+		assert_eq!(texts(tailer.poll()), vec!["two", "four"]);
+
+		tailer.rotated[0].1 = Instant::now();
+		assert_eq!(texts(tailer.poll()), vec!["three"]);
+		assert!(tailer.rotated.is_empty());
+	}
+
+	#[test]
+	fn test_rename_cascade() {
+		let fixture = Fixture::new();
+		let mut tailer = fixture.tailer(StartAt::Beginning);
+		fixture.append("1.log", "one\n");
+		fixture.append("2.log", "two\n");
+		assert_eq!(texts(tailer.poll()), vec!["one", "two"]);
+
+		// Each file moves one step down, onto the path of the next one.
+		fs::rename(fixture.dir.join("2.log"), fixture.dir.join("3.log")).unwrap();
+		fs::rename(fixture.dir.join("1.log"), fixture.dir.join("2.log")).unwrap();
+		fixture.append("1.log", "three\n");
+		fixture.append("2.log", "four\n");
+		fixture.append("3.log", "five\n");
+		assert_eq!(texts(tailer.poll()), vec!["four", "five", "three"]);
+		assert!(tailer.rotated.is_empty());
+	}
+
+	#[test]
+	fn test_copytruncate_rotation() {
+		let fixture = Fixture::new();
+		let mut tailer = fixture.tailer(StartAt::Beginning);
+		fixture.append("app.log", "one\ntwo\n");
+		assert_eq!(texts(tailer.poll()), vec!["one", "two"]);
+
+		fs::write(fixture.dir.join("app.log"), "").unwrap();
+		fixture.append("app.log", "three\n");
+		assert_eq!(texts(tailer.poll()), vec!["three"]);
+	}
+
+	#[test]
+	fn test_offsets_survive_restart() {
+		let fixture = Fixture::new();
+		let mut tailer = fixture.tailer(StartAt::Beginning);
+		fixture.append("app.log", "one\ntw");
+		assert_eq!(texts(tailer.poll()), vec!["one"]);
+		tailer.save_offsets(&HashMap::new()).unwrap();
+		drop(tailer);
+
+		fixture.append("app.log", "o\n");
+		let mut tailer = fixture.tailer(StartAt::End);
+		assert_eq!(texts(tailer.poll()), vec!["two"]);
+	}
+
+	#[test]
+	fn test_held_lines_are_read_again_after_restart() {
+		let fixture = Fixture::new();
+		let mut tailer = fixture.tailer(StartAt::Beginning);
+		fixture.append("app.log", "one\ntwo\nthree\n");
+		let lines = tailer.poll();
+		assert_eq!(lines.iter().map(|line| line.offset).collect::<Vec<_>>(), vec![0, 4, 8]);
+
+		// "two" starts an event that is not finished yet.
+		let held = HashMap::from([(fixture.dir.join("app.log"), lines[1].offset)]);
+		tailer.save_offsets(&held).unwrap();
+		drop(tailer);
+
+		let mut tailer = fixture.tailer(StartAt::End);
+		assert_eq!(texts(tailer.poll()), vec!["two", "three"]);
+	}
+}
@@ -0,0 +1,352 @@
+//! The agent's in-memory buffer, bounded in entries and in bytes.
+
+use common::{AttributeValue, LogEntry, LogLevel};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+/// What `LogAgent::log` does with an entry when the buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+	/// Wait until a batch has been taken out of the buffer.
+	Block,
+	/// Drop the entry being logged.
+	DropNewest,
+	/// Drop the oldest buffered entries.
+	DropOldest,
+	/// Drop the oldest entries of the lowest severity first, down to the
+	/// severity of the entry being logged; drop that entry if nothing buffered
+	/// is less severe.
+	DropLowSeverity,
+}
+
+#[derive(Debug, Clone)]
+pub struct BufferLimits {
+	pub max_entries: usize,
+	/// Estimated in-memory size of the buffered entries.
+	pub max_bytes: usize,
+	pub overflow: OverflowPolicy,
+	/// How often the flush loop logs a synthetic "N logs dropped" entry while entries are being dropped.
+	pub drop_report_interval: Duration,
+}
+
+impl Default for BufferLimits {
+	fn default() -> Self {
+		Self {
+			max_entries: 100_000,
+			max_bytes: 64 * 1024 * 1024,
+			overflow: OverflowPolicy::DropLowSeverity,
+			drop_report_interval: Duration::from_secs(30),
+		}
+	}
+}
+
+/// Outcome of [`Buffer::push`].
+#[derive(Debug)]
+pub(crate) enum Push {
+	/// Buffered, after evicting `evicted` to make room.
+	Accepted { evicted: Vec<LogEntry> },
+	/// The entry was dropped.
+	Dropped(LogEntry),
+	/// The buffer is full and the policy is [`OverflowPolicy::Block`]; the entry is handed back.
+	Full(LogEntry),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Buffer {
+	entries: VecDeque<LogEntry>,
+	bytes: usize,
+	dropped_total: u64,
+	/// Drops per level since the last report.
+	unreported: BTreeMap<LogLevel, u64>,
+}
+
+impl Buffer {
+	pub(crate) fn push(&mut self, entry: LogEntry, limits: &BufferLimits) -> Push {
+		let size = estimated_size(&entry);
+		if self.fits(size, limits) {
+			self.push_back(entry, size);
+			return Push::Accepted { evicted: Vec::new() };
+		}
+
+		let mut evicted = Vec::new();
+		match limits.overflow {
+			OverflowPolicy::Block if !self.entries.is_empty() => return Push::Full(entry),
+			OverflowPolicy::Block | OverflowPolicy::DropNewest => {}
+			OverflowPolicy::DropOldest => {
+				while !self.fits(size, limits) {
+					let Some(oldest) = self.pop_front() else {
+						break;
+					};
+					evicted.push(oldest);
+				}
+			}
+			OverflowPolicy::DropLowSeverity => {
+				// Pick the entries first, so that nothing is evicted for an entry
+				// that would not fit anyway. Lowest level first, oldest first within a level.
+				let mut candidates: Vec<usize> =
+					(0..self.entries.len()).filter(|&i| self.entries[i].level < entry.level).collect();
+				candidates.sort_by_key(|&i| self.entries[i].level);
+				let (mut len, mut bytes) = (self.entries.len(), self.bytes);
+				let mut victims = Vec::new();
+				for index in candidates {
+					if len < limits.max_entries && bytes + size <= limits.max_bytes {
+						break;
+					}
+					len -= 1;
+					bytes -= estimated_size(&self.entries[index]);
+					victims.push(index);
+				}
+				if len < limits.max_entries && bytes + size <= limits.max_bytes {
+					victims.sort_unstable();
+					for index in victims.into_iter().rev() {
+						let removed = self.entries.remove(index).expect("index is in bounds");
+						self.bytes -= estimated_size(&removed);
+						evicted.push(removed);
+					}
+					evicted.reverse();
+				}
+			}
+		}
+
+		for entry in &evicted {
+			self.count_drop(entry.level);
+		}
+		if self.fits(size, limits) {
+			self.push_back(entry, size);
+			Push::Accepted { evicted }
+		} else {
+			// The entry does not fit even so; put back the oldest entries evicted for
+			// it, at the front where they came from.
+			for entry in evicted.into_iter().rev() {
+				self.dropped_total -= 1;
+				*self.unreported.get_mut(&entry.level).expect("counted above") -= 1;
+				let size = estimated_size(&entry);
+				self.bytes += size;
+				self.entries.push_front(entry);
+			}
+			self.count_drop(entry.level);
+			Push::Dropped(entry)
+		}
+	}
+
+	/// Puts entries back at the front, e.g. after a failed send, regardless of the limits.
+	pub(crate) fn requeue(&mut self, entries: Vec<LogEntry>) {
+		for entry in entries.into_iter().rev() {
+			self.bytes += estimated_size(&entry);
+			self.entries.push_front(entry);
+		}
+	}
+
+	/// Appends an entry regardless of the limits.
+	pub(crate) fn push_unbounded(&mut self, entry: LogEntry) {
+		let size = estimated_size(&entry);
+		self.push_back(entry, size);
+	}
+
+	/// Takes up to `max` entries from the front.
+	pub(crate) fn drain(&mut self, max: usize) -> Vec<LogEntry> {
+		let logs: Vec<LogEntry> = self.entries.drain(..max.min(self.entries.len())).collect();
+		self.bytes -= logs.iter().map(estimated_size).sum::<usize>();
+		logs
+	}
+
+	pub(crate) fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub(crate) fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub(crate) fn dropped_total(&self) -> u64 {
+		self.dropped_total
+	}
+
+	/// Drops per level since the previous call.
+	pub(crate) fn take_unreported_drops(&mut self) -> BTreeMap<LogLevel, u64> {
+		std::mem::take(&mut self.unreported)
+	}
+
+	fn fits(&self, size: usize, limits: &BufferLimits) -> bool {
+		self.entries.len() < limits.max_entries && self.bytes + size <= limits.max_bytes
+	}
+
+	fn push_back(&mut self, entry: LogEntry, size: usize) {
+		self.bytes += size;
+		self.entries.push_back(entry);
+	}
+
+	fn pop_front(&mut self) -> Option<LogEntry> {
+		let entry = self.entries.pop_front()?;
+		self.bytes -= estimated_size(&entry);
+		Some(entry)
+	}
+
+	fn count_drop(&mut self, level: LogLevel) {
+		self.count_dropped(level, 1);
+	}
+
+	/// Counts entries dropped before they reached the buffer.
+	pub(crate) fn count_dropped(&mut self, level: LogLevel, count: u64) {
+		self.dropped_total += count;
+		*self.unreported.entry(level).or_default() += count;
+	}
+}
+
+impl From<Vec<LogEntry>> for Buffer {
+	fn from(entries: Vec<LogEntry>) -> Self {
+		let mut buffer = Buffer::default();
+		buffer.requeue(entries);
+		buffer
+	}
+}
+
+/// Rough heap size of an entry; cheaper than serializing it.
+pub(crate) fn estimated_size(entry: &LogEntry) -> usize {
+	const OVERHEAD: usize = 128;
+	OVERHEAD
+		+ entry.message.len()
+		+ entry
+			.attributes
+			.iter()
+			.map(|(key, value)| key.len() + attribute_size(value))
+			.sum::<usize>()
+}
+
+fn attribute_size(value: &AttributeValue) -> usize {
+	match value {
+		AttributeValue::String(s) => s.len() + 24,
+		AttributeValue::Array(items) => 24 + items.iter().map(attribute_size).sum::<usize>(),
+		AttributeValue::Object(fields) => {
+			48 + fields
+				.iter()
+				.map(|(key, value)| key.len() + attribute_size(value))
+				.sum::<usize>()
+		}
+		AttributeValue::Bool(_) | AttributeValue::Int(_) | AttributeValue::Float(_) => 16,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+
+	fn entry(level: LogLevel, message: &str) -> LogEntry {
+		LogEntry::new("test-app".to_string(), level, message.to_string(), HashMap::new())
+	}
+
+	fn limits(overflow: OverflowPolicy) -> BufferLimits {
+		BufferLimits {
+			max_entries: 3,
+			overflow,
+			..BufferLimits::default()
+		}
+	}
+
+	fn fill(buffer: &mut Buffer, limits: &BufferLimits, levels: &[LogLevel]) {
+		for (i, level) in levels.iter().enumerate() {
+			assert!(matches!(buffer.push(entry(*level, &i.to_string()), limits), Push::Accepted { .. }));
+		}
+	}
+
+	fn messages(buffer: &Buffer) -> Vec<&str> {
+		buffer.entries.iter().map(|entry| entry.message.as_str()).collect()
+	}
+
+	#[test]
+	fn test_drop_newest_and_block() {
+		for overflow in [OverflowPolicy::DropNewest, OverflowPolicy::Block] {
+			let limits = limits(overflow);
+			let mut buffer = Buffer::default();
+			fill(&mut buffer, &limits, &[LogLevel::Info; 3]);
+
+			let outcome = buffer.push(entry(LogLevel::Error, "new"), &limits);
+			if overflow == OverflowPolicy::Block {
+				assert!(matches!(outcome, Push::Full(_)));
+				assert_eq!(buffer.dropped_total(), 0);
+			} else {
+				assert!(matches!(outcome, Push::Dropped(_)));
+				assert_eq!(buffer.dropped_total(), 1);
+			}
+			assert_eq!(messages(&buffer), vec!["0", "1", "2"]);
+		}
+	}
+
+	#[test]
+	fn test_drop_oldest() {
+		let limits = limits(OverflowPolicy::DropOldest);
+		let mut buffer = Buffer::default();
+		fill(&mut buffer, &limits, &[LogLevel::Error, LogLevel::Info, LogLevel::Info]);
+
+		let Push::Accepted { evicted } = buffer.push(entry(LogLevel::Debug, "new"), &limits) else {
+			panic!("expected the entry to be buffered");
+		};
+		assert_eq!(evicted[0].message, "0");
+		assert_eq!(messages(&buffer), vec!["1", "2", "new"]);
+		assert_eq!(buffer.take_unreported_drops(), BTreeMap::from([(LogLevel::Error, 1)]));
+		assert!(buffer.take_unreported_drops().is_empty());
+	}
+
+	#[test]
+	fn test_drop_low_severity() {
+		let limits = limits(OverflowPolicy::DropLowSeverity);
+		let mut buffer = Buffer::default();
+		fill(&mut buffer, &limits, &[LogLevel::Warn, LogLevel::Debug, LogLevel::Info]);
+
+		assert!(matches!(buffer.push(entry(LogLevel::Error, "a"), &limits), Push::Accepted { .. }));
+		assert!(matches!(buffer.push(entry(LogLevel::Error, "b"), &limits), Push::Accepted { .. }));
+		assert_eq!(messages(&buffer), vec!["0", "a", "b"]);
+
+		// Nothing buffered is less severe than a Warn.
+		assert!(matches!(buffer.push(entry(LogLevel::Warn, "c"), &limits), Push::Dropped(_)));
+		assert_eq!(messages(&buffer), vec!["0", "a", "b"]);
+		assert_eq!(
+			buffer.take_unreported_drops(),
+			BTreeMap::from([(LogLevel::Debug, 1), (LogLevel::Info, 1), (LogLevel::Warn, 1)])
+		);
+	}
+
+	#[test]
+	fn test_drop_low_severity_keeps_order_when_the_entry_does_not_fit() {
+		let small = estimated_size(&entry(LogLevel::Info, "x"));
+		let limits = BufferLimits {
+			max_entries: 10,
+			max_bytes: 4 * small,
+			overflow: OverflowPolicy::DropLowSeverity,
+			..BufferLimits::default()
+		};
+		let mut buffer = Buffer::default();
+		fill(&mut buffer, &limits, &[LogLevel::Warn, LogLevel::Debug, LogLevel::Error, LogLevel::Info]);
+
+		// Evicting both entries below Warn would not make room for it.
+		let large = entry(LogLevel::Warn, &"x".repeat(3 * small));
+		assert!(matches!(buffer.push(large, &limits), Push::Dropped(_)));
+		assert_eq!(messages(&buffer), vec!["0", "1", "2", "3"]);
+		assert_eq!(buffer.bytes, 4 * small);
+		assert_eq!(buffer.take_unreported_drops(), BTreeMap::from([(LogLevel::Warn, 1)]));
+
+		// An entry that fits evicts the Debug in the middle and keeps the rest in order.
+		let Push::Accepted { evicted } = buffer.push(entry(LogLevel::Error, "y"), &limits) else {
+			panic!("expected the entry to be buffered");
+		};
+		assert_eq!(evicted[0].message, "1");
+		assert_eq!(messages(&buffer), vec!["0", "2", "3", "y"]);
+	}
+
+	#[test]
+	fn test_byte_limit() {
+		let limits = BufferLimits {
+			max_bytes: 2 * estimated_size(&entry(LogLevel::Info, "x")),
+			overflow: OverflowPolicy::DropOldest,
+			..BufferLimits::default()
+		};
+		let mut buffer = Buffer::default();
+		fill(&mut buffer, &limits, &[LogLevel::Info; 2]);
+
+		assert!(matches!(buffer.push(entry(LogLevel::Info, &"x".repeat(1000)), &limits), Push::Dropped(_)));
+		assert_eq!(messages(&buffer), vec!["0", "1"]);
+		assert_eq!(buffer.drain(10).len(), 2);
+		assert_eq!(buffer.bytes, 0);
+	}
+}
@@ -0,0 +1,231 @@
+//! Builds the structured `error` field of a [`LogEntry`](common::LogEntry)
+//! from Rust errors.
+//!
+//! ```ignore
+//! if let Err(e) = query().await {
+//!     let entry = LogEntry::new(app, LogLevel::Error, "query failed".into(), attrs)
+//!         .with_error(agent::error::capture(&e));
+//!     agent.log(entry).await;
+//! }
+//! ```
+//!
+//! Backtraces follow the `std` rules: frames are only captured when
+//! `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` is set. A backtrace captured here
+//! starts at the logging call; an `anyhow::Error` carries the one taken where
+//! the error was created.
+
+use common::{ErrorCause, ErrorInfo, StackFrame};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::error::Error;
+
+/// Frames kept per error, innermost first.
+pub const MAX_FRAMES: usize = 64;
+
+/// Leading frames that belong to error and backtrace machinery rather than the caller.
+const SKIPPED_FRAME_PREFIXES: &[&str] = &[
+	"agent::error::",
+	"agent::layer::",
+	"tracing::",
+	"tracing_core::",
+	"tracing_subscriber::",
+	"<tracing_subscriber::",
+	"anyhow::",
+	"<anyhow::",
+	"std::backtrace",
+	"core::",
+	"<core::",
+	"alloc::",
+	"<alloc::",
+];
+
+/// Frame after which only the runtime's start-up code follows.
+const RUNTIME_START_FRAME: &str = "std::sys::backtrace::__rust_begin_short_backtrace";
+
+/// Describes `error` and its `source()` chain, with a backtrace captured here.
+///
+/// The type is the name of `E` without its module path.
+pub fn capture<E: Error + 'static>(error: &E) -> ErrorInfo {
+	let mut info = describe(error);
+	info.error_type = short_type_name(std::any::type_name::<E>());
+	info.frames = frames(&Backtrace::capture());
+	info
+}
+
+/// Like [`capture`] for a type-erased error. The type is taken from the error's
+/// `Debug` output, e.g. `TimeoutError` for `TimeoutError { after: 5s }`.
+pub fn capture_dyn(error: &(dyn Error + 'static)) -> ErrorInfo {
+	let mut info = describe(error);
+	info.frames = frames(&Backtrace::capture());
+	info
+}
+
+/// Describes an `anyhow::Error` with the backtrace it was created with.
+pub fn capture_anyhow(error: &anyhow::Error) -> ErrorInfo {
+	let mut info = describe(error.as_ref());
+	info.frames = frames(error.backtrace());
+	info
+}
+
+fn describe(error: &(dyn Error + 'static)) -> ErrorInfo {
+	let mut info = ErrorInfo::new(dyn_type_name(error), error.to_string());
+
+	let mut source = error.source();
+	while let Some(cause) = source {
+		info.causes.push(ErrorCause {
+			error_type: dyn_type_name(cause),
+			message: cause.to_string(),
+		});
+		source = cause.source();
+	}
+	info
+}
+
+fn dyn_type_name(error: &(dyn Error + 'static)) -> String {
+	if error.is::<std::io::Error>() {
+		return "io::Error".to_string();
+	}
+	type_name_from_debug(&format!("{:?}", error)).unwrap_or_else(|| "Error".to_string())
+}
+
+/// The leading identifier of a derived `Debug` output, such as `Timeout` in
+/// `Timeout { after: 5 }` or `Timeout(5)`.
+fn type_name_from_debug(debug: &str) -> Option<String> {
+	let end = debug
+		.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+		.unwrap_or(debug.len());
+	let (name, rest) = debug.split_at(end);
+	let looks_like_type = name.starts_with(|c: char| c.is_ascii_uppercase())
+		&& (rest.is_empty() || rest.starts_with([' ', '(', '{']));
+	looks_like_type.then(|| name.to_string())
+}
+
+/// `myapp::db::TimeoutError<T>` becomes `TimeoutError`.
+fn short_type_name(type_name: &str) -> String {
+	let without_generics = type_name.split('<').next().unwrap_or(type_name);
+	without_generics.rsplit("::").next().unwrap_or(without_generics).to_string()
+}
+
+fn frames(backtrace: &Backtrace) -> Vec<StackFrame> {
+	if backtrace.status() != BacktraceStatus::Captured {
+		return Vec::new();
+	}
+	parse_backtrace(&backtrace.to_string())
+}
+
+/// Parses the `Display` output of a [`Backtrace`]:
+///
+/// ```text
+///    0: myapp::db::query
+///              at ./src/db.rs:42:9
+/// ```
+///
+/// Inlined functions are listed without an index under the frame they were
+/// inlined into; each becomes a frame of its own.
+fn parse_backtrace(text: &str) -> Vec<StackFrame> {
+	let mut frames: Vec<StackFrame> = Vec::new();
+
+	for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+		if let Some(location) = line.strip_prefix("at ") {
+			if let Some(frame) = frames.last_mut() {
+				let (file, line, column) = parse_location(location);
+				frame.file = Some(file);
+				frame.line = line;
+				frame.column = column;
+			}
+			continue;
+		}
+
+		let function = match line.split_once(": ") {
+			Some((index, function)) if index.bytes().all(|b| b.is_ascii_digit()) => function,
+			_ => line,
+		};
+		if function == RUNTIME_START_FRAME {
+			break;
+		}
+		if frames.is_empty() && SKIPPED_FRAME_PREFIXES.iter().any(|prefix| function.starts_with(prefix)) {
+			continue;
+		}
+		if frames.len() == MAX_FRAMES {
+			break;
+		}
+		frames.push(StackFrame {
+			function: function.to_string(),
+			file: None,
+			line: None,
+			column: None,
+		});
+	}
+
+	frames
+}
+
+/// Splits `path:line:column`; paths may contain colons themselves.
+fn parse_location(location: &str) -> (String, Option<u32>, Option<u32>) {
+	let mut parts = location.rsplitn(3, ':');
+	let column = parts.next();
+	let line = parts.next();
+	match (parts.next(), line.and_then(|l| l.parse().ok()), column.and_then(|c| c.parse().ok())) {
+		(Some(file), Some(line), Some(column)) => (file.to_string(), Some(line), Some(column)),
+		_ => (location.to_string(), None, None),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[derive(Debug)]
+	struct Timeout {
+		source: std::io::Error,
+	}
+
+	impl std::fmt::Display for Timeout {
+		fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+			f.write_str("query timed out")
+		}
+	}
+
+	impl Error for Timeout {
+		fn source(&self) -> Option<&(dyn Error + 'static)> {
+			Some(&self.source)
+		}
+	}
+
+	#[test]
+	fn test_capture_walks_sources() {
+		let error = Timeout {
+			source: std::io::Error::new(std::io::ErrorKind::TimedOut, "connection reset"),
+		};
+
+		let info = capture(&error);
+		assert_eq!(info.error_type, "Timeout");
+		assert_eq!(info.message, "query timed out");
+		assert_eq!(info.causes.len(), 1);
+		assert_eq!(info.causes[0].error_type, "io::Error");
+		assert_eq!(info.causes[0].message, "connection reset");
+
+		assert_eq!(capture_dyn(&error).error_type, "Timeout");
+	}
+
+	#[test]
+	fn test_parse_backtrace() {
+		let text = "   0: agent::error::capture
+             at ./agent/src/error.rs:44:18
+   1: myapp::db::query
+             at ./src/db.rs:42:9
+      myapp::db::run
+             at C:\\src\\db.rs:7:1
+   2: std::sys::backtrace::__rust_begin_short_backtrace
+             at /rustc/library/std/src/sys/backtrace.rs:166:18
+   3: main";
+
+		let frames = parse_backtrace(text);
+		assert_eq!(frames.len(), 2);
+		assert_eq!(frames[0].function, "myapp::db::query");
+		assert_eq!(frames[0].file.as_deref(), Some("./src/db.rs"));
+		assert_eq!((frames[0].line, frames[0].column), (Some(42), Some(9)));
+		assert_eq!(frames[0].file_name(), Some("db.rs"));
+		assert_eq!(frames[1].function, "myapp::db::run");
+		assert_eq!(frames[1].file.as_deref(), Some("C:\\src\\db.rs"));
+	}
+}
@@ -0,0 +1,104 @@
+//! Hands entries from synchronous logging frontends to a [`LogAgent`] without
+//! blocking the thread that logs.
+
+use crate::LogAgent;
+use common::{LogEntry, LogLevel};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Entries waiting to be handed to the agent. Entries beyond this are dropped
+/// rather than blocking the thread that logged them.
+pub const CHANNEL_CAPACITY: usize = 8192;
+
+/// Targets whose records are never shipped: the agent's own, and those of the
+/// HTTP client it sends with, since shipping them would log again.
+const FEEDBACK_TARGETS: &[&str] = &[env!("CARGO_CRATE_NAME"), "reqwest", "hyper", "h2"];
+
+pub(crate) struct Forwarder {
+	sender: mpsc::Sender<LogEntry>,
+	/// Entries dropped on a full channel, per level, until the agent counts them.
+	dropped: Arc<[AtomicU64; 6]>,
+}
+
+impl Forwarder {
+	/// Spawns the task that logs received entries into `agent`. Must be called
+	/// from within a Tokio runtime.
+	pub(crate) fn spawn(agent: &LogAgent) -> Self {
+		let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+		let forwarder = Self::new(sender);
+
+		let agent = agent.clone();
+		let dropped = forwarder.dropped.clone();
+		tokio::spawn(async move {
+			while let Some(entry) = receiver.recv().await {
+				count_dropped(&agent, &dropped).await;
+				agent.log(entry).await;
+			}
+		});
+		forwarder
+	}
+
+	pub(crate) fn new(sender: mpsc::Sender<LogEntry>) -> Self {
+		Self {
+			sender,
+			dropped: Arc::new(Default::default()),
+		}
+	}
+
+	pub(crate) fn send(&self, entry: LogEntry) {
+		let level = entry.level;
+		if self.sender.try_send(entry).is_err() {
+			self.dropped[level_index(level)].fetch_add(1, Ordering::Relaxed);
+		}
+	}
+}
+
+/// Adds the entries the forwarder dropped to the agent's drop counts.
+async fn count_dropped(agent: &LogAgent, dropped: &[AtomicU64; 6]) {
+	for (level, count) in LogLevel::ALL.iter().zip(dropped.iter()) {
+		let count = count.swap(0, Ordering::Relaxed);
+		if count > 0 {
+			agent.buffer.lock().await.count_dropped(*level, count);
+		}
+	}
+}
+
+fn level_index(level: LogLevel) -> usize {
+	LogLevel::ALL.iter().position(|l| *l == level).expect("every level is listed")
+}
+
+pub(crate) fn is_feedback_target(target: &str) -> bool {
+	FEEDBACK_TARGETS.iter().any(|prefix| {
+		target
+			.strip_prefix(prefix)
+			.is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+
+	#[test]
+	fn test_full_channel_counts_drops() {
+		let (sender, _receiver) = mpsc::channel(1);
+		let forwarder = Forwarder::new(sender);
+		for _ in 0..3 {
+			forwarder.send(LogEntry::new("test-app".to_string(), LogLevel::Error, "burst".to_string(), HashMap::new()));
+		}
+
+		assert_eq!(forwarder.dropped[level_index(LogLevel::Error)].load(Ordering::Relaxed), 2);
+		assert_eq!(forwarder.dropped[level_index(LogLevel::Info)].load(Ordering::Relaxed), 0);
+	}
+
+	#[test]
+	fn test_feedback_targets() {
+		assert!(is_feedback_target("agent"));
+		assert!(is_feedback_target("agent::wal"));
+		assert!(is_feedback_target("hyper::proto::h1"));
+		assert!(!is_feedback_target("agent_test"));
+		assert!(!is_feedback_target("h2o"));
+	}
+}
@@ -0,0 +1,250 @@
+//! A `tracing_subscriber` layer that ships `tracing` events through a [`LogAgent`].
+//!
+//! ```ignore
+//! let agent = LogAgent::new(url, 100);
+//! agent.start_flush_loop().await;
+//! tracing_subscriber::registry()
+//!     .with(AgentLayer::new(&agent, "checkout").with_targets("checkout=debug,hyper=warn".parse()?))
+//!     .init();
+//!
+//! let span = tracing::info_span!("order", order_id = 42);
+//! let _enter = span.enter();
+//! tracing::warn!(retries = 3, "payment slow"); // attributes: order_id, retries
+//! ```
+//!
+//! Events carry the fields of the spans they occur in and their own fields,
+//! inner values overriding outer ones; the `message` field becomes the entry's
+//! message. An `error` recorded as a `dyn Error` becomes the entry's
+//! structured error. Entries get the trace and span ids of the event's span.
+
+use crate::forward::{self, Forwarder};
+use crate::LogAgent;
+use common::{AttributeValue, LogEntry, LogLevel};
+use std::collections::HashMap;
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Events are queued for the agent and dropped, rather than blocking the
+/// emitting thread, once [`CHANNEL_CAPACITY`](forward::CHANNEL_CAPACITY) are
+/// waiting. Events of the agent and its HTTP client are never shipped.
+pub struct AgentLayer {
+	forwarder: Forwarder,
+	app_name: String,
+	targets: Targets,
+}
+
+impl AgentLayer {
+	/// Creates the layer and the task that hands its entries to `agent`.
+	/// Must be called from within a Tokio runtime.
+	///
+	/// Events at `INFO` and above are shipped by default.
+	pub fn new(agent: &LogAgent, app_name: impl Into<String>) -> Self {
+		Self::with_forwarder(Forwarder::spawn(agent), app_name.into())
+	}
+
+	fn with_forwarder(forwarder: Forwarder, app_name: String) -> Self {
+		Self {
+			forwarder,
+			app_name,
+			targets: Targets::new().with_default(Level::INFO),
+		}
+	}
+
+	/// Sets the level shipped per target, e.g. `"my_app=debug,hyper=warn"`.
+	pub fn with_targets(mut self, targets: Targets) -> Self {
+		self.targets = targets;
+		self
+	}
+}
+
+/// Fields recorded on a span, kept in its extensions.
+struct SpanFields(HashMap<String, AttributeValue>);
+
+impl<S> Layer<S> for AgentLayer
+where
+	S: Subscriber + for<'a> LookupSpan<'a>,
+{
+	fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+		let Some(span) = ctx.span(id) else {
+			return;
+		};
+		let mut visitor = FieldVisitor::default();
+		attrs.record(&mut visitor);
+		span.extensions_mut().insert(SpanFields(visitor.fields));
+	}
+
+	fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+		let Some(span) = ctx.span(id) else {
+			return;
+		};
+		let mut visitor = FieldVisitor::default();
+		values.record(&mut visitor);
+		let mut extensions = span.extensions_mut();
+		match extensions.get_mut::<SpanFields>() {
+			Some(fields) => fields.0.extend(visitor.fields),
+			None => extensions.insert(SpanFields(visitor.fields)),
+		}
+	}
+
+	fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+		let metadata = event.metadata();
+		if forward::is_feedback_target(metadata.target()) || !self.targets.would_enable(metadata.target(), metadata.level()) {
+			return;
+		}
+
+		let mut visitor = FieldVisitor::default();
+		if let Some(scope) = ctx.event_scope(event) {
+			for span in scope.from_root() {
+				if let Some(fields) = span.extensions().get::<SpanFields>() {
+					visitor.fields.extend(fields.0.iter().map(|(key, value)| (key.clone(), value.clone())));
+				}
+			}
+		}
+		event.record(&mut visitor);
+		visitor.fields.insert("target".to_string(), metadata.target().into());
+
+		let level = log_level(metadata.level());
+		let message = visitor.message.unwrap_or_else(|| metadata.name().to_string());
+		let mut entry = LogEntry::new(self.app_name.clone(), level, message, visitor.fields);
+		entry.error = visitor.error;
+		if let Some(span) = ctx.event_span(event) {
+			let trace = crate::trace::span_context(&span);
+			entry.trace_id = Some(trace.trace_id);
+			entry.span_id = Some(trace.span_id);
+		}
+
+		self.forwarder.send(entry);
+	}
+}
+
+fn log_level(level: &Level) -> LogLevel {
+	match *level {
+		Level::TRACE => LogLevel::Trace,
+		Level::DEBUG => LogLevel::Debug,
+		Level::INFO => LogLevel::Info,
+		Level::WARN => LogLevel::Warn,
+		Level::ERROR => LogLevel::Error,
+	}
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+	fields: HashMap<String, AttributeValue>,
+	message: Option<String>,
+	error: Option<common::ErrorInfo>,
+}
+
+impl FieldVisitor {
+	fn insert(&mut self, field: &Field, value: AttributeValue) {
+		self.fields.insert(field.name().to_string(), value);
+	}
+}
+
+impl Visit for FieldVisitor {
+	fn record_str(&mut self, field: &Field, value: &str) {
+		if field.name() == "message" {
+			self.message = Some(value.to_string());
+		} else {
+			self.insert(field, value.into());
+		}
+	}
+
+	fn record_i64(&mut self, field: &Field, value: i64) {
+		self.insert(field, value.into());
+	}
+
+	fn record_u64(&mut self, field: &Field, value: u64) {
+		match i64::try_from(value) {
+			Ok(value) => self.insert(field, value.into()),
+			Err(_) => self.insert(field, value.to_string().into()),
+		}
+	}
+
+	fn record_f64(&mut self, field: &Field, value: f64) {
+		self.insert(field, value.into());
+	}
+
+	fn record_bool(&mut self, field: &Field, value: bool) {
+		self.insert(field, value.into());
+	}
+
+	fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+		if self.error.is_none() {
+			self.error = Some(crate::error::capture_dyn(value));
+		}
+		self.insert(field, value.to_string().into());
+	}
+
+	fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+		if field.name() == "message" {
+			self.message = Some(format!("{:?}", value));
+		} else {
+			self.insert(field, format!("{:?}", value).into());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tracing_subscriber::layer::SubscriberExt;
+
+	// Events of these tests would have the agent's own target by default.
+	fn capture(targets: Targets, emit: impl FnOnce()) -> Vec<LogEntry> {
+		let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+		let layer = AgentLayer::with_forwarder(Forwarder::new(sender), "test-app".to_string()).with_targets(targets);
+		tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), emit);
+
+		let mut entries = Vec::new();
+		while let Ok(entry) = receiver.try_recv() {
+			entries.push(entry);
+		}
+		entries
+	}
+
+	#[test]
+	fn test_event_fields_and_span_fields() {
+		let entries = capture(Targets::new().with_default(Level::INFO), || {
+			let outer = tracing::info_span!("request", user = "alice", attempt = 1);
+			let _outer = outer.enter();
+			let inner = tracing::info_span!("query", attempt = 2u64, table = tracing::field::Empty);
+			inner.record("table", "orders");
+			let _inner = inner.enter();
+			tracing::warn!(target: "app", rows = 3, slow = true, "query took {}ms", 250);
+		});
+
+		assert_eq!(entries.len(), 1);
+		let entry = &entries[0];
+		assert_eq!(entry.level, LogLevel::Warn);
+		assert_eq!(entry.message, "query took 250ms");
+		assert_eq!(entry.attributes["user"], AttributeValue::from("alice"));
+		assert_eq!(entry.attributes["attempt"], AttributeValue::from(2i64));
+		assert_eq!(entry.attributes["table"], AttributeValue::from("orders"));
+		assert_eq!(entry.attributes["rows"], AttributeValue::from(3i64));
+		assert_eq!(entry.attributes["slow"], AttributeValue::from(true));
+		assert!(entry.trace_id.is_some());
+		assert!(entry.span_id.is_some());
+	}
+
+	#[test]
+	fn test_targets_filter_events() {
+		let targets: Targets = "noisy=error,agent_test=debug".parse().unwrap();
+		let entries = capture(targets, || {
+			tracing::warn!(target: "noisy", "skipped");
+			tracing::error!(target: "noisy", "kept");
+			tracing::debug!(target: "agent_test", "kept too");
+			tracing::error!(target: "agent::wal", "the agent's own");
+			tracing::info!(target: "elsewhere", "no default");
+		});
+
+		let messages: Vec<&str> = entries.iter().map(|entry| entry.message.as_str()).collect();
+		assert_eq!(messages, vec!["kept", "kept too"]);
+		assert!(entries[0].trace_id.is_none());
+	}
+}
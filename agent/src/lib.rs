@@ -1,63 +1,323 @@
-use common::{LogBatch, LogEntry};
+use common::wire::{self, WireFormat};
+use buffer::{Buffer, Push};
+use common::{AttributeValue, ErrorKind, LogBatch, LogEntry, LogLevel, LogSystemError, Resource};
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap};
 use std::io::Write;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, MutexGuard, Notify, Semaphore};
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+pub mod buffer;
+pub mod error;
+mod forward;
+pub mod layer;
+pub mod logger;
+pub mod parse;
+pub mod resource;
+pub mod shutdown;
+pub mod throttle;
+pub mod trace;
+pub mod wal;
+
+pub use buffer::{BufferLimits, OverflowPolicy};
+pub use layer::AgentLayer;
+pub use logger::AgentLogger;
+pub use resource::{detect_resource, detect_resource_with_version};
+pub use shutdown::ShutdownGuard;
+pub use throttle::ThrottleConfig;
+pub use trace::attach_traceparent;
+pub use wal::WalConfig;
+
+/// Batches sent concurrently from `log`; further full batches wait in the buffer.
+const MAX_IN_FLIGHT_BATCHES: usize = 4;
 
 pub struct LogAgent {
-	buffer: Arc<Mutex<VecDeque<LogEntry>>>,
+	buffer: Arc<Mutex<Buffer>>,
+	buffer_limits: Arc<BufferLimits>,
+	/// Notified whenever entries are taken out of the buffer.
+	space: Arc<Notify>,
+	in_flight: Arc<Semaphore>,
+	flush_loop: Arc<std::sync::Mutex<Option<JoinHandle<()>>>>,
+	/// Tells the flush loop to stop.
+	stop: Arc<Notify>,
+	throttle: Arc<throttle::Throttle>,
 	batch_size: usize,
 	ingestion_url: String,
 	client: reqwest::Client,
+	resource: Arc<Resource>,
+	wire_format: WireFormat,
+	compress: bool,
+	wal: Option<Arc<std::sync::Mutex<wal::Wal>>>,
 }
 
 impl LogAgent {
 	pub fn new(ingestion_url: String, batch_size: usize) -> Self {
 		Self {
-			buffer: Arc::new(Mutex::new(VecDeque::new())),
+			buffer: Arc::new(Mutex::new(Buffer::default())),
+			buffer_limits: Arc::new(BufferLimits::default()),
+			space: Arc::new(Notify::new()),
+			in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT_BATCHES)),
+			flush_loop: Arc::new(std::sync::Mutex::new(None)),
+			stop: Arc::new(Notify::new()),
+			throttle: Arc::new(throttle::Throttle::new(ThrottleConfig::default())),
 			batch_size,
 			ingestion_url,
 			client: reqwest::Client::new(),
+			resource: Arc::new(detect_resource()),
+			wire_format: WireFormat::Json,
+			compress: true,
+			wal: None,
 		}
 }
 
-pub async fn log(&self, entry: LogEntry) {
-	let mut buffer = self.buffer.lock().await;
-	buffer.push_back(entry);
+/// Selects the batch encoding. MessagePack is cheaper to encode than JSON.
+pub fn with_wire_format(mut self, wire_format: WireFormat) -> Self {
+	self.wire_format = wire_format;
+	self
+}
 
-	if buffer.len() >= self.batch_size {
-		let logs: Vec<LogEntry> = buffer.drain(..).collect();
-		drop(buffer);
+/// Enables or disables gzip on the request body (enabled by default).
+pub fn with_compression(mut self, compress: bool) -> Self {
+	self.compress = compress;
+	self
+}
 
-		let agent = self.clone();
-		tokio::spawn(async move {
-			agent.send_batch(logs).await;
-		});
+/// Replaces the detected resource attached to every batch.
+pub fn with_resource(mut self, resource: Resource) -> Self {
+	self.resource = Arc::new(resource);
+	self
+}
+
+/// Bounds the buffer and picks what happens on overflow. Dropped entries are
+/// counted and reported by the flush loop in a synthetic warning entry.
+pub fn with_buffer_limits(mut self, limits: BufferLimits) -> Self {
+	self.buffer_limits = Arc::new(limits);
+	self
+}
+
+/// Sets how the agent backs off and samples while ingestion answers 429.
+pub fn with_throttle(mut self, config: ThrottleConfig) -> Self {
+	self.throttle = Arc::new(throttle::Throttle::new(config));
+	self
+}
+
+/// Keeps buffered entries in a write-ahead log in `config.dir` until ingestion
+/// confirms them. Entries left over from a previous run are queued first.
+pub fn with_wal(mut self, config: WalConfig) -> std::io::Result<Self> {
+	let (wal, replayed) = wal::Wal::open(config)?;
+	if !replayed.is_empty() {
+		info!("Replaying {} logs from the write-ahead log", replayed.len());
 	}
+	self.buffer = Arc::new(Mutex::new(replayed.into()));
+	self.wal = Some(Arc::new(std::sync::Mutex::new(wal)));
+	Ok(self)
 }
 
-pub async fn start_flush_loop(&self) {
-	let buffer = self.buffer.clone();
-	let agent = self.clone();
+/// Buffers an entry, filling in its trace context from a `traceparent`
+/// attribute or the current `tracing` span when it has none. With a
+/// write-ahead log the entry is on disk when this returns.
+///
+/// When the buffer is full the entry is handled by the [`OverflowPolicy`];
+/// with [`OverflowPolicy::Block`] this waits until a batch has been taken out.
+/// While ingestion is rate limiting the app, low-severity entries are sampled
+/// as set by the [`ThrottleConfig`]; those left out count as dropped.
+pub async fn log(&self, mut entry: LogEntry) {
+	if !self.throttle.sample(entry.level) {
+		self.buffer.lock().await.count_dropped(entry.level, 1);
+		return;
+	}
+	trace::fill_trace_context(&mut entry);
+
+	if self.wal.is_some() {
+		let record = entry.clone();
+		if let Err(e) = self.wal_io(move |wal| wal.append(&record)).await {
+			error!("Failed to write log {} ahead: {}", entry.id, e);
+		}
+	}
+
+	loop {
+		let mut buffer = self.buffer.lock().await;
+		match buffer.push(entry, &self.buffer_limits) {
+			Push::Accepted { evicted } => {
+				self.send_if_full(buffer);
+				self.ack(&evicted).await;
+				return;
+			}
+			Push::Dropped(dropped) => {
+				drop(buffer);
+				self.ack(std::slice::from_ref(&dropped)).await;
+				return;
+			}
+			Push::Full(blocked) => {
+				entry = blocked;
+				let space = self.space.notified();
+				tokio::pin!(space);
+				space.as_mut().enable();
+				self.send_if_full(buffer);
+				space.await;
+			}
+		}
+	}
+}
+
+/// Entries dropped by the overflow policy since the agent was created.
+pub async fn dropped_count(&self) -> u64 {
+	self.buffer.lock().await.dropped_total()
+}
+
+/// Sends a full batch in the background, unless `MAX_IN_FLIGHT_BATCHES` are
+/// already being sent. The task keeps going while full batches are waiting.
+fn send_if_full(&self, mut buffer: MutexGuard<'_, Buffer>) {
+	let batch_size = self.throttle.batch_size(self.batch_size);
+	if buffer.len() < batch_size {
+		return;
+	}
+	let Ok(permit) = self.in_flight.clone().try_acquire_owned() else {
+		return;
+	};
+	let logs = buffer.drain(batch_size);
+	drop(buffer);
+	self.space.notify_waiters();
 
+	let agent = self.clone();
 	tokio::spawn(async move {
+		let _permit = permit;
+		agent.send_batch(logs).await;
 		loop {
-			sleep(Duration::from_secs(1)).await;
+			let batch_size = agent.throttle.batch_size(agent.batch_size);
+			let mut buffer = agent.buffer.lock().await;
+			if buffer.len() < batch_size {
+				break;
+			}
+			let logs = buffer.drain(batch_size);
+			drop(buffer);
+			agent.space.notify_waiters();
+			agent.send_batch(logs).await;
+		}
+	});
+}
 
-			let mut buf = buffer.lock().await;
-			if !buf.is_empty() {
-				let logs: Vec<LogEntry> = buf.drain(..).collect();
-				drop(buf);
+/// Sends the buffer every second until [`shutdown`](Self::shutdown).
+pub async fn start_flush_loop(&self) {
+	let agent = self.clone();
 
-				agent.send_batch(logs).await;
+	let handle = tokio::spawn(async move {
+		let mut last_drop_report = Instant::now();
+		loop {
+			tokio::select! {
+				_ = sleep(Duration::from_secs(1)) => {}
+				_ = agent.stop.notified() => break,
+			}
+
+			if last_drop_report.elapsed() >= agent.buffer_limits.drop_report_interval {
+				agent.report_drops().await;
+				last_drop_report = Instant::now();
 			}
+			let _permit = agent.in_flight.acquire().await.expect("semaphore is never closed");
+			agent.flush_buffered().await;
 		}
 	});
+	if let Ok(mut flush_loop) = self.flush_loop.lock() {
+		*flush_loop = Some(handle);
+	}
+}
+
+/// Waits for the batches being sent, then sends everything buffered.
+///
+/// Returns whether everything was delivered. Entries that could not be sent
+/// stay buffered, and in the write-ahead log if there is one.
+pub async fn flush(&self) -> bool {
+	self.drain(true).await
+}
+
+/// Stops the flush loop and flushes, giving up after `timeout`.
+///
+/// Returns whether everything was delivered in time.
+pub async fn shutdown(&self, timeout: Duration) -> bool {
+	self.stop.notify_one();
+	let flush_loop = self.flush_loop.lock().ok().and_then(|mut flush_loop| flush_loop.take());
+
+	let drained = tokio::time::timeout(timeout, async {
+		if let Some(flush_loop) = flush_loop {
+			// The loop finishes the batch it is sending before it stops.
+			let _ = flush_loop.await;
+		}
+		self.flush().await
+	})
+	.await;
+
+	match drained {
+		Ok(true) => true,
+		Ok(false) => {
+			error!("Shut down with {} logs undelivered", self.buffer.lock().await.len());
+			false
+		}
+		Err(_) => {
+			error!("Shutdown timed out after {:?} with logs undelivered", timeout);
+			false
+		}
+	}
+}
+
+/// Returns a guard that shuts the agent down when dropped, for programs that
+/// exit right after logging. See [`ShutdownGuard`].
+pub fn shutdown_guard(&self, timeout: Duration) -> ShutdownGuard {
+	ShutdownGuard::new(self.clone(), timeout)
+}
+
+/// Sends everything buffered, after the batches in flight unless
+/// `wait_for_in_flight` is false. Holding every send permit keeps `log` and
+/// the flush loop from starting batches meanwhile.
+async fn drain(&self, wait_for_in_flight: bool) -> bool {
+	let _permits = if wait_for_in_flight {
+		Some(
+			self.in_flight
+				.acquire_many(MAX_IN_FLIGHT_BATCHES as u32)
+				.await
+				.expect("semaphore is never closed"),
+		)
+	} else {
+		None
+	};
+
+	self.report_drops().await;
+	self.flush_buffered().await;
+	self.buffer.lock().await.is_empty()
+}
+
+/// Sends what is buffered right now in batches of `batch_size`. Entries
+/// requeued by a failed send wait for the next call.
+async fn flush_buffered(&self) {
+	let mut remaining = self.buffer.lock().await.len();
+	while remaining > 0 {
+		let mut buffer = self.buffer.lock().await;
+		let logs = buffer.drain(self.throttle.batch_size(self.batch_size).min(remaining));
+		drop(buffer);
+		if logs.is_empty() {
+			break;
+		}
+		remaining -= logs.len();
+		self.space.notify_waiters();
+		self.send_batch(logs).await;
+	}
+}
+
+/// Buffers a warning with the number of entries dropped since the last
+/// report, per level. The warning itself is never dropped.
+async fn report_drops(&self) {
+	let mut buffer = self.buffer.lock().await;
+	let dropped = buffer.take_unreported_drops();
+	if dropped.is_empty() {
+		return;
+	}
+	let entry = drop_report(&dropped, &self.buffer_limits, &self.resource);
+	warn!("{}", entry.message);
+	buffer.push_unbounded(entry);
 }
 
 async fn send_batch(&self, logs: Vec<LogEntry>) {
@@ -65,13 +325,28 @@ async fn send_batch(&self, logs: Vec<LogEntry>) {
 		return;
 	}
 
-	let batch = LogBatch::new(logs);
-	let compressed = Self::compress_batch(&batch);
+	let batch = LogBatch::new(logs).with_resource((*self.resource).clone());
+	let payload = self.encode_batch(&batch);
 
 	for attempt in 1..=3 {
-		match self.send_with_compression(&compressed).await {
+		let _permit = self.throttle.permit().await;
+		match self.send_payload(&payload).await {
 			Ok(_) => {
 				info!("Sent batch {} with {} logs", batch.batch_id, batch.logs.len());
+				self.throttle.delivered();
+				self.ack(&batch.logs).await;
+				return;
+			}
+			Err(e) if e.kind() == ErrorKind::Quota => {
+				// The throttle holds back the next send; retrying now would only add load.
+				warn!("Batch {} rate limited, requeueing {} logs: {}", batch.batch_id, batch.logs.len(), e);
+				self.buffer.lock().await.requeue(batch.logs);
+				return;
+			}
+			Err(e) if !e.retryable() => {
+				// Sending it again would fail the same way.
+				error!("Batch {} rejected with {} logs: {}", batch.batch_id, batch.logs.len(), e);
+				self.dead_letter(&batch.logs, e.to_string()).await;
 				return;
 			}
 			Err(e) => {
@@ -79,8 +354,9 @@ async fn send_batch(&self, logs: Vec<LogEntry>) {
 				if attempt < 3 {
 					sleep(Duration::from_secs(2u64.pow(attempt))).await;
 				} else {
-					error!("Failed to send batch after 3 attempts, would save to disk");
-                    self.save_to_disk(&batch).await.ok();
+					error!("Failed to send batch after 3 attempts, requeueing {} logs", batch.logs.len());
+					self.buffer.lock().await.requeue(batch.logs);
+					return;
 				}
 			}
 		}
@@ -88,35 +364,88 @@ async fn send_batch(&self, logs: Vec<LogEntry>) {
 }
 
 
-fn compress_batch(batch: &LogBatch) -> Vec<u8> {
-	let json = serde_json::to_vec(batch).unwrap();
+fn encode_batch(&self, batch: &LogBatch) -> Vec<u8> {
+	let encoded = wire::encode_batch(batch, self.wire_format).unwrap();
+	if !self.compress {
+		return encoded;
+	}
+
 	let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-	encoder.write_all(&json).unwrap();
+	encoder.write_all(&encoded).unwrap();
 	encoder.finish().unwrap()
 }
 
-async fn send_with_compression(&self, data: &[u8]) -> Result<(), anyhow::Error> {
-	let response = self
+async fn send_payload(&self, data: &[u8]) -> Result<(), LogSystemError> {
+	let mut request = self
 	.client
-	.post(&format!("{}/ingest", self.ingestion_url))
-	.header("Content-Encoding", "gzip")
-	.body(data.to_vec())
-	.send()
-	.await?;
-
-	if response.status().is_success() {
-		Ok(())
-	} else {
-		Err(anyhow::anyhow!("HTTP {}", response.status()))
+	.post(format!("{}/ingest", self.ingestion_url))
+	.header("Content-Type", self.wire_format.content_type());
+	if self.compress {
+		request = request.header("Content-Encoding", "gzip");
+	}
+
+	let response = request
+		.body(data.to_vec())
+		.send()
+		.await
+		.map_err(|e| LogSystemError::NetworkError(e.to_string()))?;
+
+	let status = response.status();
+	if status.is_success() {
+		return Ok(());
+	}
+	if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+		let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok());
+		self.throttle.rate_limited(
+			header(reqwest::header::RETRY_AFTER.as_str()).and_then(throttle::parse_retry_after),
+			header(common::RATE_LIMIT_LIMIT_HEADER).and_then(|value| value.parse().ok()),
+			header(common::RATE_LIMIT_REMAINING_HEADER).and_then(|value| value.parse().ok()),
+		);
+	}
+	let body = response.bytes().await.unwrap_or_default();
+	Err(LogSystemError::from_response_body(status.as_u16(), &body))
+}
+
+/// Moves rejected entries from the write-ahead log to its dead-letter file.
+async fn dead_letter(&self, logs: &[LogEntry], reason: String) {
+	if self.wal.is_none() {
+		return;
+	}
+	let entries = logs.to_vec();
+	if let Err(e) = self.wal_io(move |wal| wal.dead_letter(&entries, &reason)).await {
+		error!("Failed to dead-letter {} logs, keeping them in the write-ahead log: {}", logs.len(), e);
+	}
+}
+
+/// Removes delivered entries from the write-ahead log.
+async fn ack(&self, logs: &[LogEntry]) {
+	if self.wal.is_none() || logs.is_empty() {
+		return;
+	}
+	let ids: Vec<String> = logs.iter().map(|log| log.id.clone()).collect();
+	let acked = self
+		.wal_io(move |wal| {
+			wal.ack(ids.iter().map(String::as_str));
+			Ok(())
+		})
+		.await;
+	if let Err(e) = acked {
+		error!("Failed to acknowledge {} logs in the write-ahead log: {}", logs.len(), e);
 	}
 }
 
-async fn save_to_disk(&self, batch: &LogBatch) -> Result<(), anyhow::Error> {
-	let filename = format!("failed_batch_{}.json", batch.batch_id);
-	let json = serde_json::to_string_pretty(batch)?;
-	tokio::fs::write(&filename, json).await?;
-	info!("Saved batch to {}", filename);
-	Ok(())
+/// Runs `f` on the write-ahead log on the blocking pool, so that its file
+/// I/O does not stall the runtime's workers.
+async fn wal_io(&self, f: impl FnOnce(&mut wal::Wal) -> std::io::Result<()> + Send + 'static) -> std::io::Result<()> {
+	let Some(wal) = self.wal.clone() else {
+		return Ok(());
+	};
+	tokio::task::spawn_blocking(move || {
+		let mut wal = wal.lock().map_err(|_| std::io::Error::other("write-ahead log is poisoned"))?;
+		f(&mut wal)
+	})
+	.await
+	.map_err(std::io::Error::other)?
 }
 }
 
@@ -124,9 +453,93 @@ impl Clone for LogAgent {
 	fn clone(&self) -> Self {
 		Self {
 			buffer: self.buffer.clone(),
+			buffer_limits: self.buffer_limits.clone(),
+			space: self.space.clone(),
+			in_flight: self.in_flight.clone(),
+			flush_loop: self.flush_loop.clone(),
+			stop: self.stop.clone(),
+			throttle: self.throttle.clone(),
 			batch_size: self.batch_size,
 			ingestion_url: self.ingestion_url.clone(),
 			client: self.client.clone(),
+			resource: self.resource.clone(),
+			wire_format: self.wire_format,
+			compress: self.compress,
+			wal: self.wal.clone(),
 		}
 	}
-}
\ No newline at end of file
+}
+
+fn drop_report(dropped: &BTreeMap<LogLevel, u64>, limits: &BufferLimits, resource: &Resource) -> LogEntry {
+	let total: u64 = dropped.values().sum();
+	let mut attributes = HashMap::from([
+		("dropped_count".to_string(), AttributeValue::Int(total as i64)),
+		("overflow_policy".to_string(), format!("{:?}", limits.overflow).into()),
+	]);
+	for (level, count) in dropped {
+		attributes.insert(
+			format!("dropped.{}", format!("{:?}", level).to_lowercase()),
+			AttributeValue::Int(*count as i64),
+		);
+	}
+	let app_name = resource.service_name.clone().unwrap_or_else(|| "log-agent".to_string());
+	LogEntry::new(app_name, LogLevel::Warn, format!("{} logs dropped", total), attributes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use axum::http::StatusCode;
+	use axum::{Json, Router};
+	use tokio::net::TcpListener;
+
+	/// Answers every request with `status` and an empty JSON object.
+	async fn serve(status: StatusCode) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		let app = Router::new().fallback(move || async move { (status, Json(serde_json::json!({}))) });
+		tokio::spawn(async move { axum::serve(listener, app).await });
+		url
+	}
+
+	fn entry(message: &str) -> LogEntry {
+		LogEntry::new("test-app".to_string(), LogLevel::Info, message.to_string(), HashMap::new())
+	}
+
+	fn temp_dir() -> std::path::PathBuf {
+		std::env::temp_dir().join(format!("agent-lib-{}", uuid::Uuid::new_v4()))
+	}
+
+	#[tokio::test]
+	async fn test_delivered_logs_are_acknowledged() {
+		let dir = temp_dir();
+		let agent = LogAgent::new(serve(StatusCode::OK).await, 100).with_wal(WalConfig::new(&dir)).unwrap();
+		for i in 0..3 {
+			agent.log(entry(&format!("log {}", i))).await;
+		}
+		assert_eq!(agent.wal.as_ref().unwrap().lock().unwrap().pending(), 3);
+
+		assert!(agent.flush().await);
+		assert_eq!(agent.wal.as_ref().unwrap().lock().unwrap().pending(), 0);
+		drop(agent);
+		assert!(wal::Wal::open(WalConfig::new(&dir)).unwrap().1.is_empty());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_rejected_logs_are_dead_lettered() {
+		let dir = temp_dir();
+		let agent = LogAgent::new(serve(StatusCode::BAD_REQUEST).await, 100).with_wal(WalConfig::new(&dir)).unwrap();
+		agent.log(entry("rejected")).await;
+
+		// Nothing is left to send, so the flush succeeds.
+		assert!(agent.flush().await);
+		assert_eq!(agent.wal.as_ref().unwrap().lock().unwrap().pending(), 0);
+		let dead_letters = std::fs::read_to_string(dir.join("dead-letter.jsonl")).unwrap();
+		assert_eq!(dead_letters.lines().count(), 1);
+		assert!(dead_letters.contains("rejected"));
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,180 @@
+//! A `log` crate backend that ships records through a [`LogAgent`].
+//!
+//! ```ignore
+//! let agent = LogAgent::new(url, 100);
+//! agent::logger::init(&agent, "billing", log::LevelFilter::Info).await?;
+//! log::warn!(invoice = 42; "invoice overdue");
+//! ```
+//!
+//! Records become entries with their target, module path, file and line as
+//! attributes, next to their key-values. Like the `tracing` layer, the logger
+//! never blocks: records are dropped once the agent falls too far behind.
+
+use crate::forward::{self, Forwarder};
+use crate::LogAgent;
+use common::{AttributeValue, LogEntry, LogLevel};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::collections::HashMap;
+
+pub struct AgentLogger {
+	forwarder: Forwarder,
+	app_name: String,
+	max_level: LevelFilter,
+}
+
+/// Installs an [`AgentLogger`] as the global `log` logger and starts the
+/// agent's flush loop. Must be called from within a Tokio runtime.
+///
+/// Fails if a logger is already installed.
+pub async fn init(agent: &LogAgent, app_name: impl Into<String>, max_level: LevelFilter) -> Result<(), SetLoggerError> {
+	let logger = AgentLogger::new(agent, app_name, max_level);
+	log::set_boxed_logger(Box::new(logger))?;
+	log::set_max_level(max_level);
+	agent.start_flush_loop().await;
+	Ok(())
+}
+
+impl AgentLogger {
+	/// Creates a logger without installing it. Must be called from within a
+	/// Tokio runtime.
+	pub fn new(agent: &LogAgent, app_name: impl Into<String>, max_level: LevelFilter) -> Self {
+		Self::with_forwarder(Forwarder::spawn(agent), app_name.into(), max_level)
+	}
+
+	fn with_forwarder(forwarder: Forwarder, app_name: String, max_level: LevelFilter) -> Self {
+		Self {
+			forwarder,
+			app_name,
+			max_level,
+		}
+	}
+
+	fn to_entry(&self, record: &Record<'_>) -> LogEntry {
+		let mut attributes = HashMap::new();
+		attributes.insert("target".to_string(), record.target().into());
+		if let Some(module_path) = record.module_path() {
+			attributes.insert("module_path".to_string(), module_path.into());
+		}
+		if let Some(file) = record.file() {
+			attributes.insert("file".to_string(), file.into());
+		}
+		if let Some(line) = record.line() {
+			attributes.insert("line".to_string(), line.into());
+		}
+
+		let mut visitor = KeyValues(&mut attributes);
+		// Visiting only fails if the visitor does.
+		let _ = record.key_values().visit(&mut visitor);
+
+		LogEntry::new(self.app_name.clone(), log_level(record.level()), record.args().to_string(), attributes)
+	}
+}
+
+impl Log for AgentLogger {
+	fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+		metadata.level() <= self.max_level && !forward::is_feedback_target(metadata.target())
+	}
+
+	fn log(&self, record: &Record<'_>) {
+		if self.enabled(record.metadata()) {
+			self.forwarder.send(self.to_entry(record));
+		}
+	}
+
+	/// Records are sent by the agent; use [`LogAgent::flush`] or a
+	/// [`ShutdownGuard`](crate::ShutdownGuard) to wait for them.
+	fn flush(&self) {}
+}
+
+fn log_level(level: log::Level) -> LogLevel {
+	match level {
+		log::Level::Trace => LogLevel::Trace,
+		log::Level::Debug => LogLevel::Debug,
+		log::Level::Info => LogLevel::Info,
+		log::Level::Warn => LogLevel::Warn,
+		log::Level::Error => LogLevel::Error,
+	}
+}
+
+struct KeyValues<'a>(&'a mut HashMap<String, AttributeValue>);
+
+impl<'kvs> VisitSource<'kvs> for KeyValues<'_> {
+	fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+		self.0.insert(key.as_str().to_string(), attribute_value(&value));
+		Ok(())
+	}
+}
+
+fn attribute_value(value: &Value<'_>) -> AttributeValue {
+	if let Some(b) = value.to_bool() {
+		AttributeValue::Bool(b)
+	} else if let Some(i) = value.to_i64() {
+		AttributeValue::Int(i)
+	} else if let Some(f) = value.to_f64().filter(|_| value.to_u64().is_none()) {
+		AttributeValue::Float(f)
+	} else if let Some(s) = value.to_borrowed_str() {
+		s.into()
+	} else {
+		value.to_string().into()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::sync::mpsc;
+
+	fn logger(max_level: LevelFilter) -> (AgentLogger, mpsc::Receiver<LogEntry>) {
+		let (sender, receiver) = mpsc::channel(16);
+		(AgentLogger::with_forwarder(Forwarder::new(sender), "test-app".to_string(), max_level), receiver)
+	}
+
+	#[test]
+	fn test_record_to_entry() {
+		let (logger, mut receiver) = logger(LevelFilter::Info);
+		let key_values: &[(&str, Value<'_>)] = &[
+			("user", Value::from("alice")),
+			("attempt", Value::from(3)),
+			("ratio", Value::from(0.5)),
+			("cached", Value::from(false)),
+		];
+		logger.log(
+			&Record::builder()
+				.level(log::Level::Warn)
+				.target("billing::invoice")
+				.module_path(Some("billing::invoice"))
+				.file(Some("src/invoice.rs"))
+				.line(Some(42))
+				.key_values(&key_values)
+				.args(format_args!("invoice {} overdue", 7))
+				.build(),
+		);
+
+		let entry = receiver.try_recv().unwrap();
+		assert_eq!(entry.level, LogLevel::Warn);
+		assert_eq!(entry.message, "invoice 7 overdue");
+		assert_eq!(entry.attributes["module_path"], AttributeValue::from("billing::invoice"));
+		assert_eq!(entry.attributes["file"], AttributeValue::from("src/invoice.rs"));
+		assert_eq!(entry.attributes["line"], AttributeValue::from(42u32));
+		assert_eq!(entry.attributes["user"], AttributeValue::from("alice"));
+		assert_eq!(entry.attributes["attempt"], AttributeValue::from(3i64));
+		assert_eq!(entry.attributes["ratio"], AttributeValue::from(0.5));
+		assert_eq!(entry.attributes["cached"], AttributeValue::from(false));
+	}
+
+	#[test]
+	fn test_level_and_feedback_targets_are_filtered() {
+		let (logger, mut receiver) = logger(LevelFilter::Info);
+		for (level, target) in [
+			(log::Level::Debug, "billing"),
+			(log::Level::Error, "reqwest::connect"),
+			(log::Level::Info, "billing"),
+		] {
+			logger.log(&Record::builder().level(level).target(target).args(format_args!("{}", target)).build());
+		}
+
+		assert_eq!(receiver.try_recv().unwrap().level, LogLevel::Info);
+		assert!(receiver.try_recv().is_err());
+	}
+}
@@ -0,0 +1,68 @@
+use super::{parse_timestamp_str, Parsed};
+use common::{AttributeValue, LogLevel};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// `host ident user [time] "request" status bytes`, optionally followed by
+/// `"referer" "user agent"`. Quoted fields may contain `\"`.
+const COMBINED: &str = r#"^(\S+) \S+ (\S+) \[([^\]]+)\] "((?:[^"\\]|\\.)*)" (\d{3}) (\d+|-)(?: "((?:[^"\\]|\\.)*)" "((?:[^"\\]|\\.)*)")?"#;
+
+/// Parses a combined or common access log line into OpenTelemetry HTTP
+/// attributes. The level follows the status: 5xx is an error, 4xx a warning.
+/// The raw line stays the message.
+pub(super) fn parse(line: &str) -> Option<Parsed> {
+	static PATTERN: OnceLock<Regex> = OnceLock::new();
+	let pattern = PATTERN.get_or_init(|| Regex::new(COMBINED).expect("access log pattern is valid"));
+	let captures = pattern.captures(line.trim())?;
+	let field = |index: usize| captures.get(index).map(|m| m.as_str()).filter(|value| *value != "-");
+
+	let mut attributes: HashMap<String, AttributeValue> = HashMap::new();
+	let mut insert = |key: &str, value: AttributeValue| {
+		attributes.insert(key.to_string(), value);
+	};
+	insert("client.address", captures[1].into());
+	if let Some(user) = field(2) {
+		insert("user.name", user.into());
+	}
+
+	let request = captures[4].replace("\\\"", "\"");
+	let mut parts = request.split(' ');
+	if let (Some(method), Some(target)) = (parts.next(), parts.next()) {
+		insert("http.request.method", method.into());
+		match target.split_once('?') {
+			Some((path, query)) => {
+				insert("url.path", path.into());
+				insert("url.query", query.into());
+			}
+			None => insert("url.path", target.into()),
+		}
+		if let Some(version) = parts.next().and_then(|protocol| protocol.strip_prefix("HTTP/")) {
+			insert("network.protocol.version", version.into());
+		}
+	}
+
+	let status: i64 = captures[5].parse().ok()?;
+	insert("http.response.status_code", AttributeValue::Int(status));
+	if let Some(bytes) = field(6).and_then(|bytes| bytes.parse::<i64>().ok()) {
+		insert("http.response.body.size", AttributeValue::Int(bytes));
+	}
+	if let Some(referer) = field(7) {
+		insert("http.request.header.referer", referer.replace("\\\"", "\"").into());
+	}
+	if let Some(user_agent) = field(8) {
+		insert("user_agent.original", user_agent.replace("\\\"", "\"").into());
+	}
+
+	let level = match status {
+		500.. => LogLevel::Error,
+		400..=499 => LogLevel::Warn,
+		_ => LogLevel::Info,
+	};
+	Some(Parsed {
+		level: Some(level),
+		timestamp: parse_timestamp_str(&captures[3]),
+		message: None,
+		attributes,
+	})
+}
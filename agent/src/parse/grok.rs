@@ -0,0 +1,173 @@
+use super::{compile, Parsed};
+use common::{AttributeValue, LogSystemError};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Patterns available to `%{NAME}` references in grok patterns.
+pub const GROK_PATTERNS: &[(&str, &str)] = &[
+	("WORD", r"\b\w+\b"),
+	("NOTSPACE", r"\S+"),
+	("SPACE", r"\s*"),
+	("DATA", r".*?"),
+	("GREEDYDATA", r".*"),
+	("INT", r"[+-]?\d+"),
+	("POSINT", r"\b[1-9]\d*\b"),
+	("NUMBER", r"[+-]?(?:\d+(?:\.\d+)?|\.\d+)"),
+	("IPV4", r"(?:\d{1,3}\.){3}\d{1,3}"),
+	("IPV6", r"[0-9A-Fa-f]{0,4}(?::[0-9A-Fa-f]{0,4}){2,7}"),
+	("IP", r"(?:%{IPV4}|%{IPV6})"),
+	("HOSTNAME", r"\b[0-9A-Za-z][0-9A-Za-z-]{0,62}(?:\.[0-9A-Za-z][0-9A-Za-z-]{0,62})*\.?\b"),
+	("IPORHOST", r"(?:%{IP}|%{HOSTNAME})"),
+	("USERNAME", r"[a-zA-Z0-9._-]+"),
+	("UUID", r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}"),
+	("PATH", r"(?:/[^\s/]*)+"),
+	("URIPATHPARAM", r"/[^\s]*"),
+	("QUOTEDSTRING", r#""(?:[^"\\]|\\.)*""#),
+	("LOGLEVEL", r"(?i:trace|debug|info|information|notice|warn|warning|error|err|fatal|critical|crit|alert|emerg|panic)"),
+	("TIMESTAMP_ISO8601", r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}(?::\d{2}(?:[.,]\d+)?)?(?:Z|[+-]\d{2}:?\d{2})?"),
+	("HTTPDATE", r"\d{2}/\w{3}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4}"),
+	("SYSLOGTIMESTAMP", r"\w{3} +\d{1,2} \d{2}:\d{2}:\d{2}"),
+];
+
+/// References may nest this deep, which also stops cycles.
+const MAX_GROK_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conversion {
+	None,
+	Int,
+	Float,
+}
+
+#[derive(Debug, Clone)]
+struct Capture {
+	/// Name of the group in the regex.
+	group: String,
+	/// Name of the field it becomes.
+	field: String,
+	conversion: Conversion,
+}
+
+/// Parses lines with a regex whose named captures become fields.
+#[derive(Debug, Clone)]
+pub struct CaptureParser {
+	name: &'static str,
+	regex: Regex,
+	captures: Vec<Capture>,
+}
+
+impl CaptureParser {
+	pub fn regex(pattern: &str) -> Result<Self, LogSystemError> {
+		let regex = compile(pattern)?;
+		let captures = regex
+			.capture_names()
+			.flatten()
+			.map(|name| Capture {
+				group: name.to_string(),
+				field: name.to_string(),
+				conversion: Conversion::None,
+			})
+			.collect();
+		Ok(Self {
+			name: "regex",
+			regex,
+			captures,
+		})
+	}
+
+	/// Field names may be any text without `:` or `}`, such as `http.status`;
+	/// the groups in the compiled regex get generated names.
+	pub fn grok(pattern: &str) -> Result<Self, LogSystemError> {
+		let mut captures = Vec::new();
+		let expanded = expand(pattern, &mut captures, 0)?;
+		Ok(Self {
+			name: "grok",
+			regex: compile(&expanded)?,
+			captures,
+		})
+	}
+
+	pub fn name(&self) -> &'static str {
+		self.name
+	}
+
+	pub fn parse(&self, line: &str) -> Option<Parsed> {
+		let matched = self.regex.captures(line)?;
+		let mut fields = HashMap::new();
+		for capture in &self.captures {
+			let Some(value) = matched.name(&capture.group) else {
+				continue;
+			};
+			let value = value.as_str();
+			let converted = match capture.conversion {
+				Conversion::Int => value.parse().ok().map(AttributeValue::Int),
+				Conversion::Float => value.parse().ok().map(AttributeValue::Float),
+				Conversion::None => None,
+			};
+			fields.insert(capture.field.clone(), converted.unwrap_or_else(|| value.into()));
+		}
+		Some(Parsed::from_fields(fields))
+	}
+}
+
+/// Replaces `%{NAME}`, `%{NAME:field}` and `%{NAME:field:type}` references with regexes.
+fn expand(pattern: &str, captures: &mut Vec<Capture>, depth: usize) -> Result<String, LogSystemError> {
+	static REFERENCE: OnceLock<Regex> = OnceLock::new();
+	let reference = REFERENCE.get_or_init(|| {
+		Regex::new(r"%\{(\w+)(?::([^:}]+))?(?::(int|float))?\}").expect("grok reference pattern is valid")
+	});
+	if depth > MAX_GROK_DEPTH {
+		return Err(LogSystemError::InvalidConfig(format!(
+			"grok pattern nests more than {} levels deep",
+			MAX_GROK_DEPTH
+		)));
+	}
+
+	let mut expanded = String::new();
+	let mut last = 0;
+	for found in reference.captures_iter(pattern) {
+		let whole = found.get(0).expect("group 0 always matches");
+		expanded.push_str(&pattern[last..whole.start()]);
+		last = whole.end();
+
+		let name = &found[1];
+		let definition = GROK_PATTERNS
+			.iter()
+			.find(|(known, _)| *known == name)
+			.map(|(_, definition)| *definition)
+			.ok_or_else(|| LogSystemError::InvalidConfig(format!("unknown grok pattern %{{{}}}", name)))?;
+		let inner = expand(definition, captures, depth + 1)?;
+
+		match found.get(2) {
+			Some(field) => {
+				let conversion = match found.get(3).map(|m| m.as_str()) {
+					Some("int") => Conversion::Int,
+					Some("float") => Conversion::Float,
+					_ => Conversion::None,
+				};
+				let group = format!("grok{}", captures.len());
+				expanded.push_str(&format!("(?P<{}>{})", group, inner));
+				captures.push(Capture {
+					group,
+					field: field.as_str().to_string(),
+					conversion,
+				});
+			}
+			None => expanded.push_str(&format!("(?:{})", inner)),
+		}
+	}
+	expanded.push_str(&pattern[last..]);
+	Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_grok_errors() {
+		assert!(CaptureParser::grok("%{NOPE:x}").is_err());
+		assert!(CaptureParser::grok("%{INT:x} (").is_err());
+	}
+}
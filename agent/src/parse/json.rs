@@ -0,0 +1,34 @@
+use super::Parsed;
+use common::AttributeValue;
+use serde_json::Value;
+
+/// Parses a JSON object; nested objects and arrays stay nested, nulls are left out.
+pub(super) fn parse(line: &str) -> Option<Parsed> {
+	let Ok(Value::Object(object)) = serde_json::from_str(line.trim()) else {
+		return None;
+	};
+	let fields = object
+		.into_iter()
+		.filter_map(|(key, value)| Some((key, attribute(value)?)))
+		.collect();
+	Some(Parsed::from_fields(fields))
+}
+
+fn attribute(value: Value) -> Option<AttributeValue> {
+	Some(match value {
+		Value::Null => return None,
+		Value::Bool(b) => AttributeValue::Bool(b),
+		Value::Number(n) => match n.as_i64() {
+			Some(i) => AttributeValue::Int(i),
+			None => AttributeValue::Float(n.as_f64()?),
+		},
+		Value::String(s) => AttributeValue::String(s),
+		Value::Array(items) => AttributeValue::Array(items.into_iter().filter_map(attribute).collect()),
+		Value::Object(fields) => AttributeValue::Object(
+			fields
+				.into_iter()
+				.filter_map(|(key, value)| Some((key, attribute(value)?)))
+				.collect(),
+		),
+	})
+}
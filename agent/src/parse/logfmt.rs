@@ -0,0 +1,59 @@
+use super::{typed_value, Parsed};
+use common::AttributeValue;
+use std::collections::HashMap;
+
+/// Parses `key=value` pairs separated by spaces. Quoted values may contain
+/// spaces and `\"` escapes and stay strings; unquoted numbers and booleans are
+/// typed. A key without `=` is `true`.
+///
+/// Fails on unterminated quotes and on lines without a single `key=value` pair.
+pub(super) fn parse(line: &str) -> Option<Parsed> {
+	let mut fields = HashMap::new();
+	let mut pairs = 0;
+	let mut chars = line.trim().chars().peekable();
+
+	loop {
+		while chars.next_if(|c| c.is_whitespace()).is_some() {}
+		if chars.peek().is_none() {
+			break;
+		}
+
+		let mut key = String::new();
+		while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+			key.push(c);
+		}
+		if key.is_empty() || key.contains('"') {
+			return None;
+		}
+		if chars.next_if_eq(&'=').is_none() {
+			fields.insert(key, AttributeValue::Bool(true));
+			continue;
+		}
+		pairs += 1;
+
+		let value = if chars.next_if_eq(&'"').is_some() {
+			let mut value = String::new();
+			loop {
+				match chars.next()? {
+					'"' => break,
+					'\\' => match chars.next()? {
+						'n' => value.push('\n'),
+						't' => value.push('\t'),
+						other => value.push(other),
+					},
+					c => value.push(c),
+				}
+			}
+			AttributeValue::String(value)
+		} else {
+			let mut value = String::new();
+			while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+				value.push(c);
+			}
+			typed_value(&value)
+		};
+		fields.insert(key, value);
+	}
+
+	(pairs > 0).then(|| Parsed::from_fields(fields))
+}
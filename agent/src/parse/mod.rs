@@ -0,0 +1,266 @@
+//! Parsers that turn raw text lines into the level, timestamp, message and
+//! attributes of a [`LogEntry`].
+//!
+//! Structured parsers recognise the usual field names: `level`, `lvl` or
+//! `severity` for the level, `timestamp`, `time`, `ts` or `@timestamp` for the
+//! time, and `message` or `msg` for the message. Every other field becomes an
+//! attribute. A line that does not parse keeps its raw text as the message and
+//! gets a [`PARSE_FAILED_ATTRIBUTE`] naming the parser.
+
+mod access;
+mod grok;
+mod json;
+mod logfmt;
+
+pub use grok::GROK_PATTERNS;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use common::{AttributeValue, LogEntry, LogLevel, LogSystemError};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Set on entries whose line the parser could not read, to the parser's name.
+pub const PARSE_FAILED_ATTRIBUTE: &str = "log.parse_failed";
+
+const LEVEL_FIELDS: &[&str] = &["level", "lvl", "severity", "log.level"];
+const TIMESTAMP_FIELDS: &[&str] = &["timestamp", "time", "ts", "@timestamp"];
+const MESSAGE_FIELDS: &[&str] = &["message", "msg", "@message"];
+
+/// How lines are parsed, e.g. `{"type": "grok", "pattern": "%{IP:client} %{GREEDYDATA:message}"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParserConfig {
+	/// One JSON object per line.
+	Json,
+	/// `key=value` pairs; values with spaces are double-quoted.
+	Logfmt,
+	/// The Apache/nginx combined log format, or the common format without
+	/// referer and user agent.
+	CombinedAccessLog,
+	/// A regular expression; named captures become fields.
+	Regex { pattern: String },
+	/// A regular expression with grok references: `%{NAME}` inserts a built-in
+	/// pattern, `%{NAME:field}` captures it as `field`, and `%{NAME:field:int}`
+	/// or `:float` also converts it. See [`GROK_PATTERNS`].
+	Grok { pattern: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct LineParser {
+	kind: ParserKind,
+}
+
+#[derive(Debug, Clone)]
+enum ParserKind {
+	Json,
+	Logfmt,
+	CombinedAccessLog,
+	Captures(grok::CaptureParser),
+}
+
+/// What a parser read from a line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parsed {
+	pub level: Option<LogLevel>,
+	pub timestamp: Option<DateTime<Utc>>,
+	pub message: Option<String>,
+	pub attributes: HashMap<String, AttributeValue>,
+}
+
+impl LineParser {
+	pub fn new(config: &ParserConfig) -> Result<Self, LogSystemError> {
+		let kind = match config {
+			ParserConfig::Json => ParserKind::Json,
+			ParserConfig::Logfmt => ParserKind::Logfmt,
+			ParserConfig::CombinedAccessLog => ParserKind::CombinedAccessLog,
+			ParserConfig::Regex { pattern } => ParserKind::Captures(grok::CaptureParser::regex(pattern)?),
+			ParserConfig::Grok { pattern } => ParserKind::Captures(grok::CaptureParser::grok(pattern)?),
+		};
+		Ok(Self { kind })
+	}
+
+	pub fn name(&self) -> &'static str {
+		match &self.kind {
+			ParserKind::Json => "json",
+			ParserKind::Logfmt => "logfmt",
+			ParserKind::CombinedAccessLog => "combined_access_log",
+			ParserKind::Captures(parser) => parser.name(),
+		}
+	}
+
+	pub fn parse(&self, line: &str) -> Option<Parsed> {
+		match &self.kind {
+			ParserKind::Json => json::parse(line),
+			ParserKind::Logfmt => logfmt::parse(line),
+			ParserKind::CombinedAccessLog => access::parse(line),
+			ParserKind::Captures(parser) => parser.parse(line),
+		}
+	}
+
+	/// Fills `entry` from `line`. Fields the line lacks, such as the level, keep
+	/// the entry's values; without a message field the raw line is the message.
+	///
+	/// Returns `false`, keeping the raw line, if the line does not parse.
+	pub fn apply(&self, line: &str, entry: &mut LogEntry) -> bool {
+		let Some(parsed) = self.parse(line) else {
+			entry.message = line.to_string();
+			entry.attributes.insert(PARSE_FAILED_ATTRIBUTE.to_string(), self.name().into());
+			return false;
+		};
+
+		entry.level = parsed.level.unwrap_or(entry.level);
+		entry.timestamp = parsed.timestamp.unwrap_or(entry.timestamp);
+		entry.message = parsed.message.unwrap_or_else(|| line.to_string());
+		entry.attributes.extend(parsed.attributes);
+		true
+	}
+}
+
+impl Parsed {
+	/// Takes the level, timestamp and message out of `fields`. A field that
+	/// does not hold a valid level or timestamp stays an attribute.
+	fn from_fields(mut fields: HashMap<String, AttributeValue>) -> Self {
+		let level = take_field(&mut fields, LEVEL_FIELDS, parse_level);
+		let timestamp = take_field(&mut fields, TIMESTAMP_FIELDS, parse_timestamp);
+		let message = take_field(&mut fields, MESSAGE_FIELDS, |value| Some(value.to_string()));
+		Self {
+			level,
+			timestamp,
+			message,
+			attributes: fields,
+		}
+	}
+}
+
+fn take_field<T>(
+	fields: &mut HashMap<String, AttributeValue>,
+	names: &[&str],
+	convert: impl Fn(&AttributeValue) -> Option<T>,
+) -> Option<T> {
+	let (name, converted) = names
+		.iter()
+		.find_map(|name| fields.get(*name).and_then(&convert).map(|converted| (*name, converted)))?;
+	fields.remove(name);
+	Some(converted)
+}
+
+/// Level names as written by common loggers, beyond those `LogLevel` parses.
+pub fn parse_level(value: &AttributeValue) -> Option<LogLevel> {
+	let text = match value {
+		AttributeValue::String(s) => s.trim().to_ascii_lowercase(),
+		AttributeValue::Int(i) => i.to_string(),
+		_ => return None,
+	};
+	match text.as_str() {
+		"err" | "eror" => Some(LogLevel::Error),
+		"information" | "notice" => Some(LogLevel::Info),
+		"warning" | "wrn" => Some(LogLevel::Warn),
+		"dbg" => Some(LogLevel::Debug),
+		"trc" => Some(LogLevel::Trace),
+		"crit" | "alert" | "emerg" | "emergency" | "panic" => Some(LogLevel::Fatal),
+		other => other.parse().ok(),
+	}
+}
+
+/// RFC 3339 and similar timestamps, the access log format, or Unix time in
+/// seconds, milliseconds, microseconds or nanoseconds. Times without an offset are UTC.
+pub fn parse_timestamp(value: &AttributeValue) -> Option<DateTime<Utc>> {
+	match value {
+		AttributeValue::Int(i) => from_unix(*i),
+		AttributeValue::Float(f) if f.is_finite() => {
+			Utc.timestamp_opt(f.trunc() as i64, (f.fract() * 1e9) as u32).single()
+		}
+		AttributeValue::String(s) => parse_timestamp_str(s.trim()),
+		_ => None,
+	}
+}
+
+fn parse_timestamp_str(text: &str) -> Option<DateTime<Utc>> {
+	if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+		return Some(time.with_timezone(&Utc));
+	}
+	if let Ok(time) = DateTime::parse_from_str(text, "%d/%b/%Y:%H:%M:%S %z") {
+		return Some(time.with_timezone(&Utc));
+	}
+	if let Ok(time) = DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f %z") {
+		return Some(time.with_timezone(&Utc));
+	}
+	for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%d %H:%M:%S,%3f"] {
+		if let Ok(time) = NaiveDateTime::parse_from_str(text, format) {
+			return Some(time.and_utc());
+		}
+	}
+	if text.bytes().all(|b| b.is_ascii_digit()) {
+		return text.parse().ok().and_then(from_unix);
+	}
+	None
+}
+
+/// Picks the unit from the magnitude: seconds until the year 5138.
+fn from_unix(value: i64) -> Option<DateTime<Utc>> {
+	match value.unsigned_abs() {
+		0..100_000_000_000 => Utc.timestamp_opt(value, 0).single(),
+		100_000_000_000..100_000_000_000_000 => Utc.timestamp_millis_opt(value).single(),
+		100_000_000_000_000..100_000_000_000_000_000 => Some(DateTime::from_timestamp_micros(value)?),
+		_ => Some(DateTime::from_timestamp_nanos(value)),
+	}
+}
+
+/// An unquoted value as the most specific attribute type it spells.
+fn typed_value(text: &str) -> AttributeValue {
+	match text {
+		"true" => return AttributeValue::Bool(true),
+		"false" => return AttributeValue::Bool(false),
+		_ => {}
+	}
+	if let Ok(i) = text.parse::<i64>() {
+		// Leading zeros or a plus sign are not numbers to the writer, e.g. in ids.
+		if i.to_string() == text {
+			return AttributeValue::Int(i);
+		}
+	}
+	if text.contains('.') && text.bytes().all(|b| b.is_ascii_digit() || b == b'.' || b == b'-') {
+		if let Ok(f) = text.parse::<f64>() {
+			return AttributeValue::Float(f);
+		}
+	}
+	AttributeValue::String(text.to_string())
+}
+
+fn compile(pattern: &str) -> Result<Regex, LogSystemError> {
+	Regex::new(pattern).map_err(|e| LogSystemError::InvalidConfig(format!("parser pattern: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_timestamp() {
+		let expected = Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap();
+		for value in [
+			AttributeValue::from("2024-03-01T12:30:00Z"),
+			AttributeValue::from("2024-03-01T14:30:00+02:00"),
+			AttributeValue::from("01/Mar/2024:12:30:00 +0000"),
+			AttributeValue::from("2024-03-01 12:30:00"),
+			AttributeValue::from("1709296200"),
+			AttributeValue::Int(1709296200),
+			AttributeValue::Int(1709296200000),
+			AttributeValue::Float(1709296200.0),
+		] {
+			assert_eq!(parse_timestamp(&value), Some(expected), "{:?}", value);
+		}
+		assert_eq!(parse_timestamp(&AttributeValue::from("yesterday")), None);
+	}
+
+	#[test]
+	fn test_apply_falls_back_to_raw_line() {
+		let parser = LineParser::new(&ParserConfig::Json).unwrap();
+		let mut entry = LogEntry::new("web".to_string(), LogLevel::Info, String::new(), HashMap::new());
+
+		assert!(!parser.apply("not json", &mut entry));
+		assert_eq!(entry.message, "not json");
+		assert_eq!(entry.attributes[PARSE_FAILED_ATTRIBUTE], AttributeValue::from("json"));
+	}
+}
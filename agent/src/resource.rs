@@ -0,0 +1,109 @@
+use common::Resource;
+use std::env;
+
+/// Detects the resource describing this process.
+///
+/// `SERVICE_NAME` falls back to the executable name and `DEPLOY_ENV` to
+/// `ENVIRONMENT`. The version is only taken from `SERVICE_VERSION`; see
+/// [`detect_resource_with_version`] to fall back to the program's own.
+/// Extra `key=value` pairs can be given comma-separated in `LOG_RESOURCE_ATTRIBUTES`.
+pub fn detect_resource() -> Resource {
+	resource_from(env_var, None)
+}
+
+/// Like [`detect_resource`], with `version` when `SERVICE_VERSION` is not set.
+/// Pass `env!("CARGO_PKG_VERSION")` from the program, which is compiled in;
+/// the variable itself is only set at runtime under `cargo run`.
+pub fn detect_resource_with_version(version: &str) -> Resource {
+	resource_from(env_var, Some(version))
+}
+
+fn resource_from(var: impl Fn(&str) -> Option<String>, version: Option<&str>) -> Resource {
+	let attributes = var("LOG_RESOURCE_ATTRIBUTES")
+		.map(|value| {
+			value
+				.split(',')
+				.filter_map(|pair| pair.split_once('='))
+				.map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+				.filter(|(k, _)| !k.is_empty())
+				.collect()
+		})
+		.unwrap_or_default();
+
+	Resource {
+		service_name: var("SERVICE_NAME").or_else(executable_name),
+		service_version: var("SERVICE_VERSION").or_else(|| version.map(str::to_string)),
+		host_name: host_name(&var),
+		process_id: Some(std::process::id()),
+		environment: var("DEPLOY_ENV").or_else(|| var("ENVIRONMENT")),
+		attributes,
+	}
+}
+
+fn env_var(name: &str) -> Option<String> {
+	env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn executable_name() -> Option<String> {
+	env::current_exe()
+		.ok()?
+		.file_stem()
+		.map(|name| name.to_string_lossy().into_owned())
+}
+
+fn host_name(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+	var("HOSTNAME")
+		.or_else(|| var("COMPUTERNAME"))
+		.or_else(|| {
+			["/proc/sys/kernel/hostname", "/etc/hostname"]
+				.iter()
+				.find_map(|path| std::fs::read_to_string(path).ok())
+				.map(|name| name.trim().to_string())
+				.filter(|name| !name.is_empty())
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+
+	fn detect(vars: &[(&str, &str)], version: Option<&str>) -> Resource {
+		let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+		resource_from(|name| vars.get(name).map(|value| value.to_string()), version)
+	}
+
+	#[test]
+	fn test_from_variables() {
+		let resource = detect(
+			&[
+				("SERVICE_NAME", "checkout"),
+				("SERVICE_VERSION", "2.1.0"),
+				("HOSTNAME", "web-1"),
+				("ENVIRONMENT", "staging"),
+				("LOG_RESOURCE_ATTRIBUTES", "region = eu-west-1,zone=b,=orphan,malformed"),
+			],
+			Some("0.1.0"),
+		);
+
+		assert_eq!(resource.service_name.as_deref(), Some("checkout"));
+		assert_eq!(resource.service_version.as_deref(), Some("2.1.0"));
+		assert_eq!(resource.host_name.as_deref(), Some("web-1"));
+		assert_eq!(resource.environment.as_deref(), Some("staging"));
+		assert_eq!(resource.process_id, Some(std::process::id()));
+		assert_eq!(
+			resource.attributes,
+			HashMap::from([("region".to_string(), "eu-west-1".to_string()), ("zone".to_string(), "b".to_string())])
+		);
+	}
+
+	#[test]
+	fn test_fallbacks() {
+		let resource = detect(&[("DEPLOY_ENV", "prod"), ("ENVIRONMENT", "staging")], Some("0.1.0"));
+		assert_eq!(resource.service_version.as_deref(), Some("0.1.0"));
+		assert_eq!(resource.environment.as_deref(), Some("prod"));
+		assert!(resource.service_name.is_some(), "the executable name");
+		assert!(resource.attributes.is_empty());
+		assert!(detect(&[], None).service_version.is_none());
+	}
+}
@@ -0,0 +1,124 @@
+//! Shutting the agent down from synchronous code.
+//!
+//! ```ignore
+//! #[tokio::main]
+//! async fn main() {
+//!     let agent = LogAgent::new(url, 100);
+//!     let _guard = agent.shutdown_guard(Duration::from_secs(5));
+//!     agent.log(entry).await;
+//! } // buffered logs are sent here
+//! ```
+
+use crate::LogAgent;
+use std::time::Duration;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tracing::error;
+
+/// Calls [`LogAgent::shutdown`] when dropped, blocking until the buffer is
+/// delivered or the timeout passes.
+///
+/// On a multi-threaded runtime the shutdown runs on the current runtime. A
+/// current-thread runtime cannot make progress while its only thread waits
+/// here, so the buffer is sent from a runtime of its own instead; batches that
+/// were in flight on the blocked runtime are abandoned, and stay in the
+/// write-ahead log if there is one.
+#[must_use = "the agent is shut down when the guard is dropped"]
+pub struct ShutdownGuard {
+	agent: LogAgent,
+	timeout: Duration,
+}
+
+impl ShutdownGuard {
+	pub(crate) fn new(agent: LogAgent, timeout: Duration) -> Self {
+		Self { agent, timeout }
+	}
+}
+
+impl Drop for ShutdownGuard {
+	fn drop(&mut self) {
+		match Handle::try_current() {
+			Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+				tokio::task::block_in_place(|| handle.block_on(self.agent.shutdown(self.timeout)));
+			}
+			_ => {
+				let agent = self.agent.clone();
+				let timeout = self.timeout;
+				let drained = std::thread::spawn(move || {
+					let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+					Ok::<_, std::io::Error>(runtime.block_on(shutdown_detached(agent, timeout)))
+				})
+				.join();
+				if !matches!(drained, Ok(Ok(_))) {
+					error!("Failed to shut down the log agent");
+				}
+			}
+		}
+	}
+}
+
+/// Shuts down without waiting on tasks of the runtime the agent ran on.
+async fn shutdown_detached(mut agent: LogAgent, timeout: Duration) -> bool {
+	// Pooled connections belong to the other runtime.
+	agent.client = reqwest::Client::new();
+	agent.stop.notify_one();
+
+	match tokio::time::timeout(timeout, agent.drain(false)).await {
+		Ok(drained) => drained,
+		Err(_) => {
+			error!("Shutdown timed out after {:?} with logs undelivered", timeout);
+			false
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::WalConfig;
+	use common::{LogEntry, LogLevel};
+	use std::collections::HashMap;
+	use std::time::Instant;
+
+	/// Nothing listens on the discard port, so every send fails and is retried.
+	const UNREACHABLE_URL: &str = "http://127.0.0.1:9";
+
+	async fn agent_with_logs(dir: &std::path::Path) -> LogAgent {
+		let agent = LogAgent::new(UNREACHABLE_URL.to_string(), 100).with_wal(WalConfig::new(dir)).unwrap();
+		agent.start_flush_loop().await;
+		for i in 0..3 {
+			let entry = LogEntry::new("test-app".to_string(), LogLevel::Info, format!("log {}", i), HashMap::new());
+			agent.log(entry).await;
+		}
+		agent
+	}
+
+	fn undelivered(dir: &std::path::Path) -> usize {
+		crate::wal::Wal::open(WalConfig::new(dir)).unwrap().1.len()
+	}
+
+	#[tokio::test]
+	async fn test_guard_on_current_thread_runtime() {
+		let dir = std::env::temp_dir().join(format!("agent-shutdown-{}", uuid::Uuid::new_v4()));
+		let agent = agent_with_logs(&dir).await;
+
+		let started = Instant::now();
+		drop(agent.shutdown_guard(Duration::from_millis(300)));
+		assert!(started.elapsed() < Duration::from_secs(2));
+		assert_eq!(undelivered(&dir), 3);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+	async fn test_shutdown_times_out() {
+		let dir = std::env::temp_dir().join(format!("agent-shutdown-{}", uuid::Uuid::new_v4()));
+		let agent = agent_with_logs(&dir).await;
+
+		let started = Instant::now();
+		assert!(!agent.shutdown(Duration::from_millis(300)).await);
+		assert!(started.elapsed() < Duration::from_secs(2));
+		assert_eq!(undelivered(&dir), 3);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,199 @@
+//! Client-side throttling while ingestion rejects batches with 429.
+//!
+//! A 429 pauses sending for its `Retry-After`, or for a backoff that doubles
+//! with each consecutive 429. Until `recovery` after the last one, batches are
+//! sent one at a time, no larger than the app's quota, and low-severity
+//! entries are sampled as they are logged.
+
+use chrono::{DateTime, Utc};
+use common::LogLevel;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::warn;
+
+#[derive(Debug, Clone)]
+pub struct ThrottleConfig {
+	/// Pause after a 429 without a `Retry-After`, doubled for each further one.
+	pub initial_backoff: Duration,
+	/// Longest pause, also for a `Retry-After`.
+	pub max_backoff: Duration,
+	/// How long throttling lasts after the last 429.
+	pub recovery: Duration,
+	/// Share of entries kept per level while throttled. Levels not listed are all kept.
+	pub sample_rates: BTreeMap<LogLevel, f64>,
+}
+
+impl Default for ThrottleConfig {
+	fn default() -> Self {
+		Self {
+			initial_backoff: Duration::from_secs(1),
+			max_backoff: Duration::from_secs(60),
+			recovery: Duration::from_secs(30),
+			sample_rates: BTreeMap::from([(LogLevel::Trace, 0.0), (LogLevel::Debug, 0.1), (LogLevel::Info, 0.5)]),
+		}
+	}
+}
+
+#[derive(Debug, Default)]
+struct State {
+	/// Consecutive 429s.
+	strikes: u32,
+	/// Nothing is sent before this.
+	paused_until: Option<Instant>,
+	/// Throttling ends at this instant.
+	throttled_until: Option<Instant>,
+	/// The app's quota in logs per second, from the last 429.
+	limit: Option<u64>,
+	/// Per level, the fraction of an entry owed to the log: an entry is kept
+	/// each time it reaches one, which keeps exactly the sample rate.
+	credit: BTreeMap<LogLevel, f64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Throttle {
+	config: ThrottleConfig,
+	state: std::sync::Mutex<State>,
+	/// Held by the one send allowed at a time while throttled.
+	sending: Mutex<()>,
+}
+
+impl Throttle {
+	pub(crate) fn new(config: ThrottleConfig) -> Self {
+		Self {
+			config,
+			state: std::sync::Mutex::new(State::default()),
+			sending: Mutex::new(()),
+		}
+	}
+
+	/// Records a 429 with its `Retry-After`, quota and remaining quota, if sent.
+	pub(crate) fn rate_limited(&self, retry_after: Option<Duration>, limit: Option<u64>, remaining: Option<u64>) {
+		let mut state = self.state();
+		state.strikes += 1;
+		let backoff = retry_after
+			.unwrap_or_else(|| self.config.initial_backoff.saturating_mul(1 << (state.strikes - 1).min(16)))
+			.min(self.config.max_backoff);
+		let now = Instant::now();
+		if state.throttled_until.is_none_or(|until| until <= now) {
+			warn!(
+				"Ingestion is rate limiting this app (quota {:?}/s, {:?} left), throttling for {:?}",
+				limit, remaining, backoff
+			);
+		}
+		state.paused_until = Some(now + backoff);
+		state.throttled_until = Some(now + backoff + self.config.recovery);
+		state.limit = limit.or(state.limit);
+	}
+
+	/// Records a delivered batch. Throttling still lasts its recovery period.
+	pub(crate) fn delivered(&self) {
+		self.state().strikes = 0;
+	}
+
+	pub(crate) fn is_throttled(&self) -> bool {
+		self.state().throttled_until.is_some_and(|until| until > Instant::now())
+	}
+
+	/// Waits until a batch may be sent. While throttled, the returned guard
+	/// must be held for the send, which keeps sends one at a time.
+	pub(crate) async fn permit(&self) -> Option<MutexGuard<'_, ()>> {
+		if !self.is_throttled() {
+			return None;
+		}
+		let guard = self.sending.lock().await;
+		// Read after taking the guard: the send before may have extended the pause.
+		let paused_until = self.state().paused_until;
+		if let Some(until) = paused_until {
+			tokio::time::sleep_until(until.into()).await;
+		}
+		Some(guard)
+	}
+
+	/// The largest batch to send: `batch_size`, or at most the quota while
+	/// throttled, since a larger batch has to wait for the full quota.
+	pub(crate) fn batch_size(&self, batch_size: usize) -> usize {
+		if !self.is_throttled() {
+			return batch_size;
+		}
+		match self.state().limit {
+			Some(limit) => batch_size.min(limit.max(1) as usize),
+			None => batch_size,
+		}
+	}
+
+	/// Whether to keep an entry of `level`: always, unless throttled.
+	pub(crate) fn sample(&self, level: LogLevel) -> bool {
+		let Some(rate) = self.config.sample_rates.get(&level).copied() else {
+			return true;
+		};
+		if !self.is_throttled() {
+			return true;
+		}
+		let mut state = self.state();
+		let credit = state.credit.entry(level).or_insert(0.0);
+		*credit += rate.clamp(0.0, 1.0);
+		// Ten rates of 0.1 add up to just under one.
+		if *credit + 1e-9 >= 1.0 {
+			*credit -= 1.0;
+			true
+		} else {
+			false
+		}
+	}
+
+	fn state(&self) -> std::sync::MutexGuard<'_, State> {
+		self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+}
+
+/// `Retry-After` as delay-seconds or an HTTP date.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+	let value = value.trim();
+	if let Ok(seconds) = value.parse::<u64>() {
+		return Some(Duration::from_secs(seconds));
+	}
+	let date = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+	Some((date - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_samples_low_severity_while_throttled() {
+		let throttle = Throttle::new(ThrottleConfig::default());
+		assert!((0..10).all(|_| throttle.sample(LogLevel::Debug)));
+		assert_eq!(throttle.batch_size(100), 100);
+
+		throttle.rate_limited(Some(Duration::from_secs(5)), Some(20), Some(0));
+		assert!(throttle.is_throttled());
+		let kept = |level| (0..100).filter(|_| throttle.sample(level)).count();
+		assert_eq!(kept(LogLevel::Trace), 0);
+		assert_eq!(kept(LogLevel::Debug), 10);
+		assert_eq!(kept(LogLevel::Info), 50);
+		assert_eq!(kept(LogLevel::Warn), 100);
+		assert_eq!(throttle.batch_size(100), 20);
+	}
+
+	#[test]
+	fn test_backoff_doubles_and_retry_after_is_capped() {
+		let config = ThrottleConfig {
+			recovery: Duration::ZERO,
+			..ThrottleConfig::default()
+		};
+		let throttle = Throttle::new(config);
+		let pause = || throttle.state().paused_until.unwrap().duration_since(Instant::now());
+
+		throttle.rate_limited(None, None, None);
+		throttle.rate_limited(None, None, None);
+		assert!(pause() > Duration::from_millis(1900) && pause() <= Duration::from_secs(2));
+		throttle.rate_limited(Some(Duration::from_secs(3600)), None, None);
+		assert!(pause() <= Duration::from_secs(60));
+
+		assert_eq!(parse_retry_after(" 7 "), Some(Duration::from_secs(7)));
+		assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+		assert_eq!(parse_retry_after("soon"), None);
+	}
+}
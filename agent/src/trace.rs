@@ -0,0 +1,177 @@
+use common::{LogEntry, TraceContext};
+use tracing::Span;
+use tracing_subscriber::registry::{LookupSpan, Registry, SpanRef};
+use uuid::Uuid;
+
+/// Continues the trace from a W3C `traceparent` value inside `span`.
+///
+/// Logs written while `span` (or any of its children) is current carry the
+/// header's trace id. Returns `false` if the header is invalid or the active
+/// subscriber is not built on `tracing_subscriber::Registry`.
+pub fn attach_traceparent(span: &Span, traceparent: &str) -> bool {
+	let Some(parent) = TraceContext::from_traceparent(traceparent) else {
+		return false;
+	};
+
+	span.with_subscriber(|(id, dispatch)| {
+		let Some(span) = dispatch.downcast_ref::<Registry>().and_then(|registry| registry.span(id)) else {
+			return false;
+		};
+		span.extensions_mut().replace(TraceContext {
+			trace_id: parent.trace_id,
+			span_id: random_hex(8),
+			sampled: parent.sampled,
+		});
+		true
+	})
+	.unwrap_or(false)
+}
+
+/// Fills `trace_id`/`span_id` on an entry that does not already have them.
+///
+/// A `traceparent` attribute takes precedence and is consumed; otherwise the
+/// context comes from the current `tracing` span.
+pub(crate) fn fill_trace_context(entry: &mut LogEntry) {
+	if entry.trace_id.is_some() {
+		return;
+	}
+
+	if let Some(value) = entry.attributes.remove("traceparent") {
+		if let Some(ctx) = value.as_str().and_then(TraceContext::from_traceparent) {
+			entry.trace_id = Some(ctx.trace_id);
+			entry.span_id = Some(ctx.span_id);
+			return;
+		}
+		entry.attributes.insert("traceparent".to_string(), value);
+	}
+
+	if let Some(ctx) = current_span_context() {
+		entry.trace_id = Some(ctx.trace_id);
+		entry.span_id = Some(ctx.span_id);
+	}
+}
+
+/// Trace context of the current span, assigning ids on first use.
+fn current_span_context() -> Option<TraceContext> {
+	Span::current()
+		.with_subscriber(|(id, dispatch)| {
+			let registry = dispatch.downcast_ref::<Registry>()?;
+			Some(span_context(&registry.span(id)?))
+		})
+		.flatten()
+}
+
+/// Trace context of `span`, assigning ids on first use.
+///
+/// Registry span ids are reused once a span closes, so each span gets a random
+/// span id and each root span a random trace id, stored in its extensions.
+pub(crate) fn span_context<'a, S: LookupSpan<'a>>(span: &SpanRef<'a, S>) -> TraceContext {
+	if let Some(ctx) = span.extensions().get::<TraceContext>() {
+		return ctx.clone();
+	}
+
+	let inherited = span
+		.scope()
+		.skip(1)
+		.find_map(|ancestor| ancestor.extensions().get::<TraceContext>().cloned());
+	let (trace_id, sampled) = match inherited {
+		Some(parent) => (parent.trace_id, parent.sampled),
+		None => {
+			let trace_id = Uuid::new_v4().simple().to_string();
+			if let Some(root) = span.scope().from_root().next().filter(|root| root.id() != span.id()) {
+				root.extensions_mut().insert(TraceContext {
+					trace_id: trace_id.clone(),
+					span_id: random_hex(8),
+					sampled: true,
+				});
+			}
+			(trace_id, true)
+		}
+	};
+
+	let ctx = TraceContext {
+		trace_id,
+		span_id: random_hex(8),
+		sampled,
+	};
+	span.extensions_mut().insert(ctx.clone());
+	ctx
+}
+
+fn random_hex(bytes: usize) -> String {
+	Uuid::new_v4().simple().to_string()[..bytes * 2].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::LogLevel;
+	use std::collections::HashMap;
+
+	const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+	const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+	fn entry(traceparent: Option<&str>) -> LogEntry {
+		let attributes = traceparent
+			.map(|value| HashMap::from([("traceparent".to_string(), value.into())]))
+			.unwrap_or_default();
+		LogEntry::new("test-app".to_string(), LogLevel::Info, "message".to_string(), attributes)
+	}
+
+	fn filled(traceparent: Option<&str>) -> LogEntry {
+		let mut entry = entry(traceparent);
+		fill_trace_context(&mut entry);
+		entry
+	}
+
+	#[test]
+	fn test_traceparent_attribute() {
+		let entry = filled(Some(TRACEPARENT));
+		assert_eq!(entry.trace_id.as_deref(), Some(TRACE_ID));
+		assert_eq!(entry.span_id.as_deref(), Some("00f067aa0ba902b7"));
+		assert!(!entry.attributes.contains_key("traceparent"));
+
+		let entry = filled(Some("00-not-a-trace-01"));
+		assert!(entry.trace_id.is_none());
+		assert_eq!(entry.attributes["traceparent"].as_str(), Some("00-not-a-trace-01"));
+	}
+
+	#[test]
+	fn test_child_span_inherits_root_trace_id() {
+		tracing::subscriber::with_default(Registry::default(), || {
+			let root = tracing::info_span!("request");
+			let _root = root.enter();
+			let child = tracing::info_span!("query");
+			let in_child = child.in_scope(|| filled(None));
+			let in_root = filled(None);
+
+			assert!(in_child.trace_id.is_some());
+			assert_eq!(in_child.trace_id, in_root.trace_id);
+			assert_ne!(in_child.span_id, in_root.span_id);
+			assert_eq!(child.in_scope(|| filled(None)).span_id, in_child.span_id);
+
+			// A traceparent attribute wins over the current span.
+			assert_eq!(filled(Some(TRACEPARENT)).trace_id.as_deref(), Some(TRACE_ID));
+		});
+	}
+
+	#[test]
+	fn test_attach_traceparent() {
+		let span = tracing::info_span!("request");
+		assert!(!attach_traceparent(&span, TRACEPARENT), "no Registry subscriber");
+
+		tracing::subscriber::with_default(Registry::default(), || {
+			let span = tracing::info_span!("request");
+			assert!(!attach_traceparent(&span, "garbage"));
+			assert!(attach_traceparent(&span, TRACEPARENT));
+
+			let _span = span.enter();
+			let in_span = filled(None);
+			assert_eq!(in_span.trace_id.as_deref(), Some(TRACE_ID));
+			assert_ne!(in_span.span_id.as_deref(), Some("00f067aa0ba902b7"));
+			let in_child = tracing::info_span!("query").in_scope(|| filled(None));
+			assert_eq!(in_child.trace_id.as_deref(), Some(TRACE_ID));
+			assert_ne!(in_child.span_id, in_span.span_id);
+		});
+	}
+}
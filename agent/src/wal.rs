@@ -0,0 +1,432 @@
+//! Write-ahead log that keeps buffered entries on disk until ingestion has them.
+//!
+//! Entries are appended to numbered segment files in a directory. Each record
+//! is a little-endian `u32` length, a CRC32 of the payload and the entry as
+//! JSON. A segment is deleted once every entry in it has been acknowledged;
+//! whatever is left is replayed, oldest first, when the log is reopened.
+//! Entries ingestion rejects for good are moved to a dead-letter file in the
+//! same directory, where they are kept for inspection but not replayed.
+//!
+//! Records are written with a plain `write`, so they survive a crash of the
+//! process but not necessarily of the machine unless `sync` is enabled.
+//! Entries acknowledged shortly before a crash may be sent again after the
+//! restart; ingestion drops them as duplicates by entry id.
+
+use common::LogEntry;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+
+const SEGMENT_PREFIX: &str = "segment-";
+const SEGMENT_SUFFIX: &str = ".wal";
+/// Length and CRC32 of a record.
+const HEADER_LEN: usize = 8;
+/// One JSON object per line: `{"reason": ..., "entry": ...}`.
+const DEAD_LETTER_FILE: &str = "dead-letter.jsonl";
+
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+	pub dir: PathBuf,
+	/// Size at which the active segment is closed and a new one started.
+	pub max_segment_bytes: u64,
+	/// Disk usage above which the oldest segments are deleted, even if their
+	/// entries have not been delivered yet.
+	pub max_total_bytes: u64,
+	/// Calls `fsync` after every record, so entries also survive a power loss.
+	pub sync: bool,
+}
+
+impl WalConfig {
+	pub fn new(dir: impl Into<PathBuf>) -> Self {
+		Self {
+			dir: dir.into(),
+			max_segment_bytes: 8 * 1024 * 1024,
+			max_total_bytes: 256 * 1024 * 1024,
+			sync: false,
+		}
+	}
+
+	pub fn with_max_segment_bytes(mut self, bytes: u64) -> Self {
+		self.max_segment_bytes = bytes;
+		self
+	}
+
+	pub fn with_max_total_bytes(mut self, bytes: u64) -> Self {
+		self.max_total_bytes = bytes;
+		self
+	}
+
+	pub fn with_sync(mut self, sync: bool) -> Self {
+		self.sync = sync;
+		self
+	}
+}
+
+#[derive(Debug)]
+struct Segment {
+	number: u64,
+	bytes: u64,
+	/// Entries in this segment that ingestion has not confirmed yet.
+	pending: usize,
+}
+
+#[derive(Debug)]
+pub struct Wal {
+	config: WalConfig,
+	/// Oldest first; the last one is written to.
+	segments: VecDeque<Segment>,
+	active: File,
+	/// Segment number of every entry id not yet acknowledged.
+	pending: HashMap<String, u64>,
+}
+
+impl Wal {
+	/// Opens the log in `config.dir`, creating the directory if needed, and
+	/// returns it with the entries that were not acknowledged before, in the
+	/// order they were written.
+	pub fn open(config: WalConfig) -> io::Result<(Self, Vec<LogEntry>)> {
+		fs::create_dir_all(&config.dir)?;
+
+		let mut numbers: Vec<u64> = fs::read_dir(&config.dir)?
+			.filter_map(|entry| entry.ok())
+			.filter_map(|entry| segment_number(&entry.file_name().to_string_lossy()))
+			.collect();
+		numbers.sort_unstable();
+
+		let mut segments = VecDeque::new();
+		let mut pending = HashMap::new();
+		let mut replayed = Vec::new();
+		for number in numbers {
+			let path = segment_path(&config.dir, number);
+			let (entries, valid_len) = read_segment(&path)?;
+			let file_len = fs::metadata(&path)?.len();
+			if valid_len < file_len {
+				warn!("Truncating {} bytes of a torn write in {}", file_len - valid_len, path.display());
+				OpenOptions::new().write(true).open(&path)?.set_len(valid_len)?;
+			}
+			if entries.is_empty() {
+				fs::remove_file(&path)?;
+				continue;
+			}
+
+			for entry in &entries {
+				pending.insert(entry.id.clone(), number);
+			}
+			segments.push_back(Segment {
+				number,
+				bytes: valid_len,
+				pending: entries.len(),
+			});
+			replayed.extend(entries);
+		}
+
+		let next = segments.back().map_or(0, |segment| segment.number + 1);
+		let active = create_segment(&config.dir, next)?;
+		segments.push_back(Segment {
+			number: next,
+			bytes: 0,
+			pending: 0,
+		});
+
+		let wal = Self {
+			config,
+			segments,
+			active,
+			pending,
+		};
+		Ok((wal, replayed))
+	}
+
+	/// Appends `entry`; it is replayed on the next [`open`](Self::open) until acknowledged.
+	pub fn append(&mut self, entry: &LogEntry) -> io::Result<()> {
+		let payload = serde_json::to_vec(entry)?;
+		let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+		record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+		record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+		record.extend_from_slice(&payload);
+
+		if self.active_segment().bytes > 0 && self.active_segment().bytes + record.len() as u64 > self.config.max_segment_bytes {
+			self.rotate()?;
+		}
+
+		self.active.write_all(&record)?;
+		if self.config.sync {
+			self.active.sync_data()?;
+		}
+		let segment = self.segments.back_mut().expect("there is always an active segment");
+		segment.bytes += record.len() as u64;
+		segment.pending += 1;
+		let number = segment.number;
+		self.pending.insert(entry.id.clone(), number);
+
+		self.enforce_size_cap();
+		Ok(())
+	}
+
+	/// Marks entries as delivered and deletes the segments that are fully delivered.
+	pub fn ack<'a>(&mut self, ids: impl IntoIterator<Item = &'a str>) {
+		for id in ids {
+			let Some(number) = self.pending.remove(id) else {
+				continue;
+			};
+			if let Some(segment) = self.segments.iter_mut().find(|segment| segment.number == number) {
+				segment.pending = segment.pending.saturating_sub(1);
+			}
+		}
+
+		let active = self.active_segment().number;
+		let dir = self.config.dir.clone();
+		self.segments.retain(|segment| {
+			if segment.pending > 0 || segment.number == active {
+				return true;
+			}
+			if let Err(e) = fs::remove_file(segment_path(&dir, segment.number)) {
+				error!("Failed to delete delivered log segment {}: {}", segment.number, e);
+			}
+			false
+		});
+
+		// Start the active segment over, so that delivered entries are not replayed.
+		let segment = self.segments.back_mut().expect("there is always an active segment");
+		if segment.pending == 0 && segment.bytes > 0 {
+			match self.active.set_len(0) {
+				Ok(()) => segment.bytes = 0,
+				Err(e) => error!("Failed to reset log segment {}: {}", segment.number, e),
+			}
+		}
+	}
+
+	/// Appends entries that ingestion will never accept to the dead-letter
+	/// file, with the reason, then acknowledges them. On failure they stay in
+	/// the log.
+	pub fn dead_letter(&mut self, entries: &[LogEntry], reason: &str) -> io::Result<()> {
+		let mut lines = Vec::new();
+		for entry in entries {
+			serde_json::to_writer(&mut lines, &serde_json::json!({"reason": reason, "entry": entry}))?;
+			lines.push(b'\n');
+		}
+		let mut file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(self.config.dir.join(DEAD_LETTER_FILE))?;
+		file.write_all(&lines)?;
+		if self.config.sync {
+			file.sync_data()?;
+		}
+		self.ack(entries.iter().map(|entry| entry.id.as_str()));
+		Ok(())
+	}
+
+	/// Entries written but not acknowledged yet.
+	pub fn pending(&self) -> usize {
+		self.pending.len()
+	}
+
+	/// Bytes on disk across all segments.
+	pub fn size_bytes(&self) -> u64 {
+		self.segments.iter().map(|segment| segment.bytes).sum()
+	}
+
+	fn active_segment(&self) -> &Segment {
+		self.segments.back().expect("there is always an active segment")
+	}
+
+	fn rotate(&mut self) -> io::Result<()> {
+		let next = self.active_segment().number + 1;
+		self.active = create_segment(&self.config.dir, next)?;
+		self.segments.push_back(Segment {
+			number: next,
+			bytes: 0,
+			pending: 0,
+		});
+		Ok(())
+	}
+
+	/// Deletes the oldest closed segments while the log is over its size cap.
+	fn enforce_size_cap(&mut self) {
+		while self.size_bytes() > self.config.max_total_bytes && self.segments.len() > 1 {
+			let oldest = self.segments.pop_front().expect("checked above");
+			error!(
+				"Log segment {} with {} undelivered entries deleted, write-ahead log is over {} bytes",
+				oldest.number, oldest.pending, self.config.max_total_bytes
+			);
+			self.pending.retain(|_, number| *number != oldest.number);
+			if let Err(e) = fs::remove_file(segment_path(&self.config.dir, oldest.number)) {
+				error!("Failed to delete log segment {}: {}", oldest.number, e);
+			}
+		}
+	}
+}
+
+fn segment_path(dir: &Path, number: u64) -> PathBuf {
+	dir.join(format!("{}{:020}{}", SEGMENT_PREFIX, number, SEGMENT_SUFFIX))
+}
+
+fn segment_number(file_name: &str) -> Option<u64> {
+	file_name
+		.strip_prefix(SEGMENT_PREFIX)?
+		.strip_suffix(SEGMENT_SUFFIX)?
+		.parse()
+		.ok()
+}
+
+fn create_segment(dir: &Path, number: u64) -> io::Result<File> {
+	OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(segment_path(dir, number))
+}
+
+/// Reads the records of a segment up to the first incomplete or corrupt one.
+/// Returns the entries and the length of the valid prefix.
+fn read_segment(path: &Path) -> io::Result<(Vec<LogEntry>, u64)> {
+	let data = fs::read(path)?;
+	let mut entries = Vec::new();
+	let mut offset = 0;
+
+	while data.len() - offset >= HEADER_LEN {
+		let len = u32::from_le_bytes(data[offset..offset + 4].try_into().expect("4 bytes")) as usize;
+		let crc = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().expect("4 bytes"));
+		let start = offset + HEADER_LEN;
+		let Some(payload) = data.get(start..start + len) else {
+			break;
+		};
+		if crc32fast::hash(payload) != crc {
+			break;
+		}
+		match serde_json::from_slice::<LogEntry>(payload) {
+			Ok(entry) => entries.push(entry),
+			Err(e) => {
+				warn!("Skipping unreadable entry in {}: {}", path.display(), e);
+			}
+		}
+		offset = start + len;
+	}
+
+	Ok((entries, offset as u64))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::LogLevel;
+
+	fn temp_dir() -> PathBuf {
+		std::env::temp_dir().join(format!("agent-wal-{}", uuid::Uuid::new_v4()))
+	}
+
+	fn entry(message: &str) -> LogEntry {
+		LogEntry::new("test-app".to_string(), LogLevel::Info, message.to_string(), HashMap::new())
+	}
+
+	fn messages(entries: &[LogEntry]) -> Vec<&str> {
+		entries.iter().map(|entry| entry.message.as_str()).collect()
+	}
+
+	#[test]
+	fn test_replays_unacknowledged_entries_in_order() {
+		let dir = temp_dir();
+		let config = WalConfig::new(&dir).with_max_segment_bytes(300);
+
+		let (mut wal, replayed) = Wal::open(config.clone()).unwrap();
+		assert!(replayed.is_empty());
+		let entries: Vec<LogEntry> = (0..6).map(|i| entry(&format!("entry {}", i))).collect();
+		for entry in &entries {
+			wal.append(entry).unwrap();
+		}
+		assert!(wal.segments.len() > 2);
+		wal.ack(entries[..2].iter().map(|entry| entry.id.as_str()));
+		wal.ack([entries[4].id.as_str()]);
+		drop(wal);
+
+		let (wal, replayed) = Wal::open(config).unwrap();
+		assert_eq!(messages(&replayed), vec!["entry 2", "entry 3", "entry 5"]);
+		assert_eq!(wal.pending(), 3);
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_delivered_segments_are_deleted() {
+		let dir = temp_dir();
+		let config = WalConfig::new(&dir).with_max_segment_bytes(300);
+
+		let (mut wal, _) = Wal::open(config.clone()).unwrap();
+		let entries: Vec<LogEntry> = (0..6).map(|i| entry(&format!("entry {}", i))).collect();
+		for entry in &entries {
+			wal.append(entry).unwrap();
+		}
+		wal.ack(entries.iter().map(|entry| entry.id.as_str()));
+
+		assert_eq!(wal.segments.len(), 1);
+		assert_eq!(wal.size_bytes(), 0);
+		assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+		drop(wal);
+		assert!(Wal::open(config).unwrap().1.is_empty());
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_dead_letter_keeps_rejected_entries() {
+		let dir = temp_dir();
+		let config = WalConfig::new(&dir);
+
+		let (mut wal, _) = Wal::open(config.clone()).unwrap();
+		let entries = vec![entry("rejected"), entry("kept")];
+		for entry in &entries {
+			wal.append(entry).unwrap();
+		}
+		wal.dead_letter(&entries[..1], "Invalid payload: bad field").unwrap();
+		assert_eq!(wal.pending(), 1);
+		wal.ack([entries[1].id.as_str()]);
+		drop(wal);
+
+		let (_, replayed) = Wal::open(config).unwrap();
+		assert!(replayed.is_empty());
+		let dead: serde_json::Value =
+			serde_json::from_str(fs::read_to_string(dir.join(DEAD_LETTER_FILE)).unwrap().trim()).unwrap();
+		assert_eq!(dead["reason"], "Invalid payload: bad field");
+		assert_eq!(dead["entry"]["message"], "rejected");
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_torn_write_is_truncated() {
+		let dir = temp_dir();
+		let config = WalConfig::new(&dir);
+
+		let (mut wal, _) = Wal::open(config.clone()).unwrap();
+		wal.append(&entry("complete")).unwrap();
+		wal.append(&entry("torn")).unwrap();
+		let number = wal.active_segment().number;
+		drop(wal);
+
+		let path = segment_path(&dir, number);
+		let len = fs::metadata(&path).unwrap().len();
+		OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 5).unwrap();
+
+		let (_, replayed) = Wal::open(config.clone()).unwrap();
+		assert_eq!(messages(&replayed), vec!["complete"]);
+		assert!(fs::metadata(&path).unwrap().len() < len - 5);
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_size_cap_drops_oldest_segments() {
+		let dir = temp_dir();
+		let config = WalConfig::new(&dir).with_max_segment_bytes(300).with_max_total_bytes(600);
+
+		let (mut wal, _) = Wal::open(config.clone()).unwrap();
+		for i in 0..20 {
+			wal.append(&entry(&format!("entry {}", i))).unwrap();
+		}
+		assert!(wal.size_bytes() <= 600);
+		drop(wal);
+
+		let (_, replayed) = Wal::open(config).unwrap();
+		assert!(!replayed.is_empty() && replayed.len() < 20);
+		assert_eq!(replayed.last().unwrap().message, "entry 19");
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,84 @@
+//! Fixtures for the line parsers: each file names a parser config and lists
+//! lines with what they should become, or `"fails": true` for lines that must
+//! fall back to the raw text.
+
+use agent::parse::{LineParser, ParserConfig, PARSE_FAILED_ATTRIBUTE};
+use chrono::{DateTime, Utc};
+use common::{LogEntry, LogLevel};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+struct Fixture {
+	parser: ParserConfig,
+	cases: Vec<Case>,
+}
+
+#[derive(Deserialize)]
+struct Case {
+	line: String,
+	#[serde(default)]
+	fails: bool,
+	level: Option<LogLevel>,
+	timestamp: Option<DateTime<Utc>>,
+	message: Option<String>,
+	#[serde(default)]
+	attributes: serde_json::Map<String, serde_json::Value>,
+}
+
+fn check(fixture: &str) {
+	let fixture: Fixture = serde_json::from_str(fixture).unwrap();
+	let parser = LineParser::new(&fixture.parser).unwrap();
+
+	for case in fixture.cases {
+		let received_at = Utc::now();
+		let mut entry = LogEntry::new("test-app".to_string(), LogLevel::Info, String::new(), HashMap::new());
+		entry.timestamp = received_at;
+		let parsed = parser.apply(&case.line, &mut entry);
+
+		if case.fails {
+			assert!(!parsed, "{:?} should not parse", case.line);
+			assert_eq!(entry.message, case.line);
+			assert_eq!(entry.attributes[PARSE_FAILED_ATTRIBUTE], parser.name().into());
+			assert_eq!(entry.attributes.len(), 1);
+			continue;
+		}
+
+		assert!(parsed, "{:?} should parse", case.line);
+		assert_eq!(entry.level, case.level.unwrap_or(LogLevel::Info), "level of {:?}", case.line);
+		assert_eq!(entry.timestamp, case.timestamp.unwrap_or(received_at), "timestamp of {:?}", case.line);
+		assert_eq!(Some(&entry.message), case.message.as_ref(), "message of {:?}", case.line);
+		assert_eq!(
+			serde_json::to_value(&entry.attributes).unwrap(),
+			serde_json::Value::Object(case.attributes),
+			"attributes of {:?}",
+			case.line
+		);
+	}
+}
+
+#[test]
+fn test_json() {
+	check(include_str!("fixtures/parsers/json.json"));
+}
+
+#[test]
+fn test_logfmt() {
+	check(include_str!("fixtures/parsers/logfmt.json"));
+}
+
+#[test]
+fn test_combined_access_log() {
+	check(include_str!("fixtures/parsers/combined_access_log.json"));
+}
+
+#[test]
+fn test_regex() {
+	check(include_str!("fixtures/parsers/regex.json"));
+}
+
+#[test]
+fn test_grok() {
+	check(include_str!("fixtures/parsers/grok.json"));
+	check(include_str!("fixtures/parsers/grok_typed.json"));
+}
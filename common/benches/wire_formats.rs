@@ -0,0 +1,119 @@
+//! Compares encode/decode cost and payload size of the `LogBatch` wire formats.
+//!
+//! Run with `cargo bench -p common --bench wire_formats`.
+
+use common::wire::{decode_batch, encode_batch, WireFormat};
+use common::{AttributeValue, LogBatch, LogEntry, LogLevel, Resource, TraceContext};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 200;
+
+fn realistic_batch(size: usize) -> LogBatch {
+	let ctx = TraceContext::from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+	let levels = [LogLevel::Debug, LogLevel::Info, LogLevel::Info, LogLevel::Info, LogLevel::Warn, LogLevel::Error];
+
+	let logs = (0..size)
+		.map(|i| {
+			let mut attrs: HashMap<String, AttributeValue> = HashMap::new();
+			attrs.insert("user_id".to_string(), format!("user-{}", i % 97).into());
+			attrs.insert("http.method".to_string(), "POST".into());
+			attrs.insert("http.route".to_string(), "/api/v1/payments/{id}/capture".into());
+			attrs.insert("http.status".to_string(), (200 + (i % 3) as i64 * 100).into());
+			attrs.insert("duration_ms".to_string(), (((i * 37) % 1500) as i64).into());
+			attrs.insert("cache_hit".to_string(), (i % 4 == 0).into());
+			attrs.insert("tags".to_string(), vec!["payments", "capture"].into());
+
+			let entry = LogEntry::new(
+				"payment-service".to_string(),
+				levels[i % levels.len()],
+				format!("Captured payment {} for order ord-{:08} in {} ms", i, i * 7, (i * 37) % 1500),
+				attrs,
+			);
+			if i % 2 == 0 {
+				entry.with_trace_context(&ctx)
+			} else {
+				entry
+			}
+		})
+		.collect();
+
+	LogBatch::new(logs).with_resource(Resource {
+		service_name: Some("payment-service".to_string()),
+		service_version: Some("1.4.2".to_string()),
+		host_name: Some("web-1".to_string()),
+		process_id: Some(4242),
+		environment: Some("prod".to_string()),
+		attributes: HashMap::new(),
+	})
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+	let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+	encoder.write_all(bytes).unwrap();
+	encoder.finish().unwrap()
+}
+
+fn gunzip(bytes: &[u8]) -> Vec<u8> {
+	let mut out = Vec::new();
+	GzDecoder::new(bytes).read_to_end(&mut out).unwrap();
+	out
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+	f();
+	let start = Instant::now();
+	for _ in 0..ITERATIONS {
+		f();
+	}
+	start.elapsed() / ITERATIONS
+}
+
+fn main() {
+	for size in [100, 1000] {
+		let batch = realistic_batch(size);
+		println!("batch of {} entries", size);
+		println!(
+			"  {:<18} {:>10} {:>12} {:>12}",
+			"format", "bytes", "encode", "decode"
+		);
+
+		for (name, format, compress) in [
+			("json+gzip", WireFormat::Json, true),
+			("json", WireFormat::Json, false),
+			("msgpack+gzip", WireFormat::MessagePack, true),
+			("msgpack", WireFormat::MessagePack, false),
+		] {
+			let encode = || {
+				let bytes = encode_batch(&batch, format).unwrap();
+				if compress {
+					gzip(&bytes)
+				} else {
+					bytes
+				}
+			};
+			let payload = encode();
+
+			let encode_time = time(|| {
+				black_box(encode());
+			});
+			let decode_time = time(|| {
+				let bytes = if compress { gunzip(&payload) } else { payload.clone() };
+				black_box(decode_batch(&bytes, format).unwrap());
+			});
+
+			println!(
+				"  {:<18} {:>10} {:>12?} {:>12?}",
+				name,
+				payload.len(),
+				encode_time,
+				decode_time
+			);
+		}
+	}
+}
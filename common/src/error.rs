@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+
+/// Error shared by every service. Handlers return it as an [`ErrorResponse`] JSON body.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum LogSystemError {
+	#[error("Invalid payload: {0}")]
+	InvalidPayload(String),
+	#[error("Invalid log level: {0}")]
+	InvalidLevel(String),
+	#[error("Invalid config: {0}")]
+	InvalidConfig(String),
+	#[error("Invalid query: {0}")]
+	InvalidQuery(String),
+	#[error("Unsupported schema version {0}, supported versions are {min}-{max}", min = crate::wire::MIN_SUPPORTED_SCHEMA_VERSION, max = crate::wire::CURRENT_SCHEMA_VERSION)]
+	UnsupportedSchemaVersion(u32),
+	#[error("Unsupported media type: {0}")]
+	UnsupportedMediaType(String),
+	#[error("Unauthorized: {0}")]
+	Unauthorized(String),
+	#[error("Forbidden: {0}")]
+	Forbidden(String),
+	#[error("Rate limit exceeded: {0}")]
+	RateLimitExceeded(String),
+	#[error("Conflict: {0}")]
+	Conflict(String),
+	#[error("Storage error: {0}")]
+	StorageError(String),
+	#[error("Network error: {0}")]
+	NetworkError(String),
+	#[error("Internal error: {0}")]
+	Internal(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+	Validation,
+	Auth,
+	Quota,
+	Conflict,
+	Downstream,
+	Internal,
+}
+
+impl LogSystemError {
+	pub fn kind(&self) -> ErrorKind {
+		match self {
+			LogSystemError::InvalidPayload(_)
+			| LogSystemError::InvalidLevel(_)
+			| LogSystemError::InvalidConfig(_)
+			| LogSystemError::InvalidQuery(_)
+			| LogSystemError::UnsupportedSchemaVersion(_)
+			| LogSystemError::UnsupportedMediaType(_) => ErrorKind::Validation,
+			LogSystemError::Unauthorized(_) | LogSystemError::Forbidden(_) => ErrorKind::Auth,
+			LogSystemError::RateLimitExceeded(_) => ErrorKind::Quota,
+			LogSystemError::Conflict(_) => ErrorKind::Conflict,
+			LogSystemError::StorageError(_) | LogSystemError::NetworkError(_) => ErrorKind::Downstream,
+			LogSystemError::Internal(_) => ErrorKind::Internal,
+		}
+	}
+
+	/// Stable machine-readable code, e.g. `rate_limit_exceeded`.
+	pub fn code(&self) -> &'static str {
+		match self {
+			LogSystemError::InvalidPayload(_) => "invalid_payload",
+			LogSystemError::InvalidLevel(_) => "invalid_level",
+			LogSystemError::InvalidConfig(_) => "invalid_config",
+			LogSystemError::InvalidQuery(_) => "invalid_query",
+			LogSystemError::UnsupportedSchemaVersion(_) => "unsupported_schema_version",
+			LogSystemError::UnsupportedMediaType(_) => "unsupported_media_type",
+			LogSystemError::Unauthorized(_) => "unauthorized",
+			LogSystemError::Forbidden(_) => "forbidden",
+			LogSystemError::RateLimitExceeded(_) => "rate_limit_exceeded",
+			LogSystemError::Conflict(_) => "conflict",
+			LogSystemError::StorageError(_) => "storage_error",
+			LogSystemError::NetworkError(_) => "network_error",
+			LogSystemError::Internal(_) => "internal",
+		}
+	}
+
+	/// Whether the same request may succeed if sent again later.
+	pub fn retryable(&self) -> bool {
+		matches!(
+			self.kind(),
+			ErrorKind::Quota | ErrorKind::Conflict | ErrorKind::Downstream
+		)
+	}
+
+	pub fn status_code(&self) -> u16 {
+		match self {
+			LogSystemError::UnsupportedMediaType(_) => 415,
+			LogSystemError::Unauthorized(_) => 401,
+			LogSystemError::Forbidden(_) => 403,
+			LogSystemError::RateLimitExceeded(_) => 429,
+			LogSystemError::Conflict(_) => 409,
+			LogSystemError::StorageError(_) => 502,
+			LogSystemError::NetworkError(_) => 503,
+			LogSystemError::Internal(_) => 500,
+			_ => 400,
+		}
+	}
+
+	pub fn to_response(&self) -> ErrorResponse {
+		ErrorResponse {
+			error: ErrorBody {
+				code: self.code().to_string(),
+				kind: self.kind(),
+				message: self.to_string(),
+				retryable: self.retryable(),
+			},
+		}
+	}
+}
+
+/// JSON body of every error response: `{"error": {"code": ..., "kind": ..., "message": ..., "retryable": ...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+	pub error: ErrorBody,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorBody {
+	pub code: String,
+	pub kind: ErrorKind,
+	pub message: String,
+	pub retryable: bool,
+}
+
+impl From<ErrorBody> for LogSystemError {
+	/// Rebuilds the error a service answered with. Unknown codes fall back on `kind`.
+	fn from(body: ErrorBody) -> Self {
+		// `message` is the error's `Display`, which starts with the variant's own prefix.
+		let rebuild = |variant: fn(String) -> LogSystemError| {
+			let prefix = variant(String::new()).to_string();
+			variant(body.message.strip_prefix(&prefix).unwrap_or(&body.message).to_string())
+		};
+		match body.code.as_str() {
+			"invalid_payload" => rebuild(LogSystemError::InvalidPayload),
+			"invalid_level" => rebuild(LogSystemError::InvalidLevel),
+			"invalid_config" => rebuild(LogSystemError::InvalidConfig),
+			"invalid_query" => rebuild(LogSystemError::InvalidQuery),
+			"unsupported_schema_version" => body
+				.message
+				.strip_prefix("Unsupported schema version ")
+				.and_then(|rest| rest.split(|c: char| !c.is_ascii_digit()).next())
+				.and_then(|version| version.parse().ok())
+				.map(LogSystemError::UnsupportedSchemaVersion)
+				.unwrap_or_else(|| LogSystemError::InvalidPayload(body.message.clone())),
+			"unsupported_media_type" => rebuild(LogSystemError::UnsupportedMediaType),
+			"unauthorized" => rebuild(LogSystemError::Unauthorized),
+			"forbidden" => rebuild(LogSystemError::Forbidden),
+			"rate_limit_exceeded" => rebuild(LogSystemError::RateLimitExceeded),
+			"conflict" => rebuild(LogSystemError::Conflict),
+			"storage_error" => rebuild(LogSystemError::StorageError),
+			"network_error" => rebuild(LogSystemError::NetworkError),
+			"internal" => rebuild(LogSystemError::Internal),
+			_ => match body.kind {
+				ErrorKind::Validation => rebuild(LogSystemError::InvalidPayload),
+				ErrorKind::Auth => rebuild(LogSystemError::Unauthorized),
+				ErrorKind::Quota => rebuild(LogSystemError::RateLimitExceeded),
+				ErrorKind::Conflict => rebuild(LogSystemError::Conflict),
+				ErrorKind::Downstream => rebuild(LogSystemError::StorageError),
+				ErrorKind::Internal => rebuild(LogSystemError::Internal),
+			},
+		}
+	}
+}
+
+impl LogSystemError {
+	/// Interprets a non-success response from another service.
+	pub fn from_response_body(status: u16, body: &[u8]) -> Self {
+		if let Ok(response) = serde_json::from_slice::<ErrorResponse>(body) {
+			return response.error.into();
+		}
+
+		let message = format!("HTTP {}: {}", status, String::from_utf8_lossy(body).trim());
+		match status {
+			401 => LogSystemError::Unauthorized(message),
+			403 => LogSystemError::Forbidden(message),
+			409 => LogSystemError::Conflict(message),
+			415 => LogSystemError::UnsupportedMediaType(message),
+			429 => LogSystemError::RateLimitExceeded(message),
+			400..=499 => LogSystemError::InvalidPayload(message),
+			_ => LogSystemError::StorageError(message),
+		}
+	}
+}
+
+#[cfg(feature = "axum")]
+mod axum_impl {
+	use super::LogSystemError;
+	use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+	use axum::http::StatusCode;
+	use axum::response::{IntoResponse, Response};
+	use axum::Json;
+
+	impl IntoResponse for LogSystemError {
+		fn into_response(self) -> Response {
+			let status = StatusCode::from_u16(self.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+			(status, Json(self.to_response())).into_response()
+		}
+	}
+
+	impl From<JsonRejection> for LogSystemError {
+		fn from(rejection: JsonRejection) -> Self {
+			match rejection {
+				JsonRejection::MissingJsonContentType(_) => LogSystemError::UnsupportedMediaType(rejection.body_text()),
+				_ => LogSystemError::InvalidPayload(rejection.body_text()),
+			}
+		}
+	}
+
+	impl From<QueryRejection> for LogSystemError {
+		fn from(rejection: QueryRejection) -> Self {
+			LogSystemError::InvalidPayload(rejection.body_text())
+		}
+	}
+
+	impl From<PathRejection> for LogSystemError {
+		fn from(rejection: PathRejection) -> Self {
+			LogSystemError::InvalidPayload(rejection.body_text())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_error_response_shape() {
+		let error = LogSystemError::RateLimitExceeded("payment-service".to_string());
+		let json = serde_json::to_value(error.to_response()).unwrap();
+
+		assert_eq!(json["error"]["code"], "rate_limit_exceeded");
+		assert_eq!(json["error"]["kind"], "quota");
+		assert_eq!(json["error"]["retryable"], true);
+		assert_eq!(json["error"]["message"], "Rate limit exceeded: payment-service");
+		assert_eq!(error.status_code(), 429);
+	}
+
+	#[test]
+	fn test_validation_errors_are_not_retryable() {
+		let error = LogSystemError::UnsupportedSchemaVersion(9);
+		assert_eq!(error.kind(), ErrorKind::Validation);
+		assert_eq!(error.status_code(), 400);
+		assert!(!error.retryable());
+		assert!(LogSystemError::StorageError("bulk failed".to_string()).retryable());
+	}
+
+	#[test]
+	fn test_from_response_body() {
+		let body = serde_json::to_vec(&LogSystemError::Conflict("in flight".to_string()).to_response()).unwrap();
+		let error = LogSystemError::from_response_body(409, &body);
+		assert_eq!(error.code(), "conflict");
+		assert!(error.retryable());
+
+		let plain = LogSystemError::from_response_body(429, b"Too many requests");
+		assert_eq!(plain, LogSystemError::RateLimitExceeded("HTTP 429: Too many requests".to_string()));
+		assert_eq!(LogSystemError::from_response_body(502, b"").kind(), ErrorKind::Downstream);
+
+		// Every code comes back as the error that was sent, without a second prefix.
+		let errors = [
+			LogSystemError::InvalidPayload("x".to_string()),
+			LogSystemError::InvalidLevel("x".to_string()),
+			LogSystemError::InvalidConfig("x".to_string()),
+			LogSystemError::InvalidQuery("x".to_string()),
+			LogSystemError::UnsupportedSchemaVersion(99),
+			LogSystemError::UnsupportedMediaType("x".to_string()),
+			LogSystemError::Unauthorized("x".to_string()),
+			LogSystemError::Forbidden("x".to_string()),
+			LogSystemError::RateLimitExceeded("x".to_string()),
+			LogSystemError::Conflict("x".to_string()),
+			LogSystemError::StorageError("x".to_string()),
+			LogSystemError::NetworkError("x".to_string()),
+			LogSystemError::Internal("x".to_string()),
+		];
+		for error in errors {
+			let body = serde_json::to_vec(&error.to_response()).unwrap();
+			assert_eq!(LogSystemError::from_response_body(error.status_code(), &body), error);
+		}
+
+		let unknown = ErrorBody {
+			code: "teapot".to_string(),
+			kind: ErrorKind::Quota,
+			message: "Rate limit exceeded: x".to_string(),
+			retryable: true,
+		};
+		assert_eq!(LogSystemError::from(unknown), LogSystemError::RateLimitExceeded("x".to_string()));
+	}
+}
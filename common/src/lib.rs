@@ -3,12 +3,225 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub mod error;
+pub mod limits;
+pub mod pii;
+pub mod pseudonym;
+pub mod query;
+pub mod redaction;
+pub mod syslog;
+pub mod trace;
+pub mod wire;
+
+pub use error::{ErrorBody, ErrorKind, ErrorResponse, LogSystemError};
+pub use limits::{Limit, LimitAction, LimitOutcome, SizeLimits};
+pub use pii::PiiKind;
+pub use pseudonym::{PseudonymVault, Pseudonymizer};
+pub use redaction::{RedactionConfig, RedactionRule, Redactor, Replacement};
+pub use trace::TraceContext;
+
+/// Log severity, ordered from least to most severe.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum LogLevel {
+	Trace,
 	Debug,
 	Info,
 	Warn,
 	Error,
+	Fatal,
+}
+
+impl LogLevel {
+	pub const ALL: [LogLevel; 6] = [
+		LogLevel::Trace,
+		LogLevel::Debug,
+		LogLevel::Info,
+		LogLevel::Warn,
+		LogLevel::Error,
+		LogLevel::Fatal,
+	];
+
+	/// The OpenTelemetry `SeverityNumber` at the bottom of this level's range.
+	pub fn severity_number(&self) -> u8 {
+		match self {
+			LogLevel::Trace => 1,
+			LogLevel::Debug => 5,
+			LogLevel::Info => 9,
+			LogLevel::Warn => 13,
+			LogLevel::Error => 17,
+			LogLevel::Fatal => 21,
+		}
+	}
+
+	/// Maps an OpenTelemetry `SeverityNumber` (1-24) onto a level.
+	pub fn from_severity_number(number: u8) -> Option<LogLevel> {
+		match number {
+			1..=4 => Some(LogLevel::Trace),
+			5..=8 => Some(LogLevel::Debug),
+			9..=12 => Some(LogLevel::Info),
+			13..=16 => Some(LogLevel::Warn),
+			17..=20 => Some(LogLevel::Error),
+			21..=24 => Some(LogLevel::Fatal),
+			_ => None,
+		}
+	}
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			LogLevel::Trace => "Trace",
+			LogLevel::Debug => "Debug",
+			LogLevel::Info => "Info",
+			LogLevel::Warn => "Warn",
+			LogLevel::Error => "Error",
+			LogLevel::Fatal => "Fatal",
+		}
+	}
+}
+
+impl std::fmt::Display for LogLevel {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+/// Parses a level name case-insensitively, or an OpenTelemetry severity number.
+impl std::str::FromStr for LogLevel {
+	type Err = LogSystemError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if let Ok(number) = s.parse::<u8>() {
+			return LogLevel::from_severity_number(number)
+				.ok_or_else(|| LogSystemError::InvalidLevel(s.to_string()));
+		}
+
+		match s.to_ascii_lowercase().as_str() {
+			"trace" => Ok(LogLevel::Trace),
+			"debug" => Ok(LogLevel::Debug),
+			"info" => Ok(LogLevel::Info),
+			"warn" | "warning" => Ok(LogLevel::Warn),
+			"error" => Ok(LogLevel::Error),
+			"fatal" | "critical" => Ok(LogLevel::Fatal),
+			_ => Err(LogSystemError::InvalidLevel(s.to_string())),
+		}
+	}
+}
+
+/// A typed attribute value. Serialized untagged, so attributes stay plain JSON on the wire.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum AttributeValue {
+	Bool(bool),
+	Int(i64),
+	Float(f64),
+	String(String),
+	Array(Vec<AttributeValue>),
+	Object(HashMap<String, AttributeValue>),
+}
+
+impl AttributeValue {
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			AttributeValue::String(s) => Some(s),
+			_ => None,
+		}
+	}
+
+	pub fn as_i64(&self) -> Option<i64> {
+		match self {
+			AttributeValue::Int(i) => Some(*i),
+			_ => None,
+		}
+	}
+
+	pub fn as_f64(&self) -> Option<f64> {
+		match self {
+			AttributeValue::Int(i) => Some(*i as f64),
+			AttributeValue::Float(f) => Some(*f),
+			_ => None,
+		}
+	}
+
+	pub fn as_bool(&self) -> Option<bool> {
+		match self {
+			AttributeValue::Bool(b) => Some(*b),
+			_ => None,
+		}
+	}
+
+	/// Calls `f` on every string in this value, including those nested in arrays and objects.
+	pub(crate) fn visit_strings_mut(&mut self, f: &mut impl FnMut(&mut String)) {
+		match self {
+			AttributeValue::String(s) => f(s),
+			AttributeValue::Array(items) => {
+				for item in items {
+					item.visit_strings_mut(f);
+				}
+			}
+			AttributeValue::Object(fields) => {
+				for value in fields.values_mut() {
+					value.visit_strings_mut(f);
+				}
+			}
+			AttributeValue::Bool(_) | AttributeValue::Int(_) | AttributeValue::Float(_) => {}
+		}
+	}
+}
+
+impl std::fmt::Display for AttributeValue {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			AttributeValue::String(s) => f.write_str(s),
+			other => write!(f, "{}", serde_json::to_string(other).map_err(|_| std::fmt::Error)?),
+		}
+	}
+}
+
+impl From<String> for AttributeValue {
+	fn from(value: String) -> Self {
+		AttributeValue::String(value)
+	}
+}
+
+impl From<&str> for AttributeValue {
+	fn from(value: &str) -> Self {
+		AttributeValue::String(value.to_string())
+	}
+}
+
+impl From<i64> for AttributeValue {
+	fn from(value: i64) -> Self {
+		AttributeValue::Int(value)
+	}
+}
+
+impl From<i32> for AttributeValue {
+	fn from(value: i32) -> Self {
+		AttributeValue::Int(value.into())
+	}
+}
+
+impl From<u32> for AttributeValue {
+	fn from(value: u32) -> Self {
+		AttributeValue::Int(value.into())
+	}
+}
+
+impl From<f64> for AttributeValue {
+	fn from(value: f64) -> Self {
+		AttributeValue::Float(value)
+	}
+}
+
+impl From<bool> for AttributeValue {
+	fn from(value: bool) -> Self {
+		AttributeValue::Bool(value)
+	}
+}
+
+impl<T: Into<AttributeValue>> From<Vec<T>> for AttributeValue {
+	fn from(values: Vec<T>) -> Self {
+		AttributeValue::Array(values.into_iter().map(Into::into).collect())
+	}
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,7 +231,16 @@ pub struct LogEntry {
 	pub level: LogLevel,
 	pub timestamp: DateTime<Utc>,
 	pub message: String,
-	pub attributes: HashMap<String, String>,
+	pub attributes: HashMap<String, AttributeValue>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub trace_id: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub span_id: Option<String>,
+	/// Set when ingestion cut the entry down to the app's size limits.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub truncated: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<ErrorInfo>,
 }
 
 impl LogEntry {
@@ -26,64 +248,215 @@ impl LogEntry {
 		app_name: String,
 		level: LogLevel,
 		message: String,
-		attributes: HashMap<String, String>,
+		attributes: HashMap<String, AttributeValue>,
 	) -> Self {
 			Self{
-				id: Uuid::new_v4().to_string(),
+				id: new_id(),
 				app_name,
 				level,
 				timestamp: Utc::now(),
 				message,
 				attributes,
+				trace_id: None,
+				span_id: None,
+				truncated: false,
+				error: None,
 			}
 	}
 
-	pub fn mask_secrets(&mut self) {
-		use regex::Regex;
+	pub fn with_trace_context(mut self, ctx: &TraceContext) -> Self {
+		self.trace_id = Some(ctx.trace_id.clone());
+		self.span_id = Some(ctx.span_id.clone());
+		self
+	}
 
-		let patterns = vec![
-			(Regex::new(r"\b\d{16}\b").unwrap(), "****-****-****-****"), 
-			(Regex::new(r"password[=:]\s*\S+").unwrap(), "password=***"),
-			(Regex::new(r"token[=:]\s*\S+").unwrap(), "token=***"),      
-			(Regex::new(r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Z|a-z]{2,}\b").unwrap(), "***@***.com"), 
-	];
+	pub fn with_error(mut self, error: ErrorInfo) -> Self {
+		self.error = Some(error);
+		self
+	}
 
-		for (pattern, replacement) in &patterns {
-			self.message = pattern.replace_all(&self.message, *replacement).to_string();
+	/// Applies the built-in redaction rules. Use a [`Redactor`] for app-specific rules.
+	pub fn mask_secrets(&mut self) {
+		Redactor::builtin().redact(self);
 	}	
-		for(key, value) in self.attributes.iter_mut() {
-			if key.to_lowercase().contains("password") 
-			|| key.to_lowercase().contains("token")
-			|| key.to_lowercase().contains("secret") {
-				*value = "***".to_string();
-			}
+}
+
+/// An error attached to a log entry, with the errors that caused it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ErrorInfo {
+	/// Type name without its module path, e.g. `TimeoutError`.
+	#[serde(rename = "type")]
+	pub error_type: String,
+	pub message: String,
+	/// Innermost call first.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub frames: Vec<StackFrame>,
+	/// The `source()` chain, starting with the direct cause.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub causes: Vec<ErrorCause>,
+}
+
+impl ErrorInfo {
+	pub fn new(error_type: impl Into<String>, message: impl Into<String>) -> Self {
+		Self {
+			error_type: error_type.into(),
+			message: message.into(),
+			frames: Vec::new(),
+			causes: Vec::new(),
 		}
-	}	
+	}
+
+	/// The frame the error was raised or captured in.
+	pub fn top_frame(&self) -> Option<&StackFrame> {
+		self.frames.first()
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ErrorCause {
+	#[serde(rename = "type")]
+	pub error_type: String,
+	pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StackFrame {
+	pub function: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub file: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub line: Option<u32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub column: Option<u32>,
+}
+
+impl StackFrame {
+	/// Last component of `file`, e.g. `db.rs`.
+	pub fn file_name(&self) -> Option<&str> {
+		let file = self.file.as_deref()?;
+		file.rsplit(['/', '\\']).next()
+	}
+}
+
+/// Describes the process that produced a batch. Sent once per batch rather than per entry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Resource {
+	pub service_name: Option<String>,
+	pub service_version: Option<String>,
+	pub host_name: Option<String>,
+	pub process_id: Option<u32>,
+	pub environment: Option<String>,
+	#[serde(default, skip_serializing_if = "HashMap::is_empty")]
+	pub attributes: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogBatch {
+	/// Wire format version, see [`wire`]. Absent in legacy payloads.
+	#[serde(default = "legacy_schema_version")]
+	pub schema_version: u32,
 	pub logs: Vec<LogEntry>,
 	pub batch_id: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub resource: Option<Resource>,
+}
+
+/// Generates a UUIDv7 id. Ids sort lexicographically in creation order.
+pub fn new_id() -> String {
+	Uuid::now_v7().to_string()
+}
+
+fn legacy_schema_version() -> u32 {
+	1
 }
 
 impl LogBatch {
 	pub fn new(logs: Vec<LogEntry>) -> Self {
 		Self {
+			schema_version: wire::CURRENT_SCHEMA_VERSION,
 			logs,
-			batch_id: Uuid::new_v4().to_string(),
+			batch_id: new_id(),
+			resource: None,
 		}
 	}
+
+	pub fn with_resource(mut self, resource: Resource) -> Self {
+		self.resource = Some(resource);
+		self
+	}
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SearchQuery {
 	pub app_name: Option<String>,
 	pub level: Option<LogLevel>,
+	/// Matches entries at this level or above.
+	pub min_level: Option<LogLevel>,
 	pub from: Option<DateTime<Utc>>,
 	pub to: Option<DateTime<Utc>>,
-	pub attributes: Option<HashMap<String, String>>,
+	pub trace_id: Option<String>,
+	/// Exact matches on resource fields, e.g. `host_name` or `attributes.region`.
+	pub resource: Option<HashMap<String, String>>,
+	pub attributes: Option<HashMap<String, AttributeValue>>,
+	/// Numeric range filters keyed by attribute name, e.g. `duration_ms > 500`.
+	pub attribute_ranges: Option<HashMap<String, AttributeRange>>,
+	/// Matches on the message text; every entry must match.
+	pub message: Option<Vec<MessageMatch>>,
+	/// Expression in the [`query`] language, ANDed with the other filters.
+	pub q: Option<String>,
 	pub limit: Option<usize>,
+	/// Timestamp sort order; newest first when unset.
+	pub sort: Option<SortOrder>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+	Asc,
+	Desc,
+}
+
+impl SortOrder {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			SortOrder::Asc => "asc",
+			SortOrder::Desc => "desc",
+		}
+	}
+}
+
+/// A match on the analyzed `message` text, e.g. `{"phrase": "connection reset"}`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageMatch {
+	/// All words must occur, in any order.
+	Text(String),
+	/// The words must occur in this order.
+	Phrase(String),
+	/// Words starting with this text; the last word may be incomplete.
+	Prefix(String),
+	/// A single word with `*` and `?` wildcards, case-insensitive.
+	Wildcard(String),
+}
+
+impl MessageMatch {
+	/// Classifies one unquoted word: `conn*` is a prefix, `us?r` a wildcard, anything else text.
+	pub fn from_word(word: &str) -> MessageMatch {
+		match word.strip_suffix('*') {
+			Some(stem) if !stem.is_empty() && !stem.contains(['*', '?']) => MessageMatch::Prefix(stem.to_string()),
+			_ if word.contains(['*', '?']) => MessageMatch::Wildcard(word.to_string()),
+			_ => MessageMatch::Text(word.to_string()),
+		}
+	}
+}
+
+/// Bounds for a numeric attribute filter. Unset bounds are open.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AttributeRange {
+	pub gt: Option<f64>,
+	pub gte: Option<f64>,
+	pub lt: Option<f64>,
+	pub lte: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -92,15 +465,11 @@ pub struct QuotaConfig {
 	pub logs_per_second: u64,
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum LogSystemError {
-	#[error("Rate limit exceeded: {0}")]
-	RateLimitExceeded(String),
-	#[error("Storage error: {0}")]
-	StorageError(String),
-	#[error("Network error: {0}")]
-	NetworkError(String),
-}
+/// Sent by ingestion with a 429, together with `Retry-After`: the app's quota
+/// in logs per second.
+pub const RATE_LIMIT_LIMIT_HEADER: &str = "x-ratelimit-limit";
+/// Sent by ingestion with a 429: the logs the app may still send right now.
+pub const RATE_LIMIT_REMAINING_HEADER: &str = "x-ratelimit-remaining";
 
 #[cfg(test)]
 mod tests {
@@ -109,7 +478,8 @@ mod tests {
 	#[test]
 	fn test_log_entry_creation() {
 		let mut attrs = HashMap::new();
-		attrs.insert("user_id".to_string(), "123".to_string());
+		attrs.insert("user_id".to_string(), "123".into());
+		attrs.insert("retries".to_string(), 3.into());
 
 		let log = LogEntry::new(
 			"test-app".to_string(),
@@ -121,8 +491,37 @@ mod tests {
 		assert_eq!(log.app_name, "test-app");
 		assert_eq!(log.level, LogLevel::Info);
 		assert_eq!(log.message, "Test message");
-		assert_eq!(log.attributes.get("user_id"), Some(&"123".to_string()));
+		assert_eq!(log.attributes.get("user_id"), Some(&AttributeValue::from("123")));
+		assert_eq!(log.attributes.get("retries"), Some(&AttributeValue::Int(3)));
 		assert!(!log.id.is_empty());
+		assert_eq!(log.trace_id, None);
+	}
+
+	#[test]
+	fn test_log_entry_trace_context() {
+		let ctx = TraceContext::from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+		let log = LogEntry::new("test-app".to_string(), LogLevel::Info, "Traced".to_string(), HashMap::new())
+			.with_trace_context(&ctx);
+
+		let json = serde_json::to_value(&log).unwrap();
+		assert_eq!(json["trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+		assert_eq!(json["span_id"], "00f067aa0ba902b7");
+
+		let untraced = LogEntry::new("test-app".to_string(), LogLevel::Info, "Plain".to_string(), HashMap::new());
+		let json = serde_json::to_value(&untraced).unwrap();
+		assert!(json.get("trace_id").is_none());
+		let back: LogEntry = serde_json::from_value(json).unwrap();
+		assert_eq!(back.span_id, None);
+	}
+
+	#[test]
+	fn test_ids_sort_by_creation_time() {
+		let ids: Vec<String> = (0..1000).map(|_| new_id()).collect();
+		let mut sorted = ids.clone();
+		sorted.sort();
+
+		assert_eq!(ids, sorted);
+		assert_eq!(Uuid::parse_str(&ids[0]).unwrap().get_version_num(), 7);
 	}
 
 	#[test]
@@ -130,14 +529,14 @@ mod tests {
 		let mut log = LogEntry::new(
 			"payment-app".to_string(),
 			LogLevel::Info,
-			"Payment with card 1234567812345678 processed".to_string(),
+			"Payment with card 4111111111111111 processed".to_string(),
 			HashMap::new(),
 		);
 
 		log.mask_secrets();
 
 		assert!(log.message.contains("****-****-****-****"));
-		assert!(!log.message.contains("1234567812345678"));
+		assert!(!log.message.contains("4111111111111111"));
 	}
 
 	#[test]
@@ -188,10 +587,10 @@ mod tests {
 	#[test]
 	fn test_mask_attributes() {
 		let mut attrs = HashMap::new();
-		attrs.insert("user_password".to_string(), "secret".to_string());
-		attrs.insert("api_token".to_string(), "abc123".to_string());
-		attrs.insert("user_secret".to_string(), "hidden".to_string());
-		attrs.insert("user_name".to_string(), "John".to_string());
+		attrs.insert("user_password".to_string(), "secret".into());
+		attrs.insert("api_token".to_string(), "abc123".into());
+		attrs.insert("user_secret".to_string(), "hidden".into());
+		attrs.insert("user_name".to_string(), "John".into());
 
 		let mut log = LogEntry::new(
 			"test-app".to_string(),
@@ -202,10 +601,10 @@ mod tests {
 
 		log.mask_secrets();
 
-		assert_eq!(log.attributes.get("user_password"), Some(&"***".to_string()));
-		assert_eq!(log.attributes.get("api_token"), Some(&"***".to_string()));
-		assert_eq!(log.attributes.get("user_secret"), Some(&"***".to_string()));
-		assert_eq!(log.attributes.get("user_name"), Some(&"John".to_string()));
+		assert_eq!(log.attributes.get("user_password"), Some(&AttributeValue::from("***")));
+		assert_eq!(log.attributes.get("api_token"), Some(&AttributeValue::from("***")));
+		assert_eq!(log.attributes.get("user_secret"), Some(&AttributeValue::from("***")));
+		assert_eq!(log.attributes.get("user_name"), Some(&AttributeValue::from("John")));
 	}
 
 	#[test]
@@ -229,6 +628,29 @@ mod tests {
 
 		assert_eq!(batch.logs.len(), 2);
 		assert!(!batch.batch_id.is_empty());
+		assert!(batch.resource.is_none());
+	}
+
+	#[test]
+	fn test_log_batch_resource_serialization() {
+		let resource = Resource {
+			service_name: Some("checkout".to_string()),
+			host_name: Some("web-1".to_string()),
+			process_id: Some(4242),
+			environment: Some("prod".to_string()),
+			..Default::default()
+		};
+		let batch = LogBatch::new(Vec::new()).with_resource(resource.clone());
+
+		let json = serde_json::to_value(&batch).unwrap();
+		assert_eq!(json["resource"]["host_name"], "web-1");
+		assert!(json["resource"].get("attributes").is_none());
+
+		let back: LogBatch = serde_json::from_value(json).unwrap();
+		assert_eq!(back.resource, Some(resource));
+
+		let without: LogBatch = serde_json::from_str(r#"{"logs":[],"batch_id":"b1"}"#).unwrap();
+		assert!(without.resource.is_none());
 	}
 
 	#[test]
@@ -239,6 +661,33 @@ mod tests {
 
 		let deserialized: LogLevel = serde_json::from_str(&json).unwrap();
 		assert_eq!(deserialized, LogLevel::Info);
+
+		let legacy: Vec<LogLevel> = serde_json::from_str(r#"["Debug","Info","Warn","Error"]"#).unwrap();
+		assert_eq!(legacy, vec![LogLevel::Debug, LogLevel::Info, LogLevel::Warn, LogLevel::Error]);
+	}
+
+	#[test]
+	fn test_log_level_ordering_and_severity() {
+		assert!(LogLevel::Trace < LogLevel::Debug);
+		assert!(LogLevel::Error < LogLevel::Fatal);
+		assert_eq!(LogLevel::ALL.iter().max(), Some(&LogLevel::Fatal));
+
+		for level in LogLevel::ALL {
+			assert_eq!(LogLevel::from_severity_number(level.severity_number()), Some(level));
+		}
+		assert_eq!(LogLevel::from_severity_number(15), Some(LogLevel::Warn));
+		assert_eq!(LogLevel::from_severity_number(0), None);
+		assert_eq!(LogLevel::from_severity_number(25), None);
+	}
+
+	#[test]
+	fn test_log_level_from_str() {
+		assert_eq!("warn".parse::<LogLevel>().unwrap(), LogLevel::Warn);
+		assert_eq!("ERROR".parse::<LogLevel>().unwrap(), LogLevel::Error);
+		assert_eq!("Trace".parse::<LogLevel>().unwrap(), LogLevel::Trace);
+		assert_eq!("21".parse::<LogLevel>().unwrap(), LogLevel::Fatal);
+		assert!("verbose".parse::<LogLevel>().is_err());
+		assert!("99".parse::<LogLevel>().is_err());
 	}
 
 	#[test]
@@ -246,14 +695,52 @@ mod tests {
 		let query = SearchQuery {
 			app_name: Some("test-app".to_string()),
 			level: Some(LogLevel::Error),
-			from: None,
-			to: None,
-			attributes: None,
 			limit: Some(100),
+			..Default::default()
 		};
 
 		assert_eq!(query.app_name, Some("test-app".to_string()));
 		assert_eq!(query.level, Some(LogLevel::Error));
 		assert_eq!(query.limit, Some(100));
 	}
+
+	#[test]
+	fn test_attribute_value_round_trip() {
+		let json = r#"{"user":"alice","duration_ms":812,"ratio":0.5,"cached":false,"tags":["a","b"],"http":{"status":503}}"#;
+		let attrs: HashMap<String, AttributeValue> = serde_json::from_str(json).unwrap();
+
+		assert_eq!(attrs["user"], AttributeValue::from("alice"));
+		assert_eq!(attrs["duration_ms"], AttributeValue::Int(812));
+		assert_eq!(attrs["ratio"], AttributeValue::Float(0.5));
+		assert_eq!(attrs["cached"], AttributeValue::Bool(false));
+		assert_eq!(attrs["tags"], AttributeValue::from(vec!["a", "b"]));
+		match &attrs["http"] {
+			AttributeValue::Object(fields) => assert_eq!(fields["status"], AttributeValue::Int(503)),
+			other => panic!("expected object, got {:?}", other),
+		}
+
+		let back: serde_json::Value = serde_json::to_value(&attrs).unwrap();
+		assert_eq!(back, serde_json::from_str::<serde_json::Value>(json).unwrap());
+	}
+
+	#[test]
+	fn test_search_query_with_attribute_range() {
+		let json = r#"{"app_name":"api","attribute_ranges":{"duration_ms":{"gt":500}}}"#;
+		let query: SearchQuery = serde_json::from_str(json).unwrap();
+
+		let range = &query.attribute_ranges.unwrap()["duration_ms"];
+		assert_eq!(range.gt, Some(500.0));
+		assert_eq!(range.lte, None);
+	}
+
+	#[test]
+	fn test_search_query_with_message_matches() {
+		let json = r#"{"message":[{"phrase":"connection reset"},{"prefix":"time"}]}"#;
+		let query: SearchQuery = serde_json::from_str(json).unwrap();
+
+		assert_eq!(
+			query.message.unwrap(),
+			vec![MessageMatch::Phrase("connection reset".to_string()), MessageMatch::Prefix("time".to_string())]
+		);
+	}
 }
@@ -0,0 +1,530 @@
+//! Per-app size limits on log entries, enforced at ingestion.
+//!
+//! Every limit either truncates the entry, marking it with
+//! [`LogEntry::truncated`], or rejects it. Lengths are in bytes; truncation
+//! never splits a UTF-8 character.
+
+use crate::{AttributeValue, ErrorInfo, LogEntry};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// What happens to an entry that exceeds a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitAction {
+	/// Cut the entry down to the limit and mark it as truncated.
+	#[default]
+	Truncate,
+	/// Drop the entry.
+	Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limit {
+	pub max: usize,
+	#[serde(default)]
+	pub action: LimitAction,
+}
+
+impl Limit {
+	pub fn truncate(max: usize) -> Self {
+		Self {
+			max,
+			action: LimitAction::Truncate,
+		}
+	}
+
+	pub fn reject(max: usize) -> Self {
+		Self {
+			max,
+			action: LimitAction::Reject,
+		}
+	}
+}
+
+/// The limit an entry exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitKind {
+	MessageBytes,
+	AttributeCount,
+	KeyLength,
+	ValueLength,
+	FrameCount,
+}
+
+impl LimitKind {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			LimitKind::MessageBytes => "message_bytes",
+			LimitKind::AttributeCount => "attribute_count",
+			LimitKind::KeyLength => "key_length",
+			LimitKind::ValueLength => "value_length",
+			LimitKind::FrameCount => "frame_count",
+		}
+	}
+}
+
+impl std::fmt::Display for LimitKind {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+/// Result of [`SizeLimits::enforce`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitOutcome {
+	Within,
+	Truncated,
+	/// The entry exceeds a limit that rejects; it is left unchanged.
+	Rejected(LimitKind),
+}
+
+/// Per-app size limits served by the config service.
+///
+/// Nested values count like top-level ones: `attribute_count` counts every
+/// key and array element, `key_length` applies to the keys of nested objects
+/// and `value_length` to every string in an attribute value.
+///
+/// The `error` field counts as well: `message_bytes` applies to the messages
+/// of the error and its causes, `value_length` to their types and to the
+/// function and file of every frame, and `frame_count` caps the frames.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SizeLimits {
+	pub app_name: String,
+	#[serde(default = "default_message_bytes")]
+	pub message_bytes: Limit,
+	#[serde(default = "default_attribute_count")]
+	pub attribute_count: Limit,
+	#[serde(default = "default_key_length")]
+	pub key_length: Limit,
+	#[serde(default = "default_value_length")]
+	pub value_length: Limit,
+	#[serde(default = "default_frame_count")]
+	pub frame_count: Limit,
+}
+
+fn default_message_bytes() -> Limit {
+	Limit::truncate(64 * 1024)
+}
+
+fn default_attribute_count() -> Limit {
+	Limit::truncate(128)
+}
+
+fn default_key_length() -> Limit {
+	Limit::truncate(128)
+}
+
+fn default_value_length() -> Limit {
+	Limit::truncate(16 * 1024)
+}
+
+fn default_frame_count() -> Limit {
+	Limit::truncate(64)
+}
+
+impl SizeLimits {
+	/// The default limits, all truncating.
+	pub fn new(app_name: impl Into<String>) -> Self {
+		Self {
+			app_name: app_name.into(),
+			message_bytes: default_message_bytes(),
+			attribute_count: default_attribute_count(),
+			key_length: default_key_length(),
+			value_length: default_value_length(),
+			frame_count: default_frame_count(),
+		}
+	}
+
+	/// Shared instance of the default limits, for apps without their own.
+	pub fn builtin() -> &'static SizeLimits {
+		static BUILTIN: OnceLock<SizeLimits> = OnceLock::new();
+		BUILTIN.get_or_init(|| SizeLimits::new(""))
+	}
+
+	pub fn limit(&self, kind: LimitKind) -> Limit {
+		match kind {
+			LimitKind::MessageBytes => self.message_bytes,
+			LimitKind::AttributeCount => self.attribute_count,
+			LimitKind::KeyLength => self.key_length,
+			LimitKind::ValueLength => self.value_length,
+			LimitKind::FrameCount => self.frame_count,
+		}
+	}
+
+	/// Limits that `entry` exceeds, in the order they are applied.
+	pub fn exceeded(&self, entry: &LogEntry) -> Vec<LimitKind> {
+		let mut exceeded = Vec::new();
+		let error = entry.error.as_ref();
+		if entry.message.len() > self.message_bytes.max
+			|| error.is_some_and(|error| longest_error_message(error) > self.message_bytes.max)
+		{
+			exceeded.push(LimitKind::MessageBytes);
+		}
+		if longest_key(&entry.attributes) > self.key_length.max {
+			exceeded.push(LimitKind::KeyLength);
+		}
+		if attribute_count(&entry.attributes) > self.attribute_count.max {
+			exceeded.push(LimitKind::AttributeCount);
+		}
+		if entry.attributes.values().any(|value| longest_string(value) > self.value_length.max)
+			|| error.is_some_and(|error| longest_error_field(error) > self.value_length.max)
+		{
+			exceeded.push(LimitKind::ValueLength);
+		}
+		if error.is_some_and(|error| error.frames.len() > self.frame_count.max) {
+			exceeded.push(LimitKind::FrameCount);
+		}
+		exceeded
+	}
+
+	/// Truncates `entry` to the limits, unless one of the limits it exceeds rejects.
+	pub fn enforce(&self, entry: &mut LogEntry) -> LimitOutcome {
+		let exceeded = self.exceeded(entry);
+		if let Some(kind) = exceeded.iter().find(|kind| self.limit(**kind).action == LimitAction::Reject) {
+			return LimitOutcome::Rejected(*kind);
+		}
+		if exceeded.is_empty() {
+			return LimitOutcome::Within;
+		}
+
+		for kind in exceeded {
+			match kind {
+				LimitKind::MessageBytes => {
+					let max = self.message_bytes.max;
+					truncate(&mut entry.message, max);
+					if let Some(error) = &mut entry.error {
+						truncate(&mut error.message, max);
+						error.causes.iter_mut().for_each(|cause| truncate(&mut cause.message, max));
+					}
+				}
+				LimitKind::KeyLength => shorten_keys(&mut entry.attributes, self.key_length.max),
+				LimitKind::AttributeCount => {
+					let mut budget = self.attribute_count.max;
+					trim_fields(&mut entry.attributes, &mut budget);
+				}
+				LimitKind::ValueLength => {
+					let max = self.value_length.max;
+					for value in entry.attributes.values_mut() {
+						value.visit_strings_mut(&mut |text| truncate(text, max));
+					}
+					if let Some(error) = &mut entry.error {
+						truncate(&mut error.error_type, max);
+						for cause in &mut error.causes {
+							truncate(&mut cause.error_type, max);
+						}
+						for frame in &mut error.frames {
+							truncate(&mut frame.function, max);
+							if let Some(file) = &mut frame.file {
+								truncate(file, max);
+							}
+						}
+					}
+				}
+				LimitKind::FrameCount => {
+					if let Some(error) = &mut entry.error {
+						// Frames are innermost first, so the outermost ones go.
+						error.frames.truncate(self.frame_count.max);
+					}
+				}
+			}
+		}
+		entry.truncated = true;
+		LimitOutcome::Truncated
+	}
+}
+
+/// Keys and array elements in `fields`, nested ones included.
+fn attribute_count(fields: &HashMap<String, AttributeValue>) -> usize {
+	fields.values().map(|value| 1 + nested_count(value)).sum()
+}
+
+fn nested_count(value: &AttributeValue) -> usize {
+	match value {
+		AttributeValue::Array(items) => items.iter().map(|item| 1 + nested_count(item)).sum(),
+		AttributeValue::Object(fields) => attribute_count(fields),
+		AttributeValue::Bool(_) | AttributeValue::Int(_) | AttributeValue::Float(_) | AttributeValue::String(_) => 0,
+	}
+}
+
+fn longest_key(fields: &HashMap<String, AttributeValue>) -> usize {
+	fields
+		.iter()
+		.map(|(key, value)| key.len().max(longest_nested_key(value)))
+		.max()
+		.unwrap_or(0)
+}
+
+fn longest_nested_key(value: &AttributeValue) -> usize {
+	match value {
+		AttributeValue::Array(items) => items.iter().map(longest_nested_key).max().unwrap_or(0),
+		AttributeValue::Object(fields) => longest_key(fields),
+		AttributeValue::Bool(_) | AttributeValue::Int(_) | AttributeValue::Float(_) | AttributeValue::String(_) => 0,
+	}
+}
+
+/// Cuts every key in `fields`, nested ones included, to `max` bytes.
+fn shorten_keys(fields: &mut HashMap<String, AttributeValue>, max: usize) {
+	let mut long_keys: Vec<String> = fields.keys().filter(|key| key.len() > max).cloned().collect();
+	long_keys.sort();
+	for key in long_keys {
+		let value = fields.remove(&key).expect("key was just listed");
+		let mut short_key = key;
+		truncate(&mut short_key, max);
+		// A key that collides with an existing one after truncation is dropped.
+		fields.entry(short_key).or_insert(value);
+	}
+	for value in fields.values_mut() {
+		shorten_nested_keys(value, max);
+	}
+}
+
+fn shorten_nested_keys(value: &mut AttributeValue, max: usize) {
+	match value {
+		AttributeValue::Array(items) => items.iter_mut().for_each(|item| shorten_nested_keys(item, max)),
+		AttributeValue::Object(fields) => shorten_keys(fields, max),
+		AttributeValue::Bool(_) | AttributeValue::Int(_) | AttributeValue::Float(_) | AttributeValue::String(_) => {}
+	}
+}
+
+/// Keeps the first `budget` keys and array elements, depth first. Keys are
+/// taken in sorted order, so the result does not depend on map order.
+fn trim_fields(fields: &mut HashMap<String, AttributeValue>, budget: &mut usize) {
+	let mut keys: Vec<String> = fields.keys().cloned().collect();
+	keys.sort();
+	for key in keys {
+		if *budget == 0 {
+			fields.remove(&key);
+			continue;
+		}
+		*budget -= 1;
+		trim_nested(fields.get_mut(&key).expect("key was just listed"), budget);
+	}
+}
+
+fn trim_nested(value: &mut AttributeValue, budget: &mut usize) {
+	match value {
+		AttributeValue::Array(items) => {
+			let mut kept = 0;
+			for item in items.iter_mut() {
+				if *budget == 0 {
+					break;
+				}
+				*budget -= 1;
+				kept += 1;
+				trim_nested(item, budget);
+			}
+			items.truncate(kept);
+		}
+		AttributeValue::Object(fields) => trim_fields(fields, budget),
+		AttributeValue::Bool(_) | AttributeValue::Int(_) | AttributeValue::Float(_) | AttributeValue::String(_) => {}
+	}
+}
+
+fn longest_string(value: &AttributeValue) -> usize {
+	match value {
+		AttributeValue::String(s) => s.len(),
+		AttributeValue::Array(items) => items.iter().map(longest_string).max().unwrap_or(0),
+		AttributeValue::Object(fields) => fields.values().map(longest_string).max().unwrap_or(0),
+		AttributeValue::Bool(_) | AttributeValue::Int(_) | AttributeValue::Float(_) => 0,
+	}
+}
+
+fn longest_error_message(error: &ErrorInfo) -> usize {
+	error
+		.causes
+		.iter()
+		.map(|cause| cause.message.len())
+		.fold(error.message.len(), usize::max)
+}
+
+/// Longest type name, function or file in `error`.
+fn longest_error_field(error: &ErrorInfo) -> usize {
+	let causes = error.causes.iter().map(|cause| cause.error_type.len());
+	let frames = error
+		.frames
+		.iter()
+		.map(|frame| frame.function.len().max(frame.file.as_ref().map_or(0, String::len)));
+	causes.chain(frames).fold(error.error_type.len(), usize::max)
+}
+
+/// Cuts `text` to at most `max` bytes on a character boundary.
+fn truncate(text: &mut String, max: usize) {
+	if text.len() <= max {
+		return;
+	}
+	let mut end = max;
+	while !text.is_char_boundary(end) {
+		end -= 1;
+	}
+	text.truncate(end);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{ErrorCause, LogLevel, StackFrame};
+	use std::collections::HashMap;
+
+	fn entry(message: &str, attributes: &[(&str, AttributeValue)]) -> LogEntry {
+		LogEntry::new(
+			"test-app".to_string(),
+			LogLevel::Info,
+			message.to_string(),
+			attributes.iter().map(|(k, v)| (k.to_string(), v.clone())).collect::<HashMap<_, _>>(),
+		)
+	}
+
+	fn limits() -> SizeLimits {
+		SizeLimits {
+			app_name: "test-app".to_string(),
+			message_bytes: Limit::truncate(8),
+			attribute_count: Limit::truncate(2),
+			key_length: Limit::truncate(4),
+			value_length: Limit::truncate(3),
+			frame_count: Limit::truncate(2),
+		}
+	}
+
+	#[test]
+	fn test_within_limits_is_untouched() {
+		let mut log = entry("short", &[("a", "abc".into()), ("n", 123456i64.into())]);
+		let before = log.clone();
+
+		assert_eq!(limits().enforce(&mut log), LimitOutcome::Within);
+		assert_eq!(log.message, before.message);
+		assert_eq!(log.attributes, before.attributes);
+		assert!(!log.truncated);
+	}
+
+	#[test]
+	fn test_truncate() {
+		let mut log = entry(
+			"héllo wörld",
+			&[
+				("longkey", "v".into()),
+				("b", vec!["abcdef", "x"].into()),
+				("c", "ok".into()),
+			],
+		);
+
+		// Three keys and the two elements of "b" count.
+		let limits = SizeLimits {
+			attribute_count: Limit::truncate(4),
+			..limits()
+		};
+		assert_eq!(limits.enforce(&mut log), LimitOutcome::Truncated);
+		assert!(log.truncated);
+		// "héllo w" is 8 bytes, the ö would not fit.
+		assert_eq!(log.message, "héllo w");
+		// "long" sorts after "b" and "c" and is dropped by the count limit.
+		assert_eq!(log.attributes.len(), 2);
+		assert_eq!(log.attributes["b"], AttributeValue::from(vec!["abc", "x"]));
+		assert_eq!(log.attributes["c"], AttributeValue::from("ok"));
+	}
+
+	#[test]
+	fn test_nested_keys_and_elements_count() {
+		let nested = |fields: &[(&str, AttributeValue)]| {
+			AttributeValue::Object(fields.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+		};
+		let limits = SizeLimits {
+			attribute_count: Limit::truncate(5),
+			..limits()
+		};
+		let mut log = entry(
+			"short",
+			&[
+				("a", nested(&[("z", "1".into()), ("longkey", "2".into()), ("y", vec!["p", "q"].into())])),
+				("b", "3".into()),
+			],
+		);
+		assert_eq!(attribute_count(&log.attributes), 7);
+		assert_eq!(limits.exceeded(&log), vec![LimitKind::KeyLength, LimitKind::AttributeCount]);
+
+		assert_eq!(limits.enforce(&mut log), LimitOutcome::Truncated);
+		// Depth first: "a", "long", "y" and its two elements; "z" and "b" are dropped.
+		assert_eq!(log.attributes["a"], nested(&[("long", "2".into()), ("y", vec!["p", "q"].into())]));
+		assert!(!log.attributes.contains_key("b"));
+		assert_eq!(attribute_count(&log.attributes), 5);
+
+		let limits = SizeLimits {
+			key_length: Limit::reject(4),
+			..limits
+		};
+		let mut log = entry("short", &[("a", nested(&[("longkey", "1".into())]))]);
+		assert_eq!(limits.enforce(&mut log), LimitOutcome::Rejected(LimitKind::KeyLength));
+	}
+
+	#[test]
+	fn test_error_counts_against_limits() {
+		let mut error = ErrorInfo::new("TimeoutError", "timed out after 30s");
+		error.causes.push(ErrorCause {
+			error_type: "io".to_string(),
+			message: "connection reset".to_string(),
+		});
+		let frame = |function: &str| StackFrame {
+			function: function.to_string(),
+			file: Some("src/db.rs".to_string()),
+			line: None,
+			column: None,
+		};
+		error.frames = vec![frame("main"), frame("run"), frame("poll")];
+		let mut log = entry("short", &[]).with_error(error);
+		let limits = SizeLimits {
+			value_length: Limit::truncate(4),
+			..limits()
+		};
+		assert_eq!(
+			limits.exceeded(&log),
+			vec![LimitKind::MessageBytes, LimitKind::ValueLength, LimitKind::FrameCount]
+		);
+
+		assert_eq!(limits.enforce(&mut log), LimitOutcome::Truncated);
+		let error = log.error.unwrap();
+		assert_eq!(error.error_type, "Time");
+		assert_eq!(error.message, "timed ou");
+		assert_eq!(error.causes[0].message, "connecti");
+		let frames: Vec<_> = error
+			.frames
+			.iter()
+			.map(|frame| (frame.function.as_str(), frame.file.as_deref()))
+			.collect();
+		assert_eq!(frames, vec![("main", Some("src/")), ("run", Some("src/"))]);
+
+		let limits = SizeLimits {
+			frame_count: Limit::reject(2),
+			..SizeLimits::new("test-app")
+		};
+		let mut log = entry("short", &[]).with_error(ErrorInfo {
+			frames: vec![frame("main"); 3],
+			..ErrorInfo::new("TimeoutError", "timed out")
+		});
+		assert_eq!(limits.enforce(&mut log), LimitOutcome::Rejected(LimitKind::FrameCount));
+	}
+
+	#[test]
+	fn test_reject() {
+		let limits = SizeLimits {
+			attribute_count: Limit::reject(1),
+			..limits()
+		};
+		let mut log = entry("a very long message", &[("a", "1".into()), ("b", "2".into())]);
+
+		assert_eq!(limits.enforce(&mut log), LimitOutcome::Rejected(LimitKind::AttributeCount));
+		assert_eq!(log.message, "a very long message");
+		assert!(!log.truncated);
+	}
+
+	#[test]
+	fn test_missing_limits_use_defaults() {
+		let limits: SizeLimits = serde_json::from_str(
+			r#"{"app_name": "test-app", "message_bytes": {"max": 1024, "action": "reject"}}"#,
+		)
+		.unwrap();
+
+		assert_eq!(limits.message_bytes, Limit::reject(1024));
+		assert_eq!(limits.attribute_count, SizeLimits::builtin().attribute_count);
+	}
+}
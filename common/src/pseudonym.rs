@@ -0,0 +1,219 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Prefix of every token from [`Pseudonymizer::token`].
+pub const TOKEN_PREFIX: &str = "psd_";
+
+/// Receives every pseudonym handed out, so that it can be re-identified later.
+pub trait PseudonymVault: Send + Sync {
+	fn record(&self, token: &str, original: &str);
+}
+
+/// Replaces sensitive values with keyed HMAC-SHA256 tokens.
+///
+/// A value always gets the same token under the same key, so entries about the
+/// same user can still be correlated, but the value cannot be recovered
+/// without the key holder's vault.
+#[derive(Clone)]
+pub struct Pseudonymizer {
+	key: Vec<u8>,
+	vault: Option<Arc<dyn PseudonymVault>>,
+}
+
+impl Pseudonymizer {
+	pub fn new(key: impl Into<Vec<u8>>) -> Self {
+		Self {
+			key: key.into(),
+			vault: None,
+		}
+	}
+
+	/// A throwaway key; tokens are only stable for the lifetime of the process.
+	pub fn with_random_key() -> Self {
+		let mut key = Uuid::new_v4().as_bytes().to_vec();
+		key.extend_from_slice(Uuid::new_v4().as_bytes());
+		Self::new(key)
+	}
+
+	pub fn with_vault(mut self, vault: Arc<dyn PseudonymVault>) -> Self {
+		self.vault = Some(vault);
+		self
+	}
+
+	/// Token such as `psd_3f9a1c2b7d4e5f60`.
+	pub fn token(&self, value: &str) -> String {
+		let digest = self.digest("value", value);
+		let mut token = String::with_capacity(TOKEN_PREFIX.len() + 16);
+		token.push_str(TOKEN_PREFIX);
+		for byte in &digest[..8] {
+			token.push_str(&format!("{:02x}", byte));
+		}
+		self.record(&token, value);
+		token
+	}
+
+	/// Format-preserving token for a card number.
+	///
+	/// Separators and the last four digits are kept; the other digits are
+	/// derived from the key. The result never passes the Luhn check, so it
+	/// cannot be mistaken for a real card. Values with four digits or fewer
+	/// get a plain [`token`](Self::token).
+	pub fn card_token(&self, card: &str) -> String {
+		let digits: String = card.chars().filter(char::is_ascii_digit).collect();
+		if digits.len() <= 4 {
+			return self.token(card);
+		}
+
+		let digest = self.digest("card", &digits);
+		let replaced = digits.len() - 4;
+		let mut new_digits: Vec<u8> = digits.bytes().map(|b| b - b'0').collect();
+		for (i, digit) in new_digits.iter_mut().take(replaced).enumerate() {
+			*digit = digest[i % digest.len()] % 10;
+		}
+		if luhn_valid(&new_digits) {
+			// Changing any single digit changes the Luhn sum by a non-multiple of 10.
+			new_digits[0] = (new_digits[0] + 1) % 10;
+		}
+
+		let mut new_digits = new_digits.into_iter();
+		let token: String = card
+			.chars()
+			.map(|c| {
+				if c.is_ascii_digit() {
+					char::from(b'0' + new_digits.next().unwrap_or_default())
+				} else {
+					c
+				}
+			})
+			.collect();
+		self.record(&token, card);
+		token
+	}
+
+	fn digest(&self, domain: &str, value: &str) -> [u8; 32] {
+		let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+		mac.update(domain.as_bytes());
+		mac.update(&[0]);
+		mac.update(value.as_bytes());
+		mac.finalize().into_bytes().into()
+	}
+
+	fn record(&self, token: &str, original: &str) {
+		if let Some(vault) = &self.vault {
+			vault.record(token, original);
+		}
+	}
+}
+
+impl fmt::Debug for Pseudonymizer {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Pseudonymizer")
+			.field("key", &"<redacted>")
+			.field("vault", &self.vault.is_some())
+			.finish()
+	}
+}
+
+/// Luhn checksum over digit values (0-9), check digit last.
+pub(crate) fn luhn_valid(digits: &[u8]) -> bool {
+	let sum: u32 = digits
+		.iter()
+		.rev()
+		.enumerate()
+		.map(|(i, &d)| {
+			let d = d as u32;
+			if i % 2 == 1 {
+				let doubled = d * 2;
+				if doubled > 9 { doubled - 9 } else { doubled }
+			} else {
+				d
+			}
+		})
+		.sum();
+	!digits.is_empty() && sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+
+	#[derive(Default)]
+	struct MemoryVault(Mutex<Vec<(String, String)>>);
+
+	impl PseudonymVault for MemoryVault {
+		fn record(&self, token: &str, original: &str) {
+			self.0.lock().unwrap().push((token.to_string(), original.to_string()));
+		}
+	}
+
+	fn digits(card: &str) -> Vec<u8> {
+		card.bytes().filter(u8::is_ascii_digit).map(|b| b - b'0').collect()
+	}
+
+	#[test]
+	fn test_token_is_stable_per_key() {
+		let a = Pseudonymizer::new("key-a");
+		let b = Pseudonymizer::new("key-b");
+
+		let token = a.token("alice@example.com");
+		assert!(token.starts_with(TOKEN_PREFIX));
+		assert_eq!(token.len(), TOKEN_PREFIX.len() + 16);
+		assert_eq!(token, a.token("alice@example.com"));
+		assert_ne!(token, a.token("bob@example.com"));
+		assert_ne!(token, b.token("alice@example.com"));
+		assert!(!format!("{:?}", a).contains("key-a"));
+	}
+
+	#[test]
+	fn test_card_token_keeps_layout_and_last_four() {
+		let pseudonymizer = Pseudonymizer::new("key");
+
+		for card in ["4111-1111-1111-1111", "4111 1111 1111 1111", "378282246310005", "6011000990139424"] {
+			let token = pseudonymizer.card_token(card);
+			assert_eq!(token.len(), card.len());
+			assert_eq!(&token[token.len() - 4..], &card[card.len() - 4..]);
+			assert_eq!(token.replace(|c: char| c.is_ascii_digit(), "#"), card.replace(|c: char| c.is_ascii_digit(), "#"));
+			assert_ne!(token, card);
+			assert!(!luhn_valid(&digits(&token)), "{} passes Luhn", token);
+		}
+
+		// Separators do not change the pseudonym digits.
+		assert_eq!(
+			pseudonymizer.card_token("4111-1111-1111-1111").replace('-', ""),
+			pseudonymizer.card_token("4111 1111 1111 1111").replace(' ', "")
+		);
+		assert!(pseudonymizer.card_token("1234").starts_with(TOKEN_PREFIX));
+	}
+
+	#[test]
+	fn test_vault_records_tokens() {
+		let vault = Arc::new(MemoryVault::default());
+		let pseudonymizer = Pseudonymizer::new("key").with_vault(vault.clone());
+
+		let token = pseudonymizer.token("alice@example.com");
+		let card = pseudonymizer.card_token("4111-1111-1111-1111");
+
+		let recorded = vault.0.lock().unwrap().clone();
+		assert_eq!(
+			recorded,
+			vec![
+				(token, "alice@example.com".to_string()),
+				(card, "4111-1111-1111-1111".to_string())
+			]
+		);
+	}
+
+	#[test]
+	fn test_luhn() {
+		assert!(luhn_valid(&digits("4111111111111111")));
+		assert!(luhn_valid(&digits("378282246310005")));
+		assert!(!luhn_valid(&digits("4111111111111112")));
+		assert!(!luhn_valid(&[]));
+	}
+}
@@ -0,0 +1,512 @@
+//! Log query language.
+//!
+//! ```text
+//! app:payment-service AND level>=Warn AND attributes.user_id:123 AND "timeout"
+//! ```
+//!
+//! ```text
+//! query   = or
+//! or      = and ("OR" and)*
+//! and     = unary (["AND"] unary)*
+//! unary   = ("NOT" | "-") unary | primary
+//! primary = "(" or ")" | field op value | value
+//! op      = ":" | "=" | ">" | ">=" | "<" | "<="
+//! ```
+//!
+//! Terms next to each other are ANDed, and AND binds tighter than OR. Operators
+//! are upper case; a lower-case `and` is an ordinary word. A value without a
+//! field matches the message like [`MessageMatch::from_word`], a quoted one as
+//! a phrase. Field values may use `*` and `?` wildcards, and `field:*` matches
+//! entries that have the field at all.
+//!
+//! Fields are `app` (or `app_name`), `level`, `timestamp`, `trace_id`,
+//! `span_id`, `id`, `message`, `attributes.<key>` and `resource.<key>`, and
+//! for entries with an attached error `error.type`, `error.function` and
+//! `error.file` (the full path or the file name) of the top frame.
+
+use crate::{LogLevel, LogSystemError, MessageMatch};
+
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+	And(Vec<Expr>),
+	Or(Vec<Expr>),
+	Not(Box<Expr>),
+	Compare { field: Field, op: Op, value: Value },
+	Message(MessageMatch),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+	AppName,
+	Level,
+	Timestamp,
+	TraceId,
+	SpanId,
+	Id,
+	ErrorType,
+	ErrorFunction,
+	ErrorFile,
+	Attribute(String),
+	Resource(String),
+}
+
+impl Field {
+	fn parse(name: &str) -> Option<Field> {
+		let field = match name {
+			"app" | "app_name" => Field::AppName,
+			"level" => Field::Level,
+			"timestamp" => Field::Timestamp,
+			"trace_id" => Field::TraceId,
+			"span_id" => Field::SpanId,
+			"id" => Field::Id,
+			"error.type" => Field::ErrorType,
+			"error.function" => Field::ErrorFunction,
+			"error.file" => Field::ErrorFile,
+			_ => {
+				if let Some(key) = name.strip_prefix("attributes.").filter(|key| !key.is_empty()) {
+					Field::Attribute(key.to_string())
+				} else if let Some(key) = name.strip_prefix("resource.").filter(|key| !key.is_empty()) {
+					Field::Resource(key.to_string())
+				} else {
+					return None;
+				}
+			}
+		};
+		Some(field)
+	}
+
+	fn supports_ranges(&self) -> bool {
+		matches!(self, Field::Level | Field::Timestamp | Field::Attribute(_))
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+	Eq,
+	Gt,
+	Gte,
+	Lt,
+	Lte,
+}
+
+impl Op {
+	pub fn symbol(&self) -> &'static str {
+		match self {
+			Op::Eq => ":",
+			Op::Gt => ">",
+			Op::Gte => ">=",
+			Op::Lt => "<",
+			Op::Lte => "<=",
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Level(LogLevel),
+	Int(i64),
+	Float(f64),
+	Bool(bool),
+	String(String),
+	/// Pattern with `*` and `?` wildcards.
+	Wildcard(String),
+	/// `field:*`, matches any value.
+	Any,
+}
+
+impl Value {
+	/// Attribute values are typed the same way the agent types them.
+	fn infer(raw: &str) -> Value {
+		if let Ok(i) = raw.parse() {
+			Value::Int(i)
+		} else if let Ok(f) = raw.parse() {
+			Value::Float(f)
+		} else if let Ok(b) = raw.parse() {
+			Value::Bool(b)
+		} else {
+			Value::String(raw.to_string())
+		}
+	}
+}
+
+/// Parse error with the character position it was found at.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message} at position {position}")]
+pub struct QueryError {
+	pub message: String,
+	pub position: usize,
+}
+
+impl From<QueryError> for LogSystemError {
+	fn from(error: QueryError) -> Self {
+		LogSystemError::InvalidQuery(error.to_string())
+	}
+}
+
+pub fn parse(input: &str) -> Result<Expr, QueryError> {
+	let mut parser = Parser { input, pos: 0, depth: 0 };
+	let expr = parser.parse_or()?;
+	parser.skip_ws();
+	match parser.peek() {
+		None => Ok(expr),
+		Some(')') => Err(parser.error(parser.pos, "unexpected ')'")),
+		Some(_) => Err(parser.error(parser.pos, "expected AND, OR or end of query")),
+	}
+}
+
+struct Parser<'a> {
+	input: &'a str,
+	/// Byte offset into `input`.
+	pos: usize,
+	depth: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn parse_or(&mut self) -> Result<Expr, QueryError> {
+		let mut terms = vec![self.parse_and()?];
+		while self.eat_keyword("OR") {
+			terms.push(self.parse_and()?);
+		}
+		Ok(if terms.len() == 1 { terms.remove(0) } else { Expr::Or(terms) })
+	}
+
+	fn parse_and(&mut self) -> Result<Expr, QueryError> {
+		let mut terms = vec![self.parse_unary()?];
+		loop {
+			if self.eat_keyword("AND") {
+				terms.push(self.parse_unary()?);
+				continue;
+			}
+			self.skip_ws();
+			if matches!(self.peek(), None | Some(')')) || self.at_keyword("OR") {
+				break;
+			}
+			terms.push(self.parse_unary()?);
+		}
+		Ok(if terms.len() == 1 { terms.remove(0) } else { Expr::And(terms) })
+	}
+
+	fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+		self.skip_ws();
+		let start = self.pos;
+		let negated = if self.eat_keyword("NOT") {
+			true
+		} else if self.rest().starts_with('-') && self.rest()[1..].starts_with(|c: char| !c.is_whitespace()) {
+			self.pos += 1;
+			true
+		} else {
+			false
+		};
+		if !negated {
+			return self.parse_primary();
+		}
+
+		self.enter(start)?;
+		let expr = self.parse_unary()?;
+		self.depth -= 1;
+		Ok(Expr::Not(Box::new(expr)))
+	}
+
+	fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+		self.skip_ws();
+		let start = self.pos;
+		match self.peek() {
+			None => Err(self.error(start, "expected a term")),
+			Some('(') => {
+				self.enter(start)?;
+				self.pos += 1;
+				let expr = self.parse_or()?;
+				self.skip_ws();
+				if self.peek() != Some(')') {
+					return Err(self.error(start, "unclosed '('"));
+				}
+				self.pos += 1;
+				self.depth -= 1;
+				Ok(expr)
+			}
+			Some(')') => Err(self.error(start, "unexpected ')'")),
+			Some('"') => Ok(Expr::Message(MessageMatch::Phrase(self.quoted()?))),
+			Some(c) => {
+				let name = self.word(|c| "()\":=<>".contains(c));
+				if name.is_empty() {
+					return Err(self.error(start, format!("unexpected '{}'", c)));
+				}
+				if name == "AND" || name == "OR" {
+					return Err(self.error(start, format!("expected a term before {}", name)));
+				}
+				match self.operator() {
+					Some(op) => self.comparison(name, start, op),
+					None => Ok(Expr::Message(MessageMatch::from_word(name))),
+				}
+			}
+		}
+	}
+
+	fn comparison(&mut self, name: &str, start: usize, (op, op_pos): (Op, usize)) -> Result<Expr, QueryError> {
+		self.skip_ws();
+		let value_start = self.pos;
+		let (raw, quoted) = match self.peek() {
+			Some('"') => (self.quoted()?, true),
+			None | Some(')') => {
+				return Err(self.error(value_start, format!("expected a value after '{}'", op.symbol())));
+			}
+			Some(_) => (self.word(|c| "()\"".contains(c)).to_string(), false),
+		};
+
+		if name == "message" {
+			if op != Op::Eq {
+				return Err(self.error(op_pos, format!("field 'message' does not support '{}'", op.symbol())));
+			}
+			let message_match = if quoted { MessageMatch::Phrase(raw) } else { MessageMatch::from_word(&raw) };
+			return Ok(Expr::Message(message_match));
+		}
+
+		let field = Field::parse(name).ok_or_else(|| {
+			let hint = if name.contains('.') { "" } else { ", attributes are written attributes.<key>" };
+			self.error(start, format!("unknown field '{}'{}", name, hint))
+		})?;
+
+		let value = match &field {
+			Field::Level => match raw.parse() {
+				Ok(level) => Value::Level(level),
+				Err(_) => return Err(self.error(value_start, format!("unknown level '{}'", raw))),
+			},
+			_ if quoted => Value::String(raw),
+			_ if raw == "*" => Value::Any,
+			_ if raw.contains(['*', '?']) => Value::Wildcard(raw),
+			Field::Attribute(_) => Value::infer(&raw),
+			Field::TraceId | Field::SpanId => Value::String(raw.to_ascii_lowercase()),
+			_ => Value::String(raw),
+		};
+
+		if op != Op::Eq {
+			if !field.supports_ranges() {
+				return Err(self.error(op_pos, format!("field '{}' does not support '{}'", name, op.symbol())));
+			}
+			if matches!(value, Value::Wildcard(_) | Value::Any | Value::Bool(_)) {
+				return Err(self.error(value_start, format!("'{}' needs a number, date, level or string", op.symbol())));
+			}
+		} else if field == Field::Timestamp {
+			return Err(self.error(op_pos, "field 'timestamp' needs a comparison such as '>='"));
+		}
+
+		Ok(Expr::Compare { field, op, value })
+	}
+
+	/// Reads a comparison operator, if one follows.
+	fn operator(&mut self) -> Option<(Op, usize)> {
+		let before = self.pos;
+		self.skip_ws();
+		let op_pos = self.pos;
+		let rest = self.rest();
+		let (op, len) = if rest.starts_with(">=") {
+			(Op::Gte, 2)
+		} else if rest.starts_with("<=") {
+			(Op::Lte, 2)
+		} else if rest.starts_with('>') {
+			(Op::Gt, 1)
+		} else if rest.starts_with('<') {
+			(Op::Lt, 1)
+		} else if rest.starts_with(':') || rest.starts_with('=') {
+			(Op::Eq, 1)
+		} else {
+			self.pos = before;
+			return None;
+		};
+		self.pos += len;
+		Some((op, op_pos))
+	}
+
+	/// Reads a double-quoted string; `\"` and `\\` are escapes.
+	fn quoted(&mut self) -> Result<String, QueryError> {
+		let start = self.pos;
+		let mut value = String::new();
+		let mut chars = self.rest()[1..].char_indices();
+		while let Some((i, c)) = chars.next() {
+			match c {
+				'"' => {
+					self.pos += i + 2;
+					return Ok(value);
+				}
+				'\\' => match chars.next() {
+					Some((_, escaped)) => value.push(escaped),
+					None => break,
+				},
+				c => value.push(c),
+			}
+		}
+		Err(self.error(start, "unterminated quote"))
+	}
+
+	fn word(&mut self, stop: impl Fn(char) -> bool) -> &'a str {
+		let rest = self.rest();
+		let end = rest.find(|c: char| c.is_whitespace() || stop(c)).unwrap_or(rest.len());
+		self.pos += end;
+		&rest[..end]
+	}
+
+	fn at_keyword(&mut self, keyword: &str) -> bool {
+		self.skip_ws();
+		let rest = self.rest();
+		rest.starts_with(keyword)
+			&& rest[keyword.len()..]
+				.chars()
+				.next()
+				.is_none_or(|c| c.is_whitespace() || c == '(' || c == '"')
+	}
+
+	fn eat_keyword(&mut self, keyword: &str) -> bool {
+		let found = self.at_keyword(keyword);
+		if found {
+			self.pos += keyword.len();
+		}
+		found
+	}
+
+	fn enter(&mut self, start: usize) -> Result<(), QueryError> {
+		self.depth += 1;
+		if self.depth > MAX_DEPTH {
+			return Err(self.error(start, "query is nested too deeply"));
+		}
+		Ok(())
+	}
+
+	fn skip_ws(&mut self) {
+		self.pos = self.input.len() - self.rest().trim_start().len();
+	}
+
+	fn rest(&self) -> &'a str {
+		&self.input[self.pos..]
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.rest().chars().next()
+	}
+
+	fn char_position(&self, byte_pos: usize) -> usize {
+		self.input[..byte_pos].chars().count()
+	}
+
+	fn error(&self, byte_pos: usize, message: impl Into<String>) -> QueryError {
+		QueryError {
+			message: message.into(),
+			position: self.char_position(byte_pos),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn compare(field: Field, op: Op, value: Value) -> Expr {
+		Expr::Compare { field, op, value }
+	}
+
+	#[test]
+	fn test_parse_example_query() {
+		let expr = parse(r#"app:payment-service AND level>=Warn AND attributes.user_id:123 AND "timeout""#).unwrap();
+		assert_eq!(
+			expr,
+			Expr::And(vec![
+				compare(Field::AppName, Op::Eq, Value::String("payment-service".to_string())),
+				compare(Field::Level, Op::Gte, Value::Level(LogLevel::Warn)),
+				compare(Field::Attribute("user_id".to_string()), Op::Eq, Value::Int(123)),
+				Expr::Message(MessageMatch::Phrase("timeout".to_string())),
+			])
+		);
+	}
+
+	#[test]
+	fn test_precedence_grouping_and_negation() {
+		let expr = parse("app:a OR app:b level:error").unwrap();
+		assert_eq!(
+			expr,
+			Expr::Or(vec![
+				compare(Field::AppName, Op::Eq, Value::String("a".to_string())),
+				Expr::And(vec![
+					compare(Field::AppName, Op::Eq, Value::String("b".to_string())),
+					compare(Field::Level, Op::Eq, Value::Level(LogLevel::Error)),
+				]),
+			])
+		);
+
+		let expr = parse("(app:a OR app:b) -resource.environment:dev* NOT conn*").unwrap();
+		assert_eq!(
+			expr,
+			Expr::And(vec![
+				Expr::Or(vec![
+					compare(Field::AppName, Op::Eq, Value::String("a".to_string())),
+					compare(Field::AppName, Op::Eq, Value::String("b".to_string())),
+				]),
+				Expr::Not(Box::new(compare(
+					Field::Resource("environment".to_string()),
+					Op::Eq,
+					Value::Wildcard("dev*".to_string())
+				))),
+				Expr::Not(Box::new(Expr::Message(MessageMatch::Prefix("conn".to_string())))),
+			])
+		);
+	}
+
+	#[test]
+	fn test_values() {
+		assert_eq!(
+			parse("timestamp >= 2024-01-01T00:00:00Z").unwrap(),
+			compare(Field::Timestamp, Op::Gte, Value::String("2024-01-01T00:00:00Z".to_string()))
+		);
+		assert_eq!(
+			parse("attributes.duration_ms>-0.5").unwrap(),
+			compare(Field::Attribute("duration_ms".to_string()), Op::Gt, Value::Float(-0.5))
+		);
+		assert_eq!(
+			parse(r#"attributes.path:"/api/\"v1\"""#).unwrap(),
+			compare(Field::Attribute("path".to_string()), Op::Eq, Value::String("/api/\"v1\"".to_string()))
+		);
+		assert_eq!(
+			parse("trace_id:4BF92F35 attributes.user_id:*").unwrap(),
+			Expr::And(vec![
+				compare(Field::TraceId, Op::Eq, Value::String("4bf92f35".to_string())),
+				compare(Field::Attribute("user_id".to_string()), Op::Eq, Value::Any),
+			])
+		);
+		assert_eq!(parse("message:time*").unwrap(), Expr::Message(MessageMatch::Prefix("time".to_string())));
+		assert_eq!(
+			parse("error.type:TimeoutError error.file:db.rs").unwrap(),
+			Expr::And(vec![
+				compare(Field::ErrorType, Op::Eq, Value::String("TimeoutError".to_string())),
+				compare(Field::ErrorFile, Op::Eq, Value::String("db.rs".to_string())),
+			])
+		);
+		assert_eq!(
+			parse("android and").unwrap(),
+			Expr::And(vec![
+				Expr::Message(MessageMatch::Text("android".to_string())),
+				Expr::Message(MessageMatch::Text("and".to_string())),
+			])
+		);
+	}
+
+	#[test]
+	fn test_error_positions() {
+		let error = |input: &str| parse(input).unwrap_err();
+
+		assert_eq!(error("level:loud").to_string(), "unknown level 'loud' at position 6");
+		assert_eq!(error("app:a (app:b OR app:c").to_string(), "unclosed '(' at position 6");
+		assert_eq!(error("app:a )").position, 6);
+		assert_eq!(error("app:a AND").to_string(), "expected a term at position 9");
+		assert_eq!(error("AND app:a").position, 0);
+		assert_eq!(error("user_id:123").message, "unknown field 'user_id', attributes are written attributes.<key>");
+		assert_eq!(error("app>=x").to_string(), "field 'app' does not support '>=' at position 3");
+		assert_eq!(error("timestamp:now").position, 9);
+		assert_eq!(error("app:").to_string(), "expected a value after ':' at position 4");
+		assert_eq!(error(r#"héllo "open"#).to_string(), "unterminated quote at position 6");
+		assert_eq!(error(&"(".repeat(100)).message, "query is nested too deeply");
+		assert_eq!(
+			LogSystemError::from(error("")),
+			LogSystemError::InvalidQuery("expected a term at position 0".to_string())
+		);
+	}
+}
@@ -0,0 +1,561 @@
+use crate::pii::PiiKind;
+use crate::pseudonym::Pseudonymizer;
+use crate::{AttributeValue, LogEntry, LogSystemError};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, OnceLock};
+
+/// What a matching rule puts in place of the sensitive text.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Replacement {
+	/// Replace the match with a fixed string. `$1`/`$name` capture references are expanded.
+	Fixed(String),
+	/// Replace every character of the match with `*`, keeping its length.
+	Mask,
+	/// Delete the match. A rule matching on attribute key alone drops the attribute.
+	Remove,
+	/// Replace the match with a keyed token that is the same for equal values,
+	/// see [`Pseudonymizer::token`]. Masks instead if the redactor has no key.
+	Pseudonymize,
+	/// Format-preserving pseudonym for card numbers that keeps the last four
+	/// digits, see [`Pseudonymizer::card_token`]. Masks instead if the redactor has no key.
+	PseudonymizeCard,
+}
+
+/// A named redaction rule as it is stored in the config service.
+///
+/// `message_pattern` is applied to the message and to the messages of an
+/// attached error. `attribute_key_pattern` selects attributes by key and
+/// `attribute_value_pattern` selects text inside attribute values (strings
+/// only, including those nested in arrays and objects); when only the key
+/// pattern is set the whole value is replaced. A `detector` finds validated
+/// matches of its kind in messages and in attribute values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionRule {
+	pub name: String,
+	pub message_pattern: Option<String>,
+	pub attribute_key_pattern: Option<String>,
+	pub attribute_value_pattern: Option<String>,
+	pub detector: Option<PiiKind>,
+	pub replacement: Replacement,
+}
+
+/// Per-app redaction settings served by the config service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionConfig {
+	pub app_name: String,
+	#[serde(default)]
+	pub rules: Vec<RedactionRule>,
+	/// Attribute keys that are never redacted, whatever the rules say.
+	#[serde(default)]
+	pub exempt_attributes: Vec<String>,
+	/// Whether the built-in rules run before the app's own rules. An app rule
+	/// with the name of a built-in rule takes its place.
+	#[serde(default = "default_include_defaults")]
+	pub include_defaults: bool,
+}
+
+impl RedactionConfig {
+	/// Whether any of the app's rules replaces matches with pseudonyms.
+	pub fn pseudonymizes(&self) -> bool {
+		self.rules
+			.iter()
+			.any(|rule| matches!(rule.replacement, Replacement::Pseudonymize | Replacement::PseudonymizeCard))
+	}
+}
+
+fn default_include_defaults() -> bool {
+	true
+}
+
+#[derive(Debug, Clone)]
+struct CompiledRule {
+	name: String,
+	message: Option<Regex>,
+	key: Option<Regex>,
+	value: Option<Regex>,
+	detector: Option<PiiKind>,
+	replacement: Replacement,
+}
+
+/// A set of precompiled redaction rules.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+	rules: Vec<CompiledRule>,
+	exempt_attributes: HashSet<String>,
+	pseudonymizer: Option<Arc<Pseudonymizer>>,
+}
+
+impl Redactor {
+	pub fn new(rules: &[RedactionRule]) -> Result<Self, LogSystemError> {
+		let rules = rules
+			.iter()
+			.map(compile_rule)
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Ok(Self {
+			rules,
+			exempt_attributes: HashSet::new(),
+			pseudonymizer: None,
+		})
+	}
+
+	pub fn from_config(config: &RedactionConfig) -> Result<Self, LogSystemError> {
+		let mut own_rules = config.rules.clone();
+		let mut rules = Vec::new();
+		if config.include_defaults {
+			for default in Self::default_rules() {
+				match own_rules.iter().position(|rule| rule.name == default.name) {
+					Some(i) => rules.push(own_rules.remove(i)),
+					None => rules.push(default),
+				}
+			}
+		}
+		rules.extend(own_rules);
+
+		let mut redactor = Self::new(&rules)?;
+		redactor.exempt_attributes = config.exempt_attributes.iter().cloned().collect();
+		Ok(redactor)
+	}
+
+	/// The rules applied when an app has no configuration of its own.
+	///
+	/// Phone numbers and IP addresses are often needed for debugging, so their
+	/// detectors are only used by apps that add a rule for them.
+	pub fn default_rules() -> Vec<RedactionRule> {
+		let message_rule = |name: &str, pattern: &str, replacement: &str| RedactionRule {
+			name: name.to_string(),
+			message_pattern: Some(pattern.to_string()),
+			attribute_key_pattern: None,
+			attribute_value_pattern: None,
+			detector: None,
+			replacement: Replacement::Fixed(replacement.to_string()),
+		};
+		let detector_rule = |kind: PiiKind, replacement: Replacement| RedactionRule {
+			name: kind.as_str().to_string(),
+			message_pattern: None,
+			attribute_key_pattern: None,
+			attribute_value_pattern: None,
+			detector: Some(kind),
+			replacement,
+		};
+
+		vec![
+			detector_rule(PiiKind::PrivateKey, Replacement::Fixed("[private key]".to_string())),
+			detector_rule(PiiKind::Jwt, Replacement::Fixed("[jwt]".to_string())),
+			detector_rule(PiiKind::CloudKey, Replacement::Fixed("[cloud key]".to_string())),
+			detector_rule(PiiKind::Iban, Replacement::Mask),
+			detector_rule(PiiKind::CreditCard, Replacement::Fixed("****-****-****-****".to_string())),
+			detector_rule(PiiKind::NationalId, Replacement::Mask),
+			message_rule("password", r"password[=:]\s*\S+", "password=***"),
+			message_rule("token", r"token[=:]\s*\S+", "token=***"),
+			message_rule(
+				"email",
+				r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Z|a-z]{2,}\b",
+				"***@***.com",
+			),
+			RedactionRule {
+				name: "sensitive_attribute".to_string(),
+				message_pattern: None,
+				attribute_key_pattern: Some("(?i)password|token|secret".to_string()),
+				attribute_value_pattern: None,
+				detector: None,
+				replacement: Replacement::Fixed("***".to_string()),
+			},
+		]
+	}
+
+	/// Key used by the `Pseudonymize` replacements.
+	pub fn with_pseudonymizer(mut self, pseudonymizer: Arc<Pseudonymizer>) -> Self {
+		self.pseudonymizer = Some(pseudonymizer);
+		self
+	}
+
+	/// Shared instance of the default rules, compiled once.
+	pub fn builtin() -> &'static Redactor {
+		static BUILTIN: OnceLock<Redactor> = OnceLock::new();
+		BUILTIN.get_or_init(|| Redactor::new(&Redactor::default_rules()).expect("built-in redaction rules are valid"))
+	}
+
+	/// Redacts `entry` in place and returns the names of the rules that fired.
+	pub fn redact(&self, entry: &mut LogEntry) -> Vec<&str> {
+		let mut fired = Vec::new();
+
+		for rule in &self.rules {
+			let mut hit = self.redact_message(rule, &mut entry.message);
+			if let Some(error) = &mut entry.error {
+				hit |= self.redact_message(rule, &mut error.message);
+				for cause in &mut error.causes {
+					hit |= self.redact_message(rule, &mut cause.message);
+				}
+			}
+
+			if rule.key.is_some() || rule.value.is_some() || rule.detector.is_some() {
+				self.redact_fields(rule, &mut entry.attributes, Some(&self.exempt_attributes), &mut hit);
+			}
+
+			if hit {
+				fired.push(rule.name.as_str());
+			}
+		}
+
+		fired
+	}
+
+	/// Applies the attribute patterns and detector of `rule` to `fields`. Keys
+	/// are matched at every depth, including in objects inside arrays.
+	fn redact_fields(
+		&self,
+		rule: &CompiledRule,
+		fields: &mut HashMap<String, AttributeValue>,
+		exempt: Option<&HashSet<String>>,
+		hit: &mut bool,
+	) {
+		fields.retain(|key, value| {
+			if exempt.is_some_and(|exempt| exempt.contains(key)) {
+				return true;
+			}
+			if let Some(key_pattern) = &rule.key {
+				if !key_pattern.is_match(key) {
+					self.redact_nested_fields(rule, value, hit);
+					return true;
+				}
+			}
+
+			if rule.value.is_some() || rule.detector.is_some() {
+				value.visit_strings_mut(&mut |text| {
+					if let Some(pattern) = &rule.value {
+						if pattern.is_match(text) {
+							*text = self.replace_matches(pattern, text, &rule.replacement);
+							*hit = true;
+						}
+					}
+					if let Some(kind) = rule.detector {
+						if let Some(redacted) = self.replace_detected(kind, text, &rule.replacement) {
+							*text = redacted;
+							*hit = true;
+						}
+					}
+				});
+				return true;
+			}
+
+			*hit = true;
+			match &rule.replacement {
+				Replacement::Fixed(text) => *value = AttributeValue::String(text.clone()),
+				Replacement::Remove => return false,
+				replacement => *value = AttributeValue::String(self.replace(&value.to_string(), replacement)),
+			}
+			true
+		});
+	}
+
+	/// Looks for keys matching `rule` in the objects inside `value`.
+	fn redact_nested_fields(&self, rule: &CompiledRule, value: &mut AttributeValue, hit: &mut bool) {
+		match value {
+			AttributeValue::Object(fields) => self.redact_fields(rule, fields, None, hit),
+			AttributeValue::Array(items) => {
+				for item in items {
+					self.redact_nested_fields(rule, item, hit);
+				}
+			}
+			AttributeValue::Bool(_) | AttributeValue::Int(_) | AttributeValue::Float(_) | AttributeValue::String(_) => {}
+		}
+	}
+
+	/// Applies the message pattern and detector of `rule` to a message.
+	fn redact_message(&self, rule: &CompiledRule, message: &mut String) -> bool {
+		let mut hit = false;
+
+		if let Some(pattern) = &rule.message {
+			if pattern.is_match(message) {
+				*message = self.replace_matches(pattern, message, &rule.replacement);
+				hit = true;
+			}
+		}
+
+		if let Some(kind) = rule.detector {
+			if let Some(redacted) = self.replace_detected(kind, message, &rule.replacement) {
+				*message = redacted;
+				hit = true;
+			}
+		}
+
+		hit
+	}
+
+	fn replace_matches(&self, pattern: &Regex, text: &str, replacement: &Replacement) -> String {
+		match replacement {
+			Replacement::Fixed(with) => pattern.replace_all(text, with.as_str()).to_string(),
+			Replacement::Remove => pattern.replace_all(text, "").to_string(),
+			replacement => pattern
+				.replace_all(text, |caps: &regex::Captures| self.replace(&caps[0], replacement))
+				.to_string(),
+		}
+	}
+
+	fn replace_detected(&self, kind: PiiKind, text: &str, replacement: &Replacement) -> Option<String> {
+		let mut detections = kind.find_iter(text).peekable();
+		detections.peek()?;
+
+		let mut redacted = String::with_capacity(text.len());
+		let mut last = 0;
+		for range in detections {
+			redacted.push_str(&text[last..range.start]);
+			redacted.push_str(&self.replace(&text[range.clone()], replacement));
+			last = range.end;
+		}
+		redacted.push_str(&text[last..]);
+		Some(redacted)
+	}
+
+	/// Replacement text for one whole match.
+	fn replace(&self, matched: &str, replacement: &Replacement) -> String {
+		match (replacement, &self.pseudonymizer) {
+			(Replacement::Fixed(with), _) => with.clone(),
+			(Replacement::Remove, _) => String::new(),
+			(Replacement::Pseudonymize, Some(pseudonymizer)) => pseudonymizer.token(matched),
+			(Replacement::PseudonymizeCard, Some(pseudonymizer)) => pseudonymizer.card_token(matched),
+			(Replacement::Mask | Replacement::Pseudonymize | Replacement::PseudonymizeCard, _) => {
+				"*".repeat(matched.chars().count())
+			}
+		}
+	}
+}
+
+fn compile_rule(rule: &RedactionRule) -> Result<CompiledRule, LogSystemError> {
+	if rule.message_pattern.is_none()
+		&& rule.attribute_key_pattern.is_none()
+		&& rule.attribute_value_pattern.is_none()
+		&& rule.detector.is_none()
+	{
+		return Err(LogSystemError::InvalidConfig(format!(
+			"redaction rule '{}' has no pattern or detector",
+			rule.name
+		)));
+	}
+
+	let compile = |pattern: &Option<String>| {
+		pattern
+			.as_deref()
+			.map(Regex::new)
+			.transpose()
+			.map_err(|e| LogSystemError::InvalidConfig(format!("redaction rule '{}': {}", rule.name, e)))
+	};
+
+	Ok(CompiledRule {
+		name: rule.name.clone(),
+		message: compile(&rule.message_pattern)?,
+		key: compile(&rule.attribute_key_pattern)?,
+		value: compile(&rule.attribute_value_pattern)?,
+		detector: rule.detector,
+		replacement: rule.replacement.clone(),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{ErrorCause, ErrorInfo, LogLevel};
+	use std::collections::HashMap;
+
+	fn entry(message: &str, attributes: &[(&str, AttributeValue)]) -> LogEntry {
+		LogEntry::new(
+			"test-app".to_string(),
+			LogLevel::Info,
+			message.to_string(),
+			attributes
+				.iter()
+				.map(|(k, v)| (k.to_string(), v.clone()))
+				.collect::<HashMap<_, _>>(),
+		)
+	}
+
+	#[test]
+	fn test_custom_rule_reports_fired_names() {
+		let config = RedactionConfig {
+			app_name: "test-app".to_string(),
+			rules: vec![RedactionRule {
+				name: "employee_id".to_string(),
+				message_pattern: Some(r"EMP-\d{6}".to_string()),
+				attribute_key_pattern: None,
+				attribute_value_pattern: None,
+				detector: None,
+				replacement: Replacement::Mask,
+			}],
+			exempt_attributes: Vec::new(),
+			include_defaults: true,
+		};
+		let redactor = Redactor::from_config(&config).unwrap();
+		let mut log = entry("EMP-123456 used password=hunter2", &[]);
+
+		let fired = redactor.redact(&mut log);
+
+		assert_eq!(log.message, "********** used password=***");
+		assert_eq!(fired, vec!["password", "employee_id"]);
+	}
+
+	#[test]
+	fn test_error_messages_are_redacted() {
+		let mut error = ErrorInfo::new("AuthError", "login failed with password=hunter2");
+		error.causes.push(ErrorCause {
+			error_type: "ParseError".to_string(),
+			message: "bad card 4111111111111111".to_string(),
+		});
+		let mut log = entry("login failed", &[]).with_error(error);
+
+		let fired = Redactor::builtin().redact(&mut log);
+
+		let error = log.error.unwrap();
+		assert_eq!(error.message, "login failed with password=***");
+		assert_eq!(error.causes[0].message, "bad card ****-****-****-****");
+		assert_eq!(fired, vec!["credit_card", "password"]);
+	}
+
+	#[test]
+	fn test_exempt_attribute_is_kept() {
+		let config = RedactionConfig {
+			app_name: "test-app".to_string(),
+			rules: Vec::new(),
+			exempt_attributes: vec!["token_type".to_string()],
+			include_defaults: true,
+		};
+		let redactor = Redactor::from_config(&config).unwrap();
+		let mut log = entry("ok", &[("token_type", "bearer".into()), ("api_token", "abc".into())]);
+
+		redactor.redact(&mut log);
+
+		assert_eq!(log.attributes.get("token_type"), Some(&AttributeValue::from("bearer")));
+		assert_eq!(log.attributes.get("api_token"), Some(&AttributeValue::from("***")));
+	}
+
+	#[test]
+	fn test_nested_keys_are_redacted() {
+		let user = AttributeValue::Object(HashMap::from([
+			("name".to_string(), "alice".into()),
+			("password".to_string(), "hunter2".into()),
+		]));
+		let sessions = AttributeValue::Array(vec![AttributeValue::Object(HashMap::from([(
+			"auth_token".to_string(),
+			"abc".into(),
+		)]))]);
+		let mut log = entry("ok", &[("user", user), ("sessions", sessions)]);
+
+		let fired = Redactor::builtin().redact(&mut log);
+
+		let AttributeValue::Object(user) = &log.attributes["user"] else {
+			panic!("user should stay an object");
+		};
+		assert_eq!(user.get("name"), Some(&AttributeValue::from("alice")));
+		assert_eq!(user.get("password"), Some(&AttributeValue::from("***")));
+		let AttributeValue::Array(sessions) = &log.attributes["sessions"] else {
+			panic!("sessions should stay an array");
+		};
+		let AttributeValue::Object(session) = &sessions[0] else {
+			panic!("session should stay an object");
+		};
+		assert_eq!(session.get("auth_token"), Some(&AttributeValue::from("***")));
+		assert!(fired.contains(&"sensitive_attribute"));
+	}
+
+	#[test]
+	fn test_value_pattern_and_remove() {
+		let redactor = Redactor::new(&[
+			RedactionRule {
+				name: "ssn".to_string(),
+				message_pattern: None,
+				attribute_key_pattern: None,
+				attribute_value_pattern: Some(r"\d{3}-\d{2}-\d{4}".to_string()),
+				detector: None,
+				replacement: Replacement::Fixed("[ssn]".to_string()),
+			},
+			RedactionRule {
+				name: "session".to_string(),
+				message_pattern: None,
+				attribute_key_pattern: Some("^session$".to_string()),
+				attribute_value_pattern: None,
+				detector: None,
+				replacement: Replacement::Remove,
+			},
+		])
+		.unwrap();
+		let mut log = entry(
+			"ok",
+			&[
+				("note", "ssn 123-45-6789".into()),
+				("ids", vec!["123-45-6789", "n/a"].into()),
+				("session", "xyz".into()),
+			],
+		);
+
+		let fired = redactor.redact(&mut log);
+
+		assert_eq!(log.attributes.get("note"), Some(&AttributeValue::from("ssn [ssn]")));
+		assert_eq!(log.attributes.get("ids"), Some(&AttributeValue::from(vec!["[ssn]", "n/a"])));
+		assert!(!log.attributes.contains_key("session"));
+		assert_eq!(fired, vec!["ssn", "session"]);
+	}
+
+	#[test]
+	fn test_invalid_rule_is_rejected() {
+		let rule = RedactionRule {
+			name: "broken".to_string(),
+			message_pattern: Some("(unclosed".to_string()),
+			attribute_key_pattern: None,
+			attribute_value_pattern: None,
+			detector: None,
+			replacement: Replacement::Mask,
+		};
+
+		assert!(Redactor::new(&[rule]).is_err());
+	}
+
+	#[test]
+	fn test_pseudonymize_rules() {
+		let config = RedactionConfig {
+			app_name: "test-app".to_string(),
+			rules: vec![
+				RedactionRule {
+					name: "email".to_string(),
+					message_pattern: Some(r"\b[\w.+-]+@[\w-]+\.[\w.]+\b".to_string()),
+					attribute_key_pattern: None,
+					attribute_value_pattern: None,
+					detector: None,
+					replacement: Replacement::Pseudonymize,
+				},
+				RedactionRule {
+					name: "credit_card".to_string(),
+					message_pattern: None,
+					attribute_key_pattern: None,
+					attribute_value_pattern: None,
+					detector: Some(PiiKind::CreditCard),
+					replacement: Replacement::PseudonymizeCard,
+				},
+			],
+			exempt_attributes: Vec::new(),
+			include_defaults: true,
+		};
+		assert!(config.pseudonymizes());
+		let pseudonymizer = Arc::new(Pseudonymizer::new("key"));
+		let redactor = Redactor::from_config(&config).unwrap().with_pseudonymizer(pseudonymizer.clone());
+
+		let mut first = entry("login failed for alice@example.com", &[("card", "4111 1111 1111 1111".into())]);
+		let mut second = entry("alice@example.com locked out", &[]);
+		let fired = redactor.redact(&mut first);
+		redactor.redact(&mut second);
+
+		// The app's rules replaced the built-in ones in place.
+		assert_eq!(fired, vec!["credit_card", "email"]);
+		let token = pseudonymizer.token("alice@example.com");
+		assert_eq!(first.message, format!("login failed for {}", token));
+		assert_eq!(second.message, format!("{} locked out", token));
+		let card = first.attributes["card"].as_str().unwrap();
+		assert!(card.ends_with(" 1111") && card != "4111 1111 1111 1111");
+
+		// Without a key the same rules mask.
+		let mut log = entry("mail alice@example.com", &[]);
+		Redactor::from_config(&config).unwrap().redact(&mut log);
+		assert_eq!(log.message, "mail *****************");
+	}
+}
@@ -0,0 +1,432 @@
+//! Syslog messages: RFC 5424, the older BSD format described by RFC 3164, and
+//! the TCP framing of RFC 6587.
+
+use crate::{AttributeValue, LogEntry, LogLevel, LogSystemError};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Utc};
+use std::collections::HashMap;
+
+/// Longest message accepted in a TCP frame.
+pub const MAX_FRAME_BYTES: usize = 64 * 1024;
+
+/// Digits in the length of the longest octet-counted frame.
+const MAX_LENGTH_DIGITS: usize = MAX_FRAME_BYTES.ilog10() as usize + 1;
+
+/// Assumed for messages without a PRI: user.notice, as RFC 3164 suggests.
+const DEFAULT_PRI: u8 = 13;
+
+/// Longest RFC 3164 tag; longer first words are part of the message.
+const MAX_TAG_LENGTH: usize = 48;
+
+/// Facility names by code, as rsyslog spells them.
+const FACILITIES: [&str; 24] = [
+	"kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp", "ntp",
+	"security", "console", "clock", "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyslogMessage {
+	pub facility: u8,
+	pub severity: u8,
+	pub timestamp: Option<DateTime<Utc>>,
+	pub hostname: Option<String>,
+	pub app_name: Option<String>,
+	pub proc_id: Option<String>,
+	pub msg_id: Option<String>,
+	/// Structured data elements: the SD-ID and its parameters, in the order sent.
+	pub structured_data: Vec<(String, Vec<(String, String)>)>,
+	pub message: String,
+}
+
+impl SyslogMessage {
+	/// Parses an RFC 5424 message, falling back to RFC 3164. Never fails: text
+	/// in neither format becomes the message of a user.notice entry.
+	///
+	/// RFC 3164 timestamps carry no year or zone; they are read as UTC in the
+	/// year that puts them closest before `now`.
+	pub fn parse(text: &str, now: DateTime<Utc>) -> Self {
+		let (pri, rest) = parse_pri(text).unwrap_or((DEFAULT_PRI, text));
+		let mut message = parse_rfc5424(rest).unwrap_or_else(|| parse_rfc3164(rest, now));
+		message.facility = pri >> 3;
+		message.severity = pri & 7;
+		message
+	}
+
+	/// Emergency, alert and critical are `Fatal`; notice and informational are `Info`.
+	pub fn level(&self) -> LogLevel {
+		match self.severity {
+			0..=2 => LogLevel::Fatal,
+			3 => LogLevel::Error,
+			4 => LogLevel::Warn,
+			5 | 6 => LogLevel::Info,
+			_ => LogLevel::Debug,
+		}
+	}
+
+	pub fn facility_name(&self) -> &'static str {
+		FACILITIES.get(self.facility as usize).copied().unwrap_or("unknown")
+	}
+
+	/// The entry for this message, under its app-name or `default_app`.
+	///
+	/// Structured data parameters become `<sd-id>.<name>` attributes; a
+	/// parameter repeated within an element becomes an array. The hostname is
+	/// not part of the entry, it belongs in the batch's [`crate::Resource`].
+	pub fn into_entry(self, default_app: &str) -> LogEntry {
+		let level = self.level();
+		let mut attributes: HashMap<String, AttributeValue> = HashMap::new();
+		attributes.insert("syslog.facility".to_string(), self.facility_name().into());
+		if let Some(proc_id) = self.proc_id {
+			attributes.insert("syslog.procid".to_string(), proc_id.into());
+		}
+		if let Some(msg_id) = self.msg_id {
+			attributes.insert("syslog.msgid".to_string(), msg_id.into());
+		}
+		for (id, params) in self.structured_data {
+			for (name, value) in params {
+				let key = format!("{}.{}", id, name);
+				match attributes.remove(&key) {
+					Some(AttributeValue::Array(mut values)) => {
+						values.push(value.into());
+						attributes.insert(key, AttributeValue::Array(values));
+					}
+					Some(previous) => {
+						attributes.insert(key, AttributeValue::Array(vec![previous, value.into()]));
+					}
+					None => {
+						attributes.insert(key, value.into());
+					}
+				}
+			}
+		}
+
+		let app_name = self.app_name.unwrap_or_else(|| default_app.to_string());
+		let mut entry = LogEntry::new(app_name, level, self.message, attributes);
+		if let Some(timestamp) = self.timestamp {
+			entry.timestamp = timestamp;
+		}
+		entry
+	}
+}
+
+/// The text of a datagram or frame, without trailing line breaks and NULs.
+pub fn decode(bytes: &[u8]) -> String {
+	let text = String::from_utf8_lossy(bytes);
+	text.trim_end_matches(['\n', '\r', '\0']).to_string()
+}
+
+/// Splits a TCP byte stream into messages. A frame is either octet-counted,
+/// `LEN SP MSG`, or ends at a newline; senders may mix the two.
+#[derive(Debug, Default)]
+pub struct Framer {
+	buffer: Vec<u8>,
+}
+
+impl Framer {
+	pub fn push(&mut self, bytes: &[u8]) {
+		self.buffer.extend_from_slice(bytes);
+	}
+
+	/// The next complete frame, or `None` until more bytes arrive. Fails on a
+	/// frame longer than [`MAX_FRAME_BYTES`], after which the stream cannot be
+	/// resynchronised.
+	pub fn next_frame(&mut self) -> Result<Option<String>, LogSystemError> {
+		loop {
+			let start = self.buffer.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(self.buffer.len());
+			self.buffer.drain(..start);
+			if self.buffer.is_empty() {
+				return Ok(None);
+			}
+
+			let digits = self.buffer.iter().take_while(|b| b.is_ascii_digit()).count();
+			if digits > MAX_LENGTH_DIGITS {
+				return Err(frame_too_long());
+			}
+			if digits > 0 && digits == self.buffer.len() {
+				// Could be the length of an octet-counted frame, or a line starting with digits.
+				return Ok(None);
+			}
+			if digits > 0 && self.buffer.get(digits) == Some(&b' ') {
+				let length: usize = std::str::from_utf8(&self.buffer[..digits])
+					.ok()
+					.and_then(|length| length.parse().ok())
+					.unwrap_or(usize::MAX);
+				if length > MAX_FRAME_BYTES {
+					return Err(frame_too_long());
+				}
+				let end = digits + 1 + length;
+				if self.buffer.len() < end {
+					return Ok(None);
+				}
+				let frame = decode(&self.buffer[digits + 1..end]);
+				self.buffer.drain(..end);
+				return Ok(Some(frame));
+			}
+
+			let Some(end) = self.buffer.iter().position(|&b| b == b'\n') else {
+				if self.buffer.len() > MAX_FRAME_BYTES {
+					return Err(frame_too_long());
+				}
+				return Ok(None);
+			};
+			let frame = decode(&self.buffer[..end]);
+			self.buffer.drain(..=end);
+			if !frame.is_empty() {
+				return Ok(Some(frame));
+			}
+		}
+	}
+
+	/// What is left once the sender closes the connection: a last message
+	/// without its trailing newline.
+	pub fn finish(self) -> Option<String> {
+		let frame = decode(&self.buffer);
+		let frame = frame.trim_start();
+		(!frame.is_empty()).then(|| frame.to_string())
+	}
+}
+
+fn frame_too_long() -> LogSystemError {
+	LogSystemError::InvalidPayload(format!("syslog frame longer than {} bytes", MAX_FRAME_BYTES))
+}
+
+/// `<PRI>` with PRI at most 191, and the rest of the message.
+fn parse_pri(text: &str) -> Option<(u8, &str)> {
+	let rest = text.strip_prefix('<')?;
+	let (pri, rest) = rest.split_once('>')?;
+	if pri.is_empty() || pri.len() > 3 || !pri.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	let pri: u8 = pri.parse().ok().filter(|pri| *pri <= 191)?;
+	Some((pri, rest))
+}
+
+/// `VERSION SP TIMESTAMP SP HOSTNAME SP APP-NAME SP PROCID SP MSGID SP SD [SP MSG]`,
+/// with `-` for absent fields.
+fn parse_rfc5424(text: &str) -> Option<SyslogMessage> {
+	let rest = text.strip_prefix("1 ")?;
+	let (timestamp, rest) = rest.split_once(' ')?;
+	let (hostname, rest) = rest.split_once(' ')?;
+	let (app_name, rest) = rest.split_once(' ')?;
+	let (proc_id, rest) = rest.split_once(' ')?;
+	let (msg_id, rest) = rest.split_once(' ')?;
+
+	let timestamp = match timestamp {
+		"-" => None,
+		timestamp => Some(DateTime::parse_from_rfc3339(timestamp).ok()?.with_timezone(&Utc)),
+	};
+	let (structured_data, rest) = match rest.strip_prefix('-') {
+		Some(rest) => (Vec::new(), rest),
+		None => parse_structured_data(rest)?,
+	};
+	let message = match rest {
+		"" => "",
+		rest => rest.strip_prefix(' ')?,
+	};
+
+	Some(SyslogMessage {
+		timestamp,
+		hostname: nil(hostname),
+		app_name: nil(app_name),
+		proc_id: nil(proc_id),
+		msg_id: nil(msg_id),
+		structured_data,
+		message: message.strip_prefix('\u{feff}').unwrap_or(message).to_string(),
+		..SyslogMessage::default()
+	})
+}
+
+fn nil(field: &str) -> Option<String> {
+	(field != "-").then(|| field.to_string())
+}
+
+/// One or more `[SD-ID *(SP PARAM-NAME="PARAM-VALUE")]` elements, where `"`,
+/// `\` and `]` in values are escaped with a backslash.
+#[allow(clippy::type_complexity)]
+fn parse_structured_data(text: &str) -> Option<(Vec<(String, Vec<(String, String)>)>, &str)> {
+	let mut elements = Vec::new();
+	let mut rest = text;
+	while let Some(element) = rest.strip_prefix('[') {
+		let id_end = element.find([' ', ']'])?;
+		let id = &element[..id_end];
+		if id.is_empty() {
+			return None;
+		}
+		rest = &element[id_end..];
+
+		let mut params = Vec::new();
+		while let Some(param) = rest.strip_prefix(' ') {
+			let (name, value) = param.split_once("=\"")?;
+			let mut unescaped = String::new();
+			let mut chars = value.char_indices();
+			let end = loop {
+				match chars.next()? {
+					(i, '"') => break i,
+					(_, '\\') => match chars.next()? {
+						(_, c @ ('"' | '\\' | ']')) => unescaped.push(c),
+						(_, c) => {
+							unescaped.push('\\');
+							unescaped.push(c);
+						}
+					},
+					(_, c) => unescaped.push(c),
+				}
+			};
+			params.push((name.to_string(), unescaped));
+			rest = &value[end + 1..];
+		}
+		rest = rest.strip_prefix(']')?;
+		elements.push((id.to_string(), params));
+	}
+	(!elements.is_empty()).then_some((elements, rest))
+}
+
+/// `TIMESTAMP SP HOSTNAME SP TAG[PID]: MSG`, where devices commonly leave out
+/// the hostname, or everything but the message.
+fn parse_rfc3164(text: &str, now: DateTime<Utc>) -> SyslogMessage {
+	let mut message = SyslogMessage::default();
+	let mut rest = text;
+	if let Some((timestamp, after)) = parse_bsd_timestamp(text, now) {
+		message.timestamp = Some(timestamp);
+		rest = after;
+		if let Some((first, after)) = rest.split_once(' ') {
+			if !is_tag(first) {
+				message.hostname = Some(first.to_string());
+				rest = after;
+			}
+		}
+	}
+
+	if let Some((first, after)) = rest.split_once(' ') {
+		if is_tag(first) {
+			let tag = first.trim_end_matches(':');
+			match tag.split_once('[') {
+				Some((app_name, proc_id)) => {
+					message.app_name = Some(app_name.to_string());
+					message.proc_id = Some(proc_id.trim_end_matches(']').to_string());
+				}
+				None => message.app_name = Some(tag.to_string()),
+			}
+			rest = after;
+		}
+	}
+	message.message = rest.to_string();
+	message
+}
+
+/// `name:` or `name[pid]:`.
+fn is_tag(word: &str) -> bool {
+	let Some(tag) = word.strip_suffix(':') else {
+		return false;
+	};
+	let name = tag.split_once('[').map_or(tag, |(name, proc_id)| {
+		if proc_id.ends_with(']') {
+			name
+		} else {
+			""
+		}
+	});
+	!name.is_empty()
+		&& name.len() <= MAX_TAG_LENGTH
+		&& name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'/'))
+}
+
+/// `Mmm dd hh:mm:ss`, the day padded with a space, or an RFC 3339 timestamp
+/// as sent by rsyslog's high-precision template.
+fn parse_bsd_timestamp(text: &str, now: DateTime<Utc>) -> Option<(DateTime<Utc>, &str)> {
+	let (first, rest) = text.split_once(' ')?;
+	if let Ok(timestamp) = DateTime::parse_from_rfc3339(first) {
+		return Some((timestamp.with_timezone(&Utc), rest));
+	}
+
+	let timestamp = text.get(..15)?;
+	let rest = text.get(15..)?;
+	let rest = rest.strip_prefix(' ').unwrap_or(rest);
+	let parse = |year: i32| NaiveDateTime::parse_from_str(&format!("{} {}", year, timestamp), "%Y %b %e %H:%M:%S");
+	// A message from late December arriving in early January is from last year.
+	let timestamp = match parse(now.year()) {
+		Ok(timestamp) if timestamp.and_utc() <= now + Duration::days(1) => timestamp,
+		_ => parse(now.year() - 1).ok()?,
+	};
+	Some((timestamp.and_utc(), rest))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	fn now() -> DateTime<Utc> {
+		Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+	}
+
+	#[test]
+	fn test_parse_rfc5424() {
+		let text = r#"<165>1 2024-03-01T11:59:00.003Z mymachine.example.com evntslog 1234 ID47 [exampleSDID@32473 iut="3" eventSource="Appl\"ica\]tion"][meta tag="a" tag="b"] BOMAn application event"#;
+		let message = SyslogMessage::parse(&text.replace("BOM", "\u{feff}"), now());
+
+		assert_eq!(message.facility, 20);
+		assert_eq!(message.level(), LogLevel::Info);
+		assert_eq!(message.hostname.as_deref(), Some("mymachine.example.com"));
+		assert_eq!(message.message, "An application event");
+		assert_eq!(message.timestamp, Some(Utc.with_ymd_and_hms(2024, 3, 1, 11, 59, 0).unwrap() + Duration::milliseconds(3)));
+
+		let entry = message.into_entry("syslog");
+		assert_eq!(entry.app_name, "evntslog");
+		assert_eq!(entry.attributes["syslog.facility"], AttributeValue::from("local4"));
+		assert_eq!(entry.attributes["syslog.procid"], AttributeValue::from("1234"));
+		assert_eq!(entry.attributes["syslog.msgid"], AttributeValue::from("ID47"));
+		assert_eq!(entry.attributes["exampleSDID@32473.iut"], AttributeValue::from("3"));
+		assert_eq!(entry.attributes["exampleSDID@32473.eventSource"], AttributeValue::from("Appl\"ica]tion"));
+		assert_eq!(entry.attributes["meta.tag"], AttributeValue::from(vec!["a", "b"]));
+
+		let message = SyslogMessage::parse("<11>1 - - - - - -", now());
+		assert_eq!(message.level(), LogLevel::Error);
+		assert_eq!(message.app_name, None);
+		assert_eq!(message.message, "");
+	}
+
+	#[test]
+	fn test_parse_rfc3164() {
+		let message = SyslogMessage::parse("<34>Oct 11 22:14:15 mymachine su: 'su root' failed", now());
+		assert_eq!(message.level(), LogLevel::Fatal);
+		assert_eq!(message.facility_name(), "auth");
+		assert_eq!(message.timestamp, Some(Utc.with_ymd_and_hms(2023, 10, 11, 22, 14, 15).unwrap()));
+		assert_eq!(message.hostname.as_deref(), Some("mymachine"));
+		assert_eq!(message.app_name.as_deref(), Some("su"));
+		assert_eq!(message.message, "'su root' failed");
+
+		let message = SyslogMessage::parse("<30>Feb  5 08:00:01 sshd[812]: Accepted publickey", now());
+		assert_eq!(message.timestamp, Some(Utc.with_ymd_and_hms(2024, 2, 5, 8, 0, 1).unwrap()));
+		assert_eq!(message.hostname, None);
+		assert_eq!(message.app_name.as_deref(), Some("sshd"));
+		assert_eq!(message.proc_id.as_deref(), Some("812"));
+
+		let message = SyslogMessage::parse("no header at all: here", now());
+		assert_eq!((message.facility, message.severity), (1, 5));
+		assert_eq!(message.app_name, None);
+		assert_eq!(message.message, "no header at all: here");
+		assert_eq!(message.into_entry("syslog").app_name, "syslog");
+	}
+
+	#[test]
+	fn test_framer_mixes_octet_counting_and_newlines() {
+		let mut framer = Framer::default();
+		framer.push(b"18 <13>1 - - - - - -");
+		assert_eq!(framer.next_frame().unwrap(), None);
+		framer.push(b"x <13>first line\r\n<13>second");
+		assert_eq!(framer.next_frame().unwrap().as_deref(), Some("<13>1 - - - - - -x"));
+		assert_eq!(framer.next_frame().unwrap().as_deref(), Some("<13>first line"));
+		assert_eq!(framer.next_frame().unwrap(), None);
+		assert_eq!(framer.finish().as_deref(), Some("<13>second"));
+
+		let mut framer = Framer::default();
+		framer.push(b"99999999 <13>");
+		assert!(framer.next_frame().is_err());
+
+		let mut framer = Framer::default();
+		framer.push(b"12345");
+		assert_eq!(framer.next_frame().unwrap(), None);
+		framer.push(b"67");
+		assert!(framer.next_frame().is_err());
+	}
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+/// W3C trace context, as carried in a `traceparent` header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+	/// 32 lowercase hex characters.
+	pub trace_id: String,
+	/// 16 lowercase hex characters.
+	pub span_id: String,
+	pub sampled: bool,
+}
+
+impl TraceContext {
+	/// Parses a `traceparent` value such as `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+	///
+	/// Returns `None` for malformed values, the invalid `ff` version and all-zero ids,
+	/// as the spec requires. Versions above `00` are accepted if their first four
+	/// fields follow the version `00` layout.
+	pub fn from_traceparent(value: &str) -> Option<Self> {
+		let mut parts = value.trim().split('-');
+		let version = parts.next()?;
+		let trace_id = parts.next()?;
+		let span_id = parts.next()?;
+		let flags = parts.next()?;
+
+		if !is_lower_hex(version, 2) || version == "ff" {
+			return None;
+		}
+		if version == "00" && parts.next().is_some() {
+			return None;
+		}
+		if !is_lower_hex(trace_id, 32) || !is_lower_hex(span_id, 16) || !is_lower_hex(flags, 2) {
+			return None;
+		}
+		if trace_id.bytes().all(|b| b == b'0') || span_id.bytes().all(|b| b == b'0') {
+			return None;
+		}
+
+		let flags = u8::from_str_radix(flags, 16).ok()?;
+		Some(Self {
+			trace_id: trace_id.to_string(),
+			span_id: span_id.to_string(),
+			sampled: flags & 0x01 == 0x01,
+		})
+	}
+
+	pub fn to_traceparent(&self) -> String {
+		format!(
+			"00-{}-{}-{}",
+			self.trace_id,
+			self.span_id,
+			if self.sampled { "01" } else { "00" }
+		)
+	}
+}
+
+fn is_lower_hex(value: &str, len: usize) -> bool {
+	value.len() == len && value.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_traceparent() {
+		let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+		let ctx = TraceContext::from_traceparent(header).unwrap();
+
+		assert_eq!(ctx.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+		assert_eq!(ctx.span_id, "00f067aa0ba902b7");
+		assert!(ctx.sampled);
+		assert_eq!(ctx.to_traceparent(), header);
+	}
+
+	#[test]
+	fn test_reject_invalid_traceparent() {
+		let invalid = [
+			"",
+			"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+			"ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+			"00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+			"00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+			"00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+			"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+		];
+
+		for value in invalid {
+			assert!(TraceContext::from_traceparent(value).is_none(), "accepted {:?}", value);
+		}
+	}
+
+	#[test]
+	fn test_future_version_is_accepted() {
+		let ctx = TraceContext::from_traceparent("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-future")
+			.unwrap();
+		assert!(!ctx.sampled);
+	}
+}
@@ -0,0 +1,135 @@
+//! Versioned `LogBatch` wire format.
+//!
+//! Version 1 is the original unversioned payload: string-only attributes, no
+//! trace context or resource. Version 2 adds typed attributes, `trace_id`/`span_id`,
+//! the batch `resource` and the Trace/Fatal levels, and per entry the attached
+//! `error` and the `truncated` flag set by ingestion. Payloads without a
+//! `schema_version` field are treated as version 1.
+//!
+//! Every version can be carried as JSON or MessagePack; the encoding is chosen
+//! by `Content-Type`. MessagePack uses named fields so that optional fields and
+//! version probing work the same way as in JSON.
+
+use crate::{LogBatch, LogEntry, LogLevel, LogSystemError};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+pub const MIN_SUPPORTED_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+	#[default]
+	Json,
+	MessagePack,
+}
+
+impl WireFormat {
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			WireFormat::Json => "application/json",
+			WireFormat::MessagePack => "application/msgpack",
+		}
+	}
+
+	/// Maps a `Content-Type` header value (parameters ignored) to a format.
+	pub fn from_content_type(value: &str) -> Option<Self> {
+		let mime = value.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+		match mime.as_str() {
+			"application/json" => Some(WireFormat::Json),
+			"application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+				Some(WireFormat::MessagePack)
+			}
+			_ => None,
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct VersionProbe {
+	schema_version: Option<u32>,
+}
+
+/// Decodes a batch of any supported version into the current model.
+pub fn decode_batch(bytes: &[u8], format: WireFormat) -> Result<LogBatch, LogSystemError> {
+	let probe: VersionProbe = from_bytes(bytes, format)?;
+
+	let mut batch = match probe.schema_version.unwrap_or(1) {
+		1 => from_bytes::<v1::LogBatch>(bytes, format).map(LogBatch::from)?,
+		2 => from_bytes::<LogBatch>(bytes, format)?,
+		version => return Err(LogSystemError::UnsupportedSchemaVersion(version)),
+	};
+
+	batch.schema_version = CURRENT_SCHEMA_VERSION;
+	Ok(batch)
+}
+
+/// Encodes a batch in the current version.
+pub fn encode_batch(batch: &LogBatch, format: WireFormat) -> Result<Vec<u8>, LogSystemError> {
+	debug_assert_eq!(batch.schema_version, CURRENT_SCHEMA_VERSION);
+	match format {
+		WireFormat::Json => serde_json::to_vec(batch).map_err(|e| LogSystemError::InvalidPayload(e.to_string())),
+		WireFormat::MessagePack => {
+			rmp_serde::to_vec_named(batch).map_err(|e| LogSystemError::InvalidPayload(e.to_string()))
+		}
+	}
+}
+
+fn from_bytes<T: DeserializeOwned>(bytes: &[u8], format: WireFormat) -> Result<T, LogSystemError> {
+	match format {
+		WireFormat::Json => serde_json::from_slice(bytes).map_err(|e| LogSystemError::InvalidPayload(e.to_string())),
+		WireFormat::MessagePack => {
+			rmp_serde::from_slice(bytes).map_err(|e| LogSystemError::InvalidPayload(e.to_string()))
+		}
+	}
+}
+
+mod v1 {
+	use super::*;
+
+	#[derive(Deserialize)]
+	pub(super) struct LogBatch {
+		pub logs: Vec<LogEntry>,
+		pub batch_id: String,
+	}
+
+	#[derive(Deserialize)]
+	pub(super) struct LogEntry {
+		pub id: String,
+		pub app_name: String,
+		pub level: LogLevel,
+		pub timestamp: DateTime<Utc>,
+		pub message: String,
+		pub attributes: HashMap<String, String>,
+	}
+}
+
+impl From<v1::LogBatch> for LogBatch {
+	fn from(batch: v1::LogBatch) -> Self {
+		LogBatch {
+			logs: batch.logs.into_iter().map(LogEntry::from).collect(),
+			batch_id: batch.batch_id,
+			resource: None,
+			schema_version: CURRENT_SCHEMA_VERSION,
+		}
+	}
+}
+
+impl From<v1::LogEntry> for LogEntry {
+	fn from(entry: v1::LogEntry) -> Self {
+		LogEntry {
+			id: entry.id,
+			app_name: entry.app_name,
+			level: entry.level,
+			timestamp: entry.timestamp,
+			message: entry.message,
+			attributes: entry.attributes.into_iter().map(|(k, v)| (k, v.into())).collect(),
+			trace_id: None,
+			span_id: None,
+			truncated: false,
+			error: None,
+		}
+	}
+}
@@ -0,0 +1,59 @@
+//! Known true and false positives for the PII detectors. A detector change that
+//! moves a case from one list to the other should be deliberate.
+
+use common::pii::{detect, PiiKind};
+use serde::Deserialize;
+
+const CORPUS: &str = include_str!("fixtures/pii_corpus.json");
+
+#[derive(Deserialize)]
+struct Corpus {
+	true_positives: Vec<TruePositive>,
+	false_positives: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct TruePositive {
+	kind: PiiKind,
+	text: String,
+	#[serde(rename = "match")]
+	matched: String,
+}
+
+fn corpus() -> Corpus {
+	serde_json::from_str(CORPUS).unwrap()
+}
+
+#[test]
+fn test_true_positives() {
+	for case in corpus().true_positives {
+		let found: Vec<(PiiKind, &str)> = detect(&case.text)
+			.into_iter()
+			.map(|detection| (detection.kind, &case.text[detection.range]))
+			.collect();
+		assert_eq!(found, vec![(case.kind, case.matched.as_str())], "in {:?}", case.text);
+	}
+}
+
+#[test]
+fn test_false_positives() {
+	for text in corpus().false_positives {
+		let found: Vec<(PiiKind, &str)> = detect(&text)
+			.into_iter()
+			.map(|detection| (detection.kind, &text[detection.range]))
+			.collect();
+		assert!(found.is_empty(), "{:?} detected in {:?}", found, text);
+	}
+}
+
+#[test]
+fn test_find_iter_matches_detect() {
+	for case in corpus().true_positives {
+		let ranges: Vec<&str> = case.kind.find_iter(&case.text).map(|range| &case.text[range]).collect();
+		assert_eq!(ranges, vec![case.matched.as_str()], "in {:?}", case.text);
+
+		for other in PiiKind::ALL.into_iter().filter(|kind| *kind != case.kind) {
+			assert_eq!(other.find_iter(&case.text).count(), 0, "{:?} in {:?}", other, case.text);
+		}
+	}
+}
@@ -0,0 +1,139 @@
+//! Golden payloads for every supported `LogBatch` wire version. These fixtures
+//! are frozen: a change that breaks one of them breaks deployed agents.
+
+use common::wire::{decode_batch, encode_batch, WireFormat, CURRENT_SCHEMA_VERSION};
+use common::{AttributeValue, LogLevel, LogSystemError};
+
+const BATCH_V1: &str = include_str!("fixtures/batch_v1.json");
+const BATCH_V2: &str = include_str!("fixtures/batch_v2.json");
+const BATCH_V2_ERROR: &str = include_str!("fixtures/batch_v2_error.json");
+const BATCH_UNSUPPORTED: &str = include_str!("fixtures/batch_unsupported.json");
+
+#[test]
+fn test_decode_v1() {
+	let batch = decode_batch(BATCH_V1.as_bytes(), WireFormat::Json).unwrap();
+
+	assert_eq!(batch.schema_version, CURRENT_SCHEMA_VERSION);
+	assert_eq!(batch.batch_id, "c3d2a1b0-1111-4222-8333-444455556666");
+	assert!(batch.resource.is_none());
+	assert_eq!(batch.logs.len(), 2);
+
+	let first = &batch.logs[0];
+	assert_eq!(first.level, LogLevel::Warn);
+	assert_eq!(first.attributes["user_id"], AttributeValue::from("123"));
+	assert_eq!(first.attributes["attempt"], AttributeValue::from("2"));
+	assert!(first.trace_id.is_none());
+	assert_eq!(batch.logs[1].timestamp.timestamp_millis(), 1_709_294_401_250);
+}
+
+#[test]
+fn test_decode_v2() {
+	let batch = decode_batch(BATCH_V2.as_bytes(), WireFormat::Json).unwrap();
+
+	assert_eq!(batch.logs.len(), 2);
+	let first = &batch.logs[0];
+	assert_eq!(first.level, LogLevel::Fatal);
+	assert_eq!(first.attributes["duration_ms"], AttributeValue::Int(812));
+	assert_eq!(first.attributes["cached"], AttributeValue::Bool(false));
+	assert_eq!(first.trace_id.as_deref(), Some("4bf92f3577b34da6a3ce929d0e0e4736"));
+	assert_eq!(batch.logs[1].level, LogLevel::Trace);
+
+	let resource = batch.resource.unwrap();
+	assert_eq!(resource.host_name.as_deref(), Some("web-1"));
+	assert_eq!(resource.process_id, Some(4242));
+	assert_eq!(resource.attributes["region"], "eu-west-1");
+}
+
+#[test]
+fn test_decode_v2_error() {
+	let batch = decode_batch(BATCH_V2_ERROR.as_bytes(), WireFormat::Json).unwrap();
+
+	let first = &batch.logs[0];
+	assert!(first.truncated);
+	let error = first.error.as_ref().unwrap();
+	assert_eq!(error.error_type, "ProcessorError");
+	assert_eq!(error.top_frame().unwrap().file_name(), Some("processor.rs"));
+	assert_eq!(error.top_frame().unwrap().line, Some(88));
+	assert_eq!(error.causes[0].message, "connection reset by peer");
+	assert!(!batch.logs[1].truncated);
+	assert!(batch.logs[1].error.is_none());
+}
+
+#[test]
+fn test_v2_round_trip_matches_golden() {
+	for fixture in [BATCH_V2, BATCH_V2_ERROR] {
+		let batch = decode_batch(fixture.as_bytes(), WireFormat::Json).unwrap();
+		let encoded: serde_json::Value = serde_json::from_slice(&encode_batch(&batch, WireFormat::Json).unwrap()).unwrap();
+		let golden: serde_json::Value = serde_json::from_str(fixture).unwrap();
+
+		assert_eq!(encoded, golden);
+	}
+}
+
+#[test]
+fn test_v1_reencodes_as_current_version() {
+	let batch = decode_batch(BATCH_V1.as_bytes(), WireFormat::Json).unwrap();
+	let encoded = encode_batch(&batch, WireFormat::Json).unwrap();
+	let reparsed = decode_batch(&encoded, WireFormat::Json).unwrap();
+
+	let encoded: serde_json::Value = serde_json::from_slice(&encoded).unwrap();
+	assert_eq!(encoded["schema_version"], CURRENT_SCHEMA_VERSION);
+	assert_eq!(reparsed.logs[0].attributes, batch.logs[0].attributes);
+}
+
+#[test]
+fn test_unsupported_version_is_rejected() {
+	match decode_batch(BATCH_UNSUPPORTED.as_bytes(), WireFormat::Json) {
+		Err(LogSystemError::UnsupportedSchemaVersion(99)) => {}
+		other => panic!("expected unsupported version error, got {:?}", other),
+	}
+}
+
+#[test]
+fn test_malformed_payload_is_rejected() {
+	assert!(matches!(
+		decode_batch(b"{\"logs\": [}", WireFormat::Json),
+		Err(LogSystemError::InvalidPayload(_))
+	));
+	assert!(matches!(
+		decode_batch(b"{\"schema_version\": 1, \"logs\": [{\"id\": 1}], \"batch_id\": \"b\"}", WireFormat::Json),
+		Err(LogSystemError::InvalidPayload(_))
+	));
+}
+
+#[test]
+fn test_message_pack_round_trip() {
+	for golden in [BATCH_V1, BATCH_V2] {
+		let batch = decode_batch(golden.as_bytes(), WireFormat::Json).unwrap();
+		let packed = encode_batch(&batch, WireFormat::MessagePack).unwrap();
+		let unpacked = decode_batch(&packed, WireFormat::MessagePack).unwrap();
+
+		assert_eq!(
+			serde_json::to_value(&unpacked).unwrap(),
+			serde_json::to_value(&batch).unwrap()
+		);
+	}
+}
+
+#[test]
+fn test_message_pack_version_probe() {
+	let mut batch = decode_batch(BATCH_V2.as_bytes(), WireFormat::Json).unwrap();
+	batch.schema_version = 99;
+	let packed = rmp_serde::to_vec_named(&batch).unwrap();
+
+	assert!(matches!(
+		decode_batch(&packed, WireFormat::MessagePack),
+		Err(LogSystemError::UnsupportedSchemaVersion(99))
+	));
+}
+
+#[test]
+fn test_content_type_mapping() {
+	assert_eq!(WireFormat::from_content_type("application/json; charset=utf-8"), Some(WireFormat::Json));
+	assert_eq!(WireFormat::from_content_type("application/x-msgpack"), Some(WireFormat::MessagePack));
+	assert_eq!(
+		WireFormat::from_content_type(WireFormat::MessagePack.content_type()),
+		Some(WireFormat::MessagePack)
+	);
+	assert_eq!(WireFormat::from_content_type("text/plain"), None);
+}
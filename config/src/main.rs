@@ -1,12 +1,14 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::{get, post}, Json, Router};
-use common::QuotaConfig;
+use axum::{extract::{rejection::JsonRejection, State}, http::StatusCode, response::IntoResponse, routing::{get, post}, Json, Router};
+use common::{LogSystemError, QuotaConfig, RedactionConfig, Redactor, SizeLimits};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use tracing::info;
+use tracing::{info, warn};
 
 struct ConfigStore {
     quotas: Arc<RwLock<HashMap<String, QuotaConfig>>>,
+    redaction: Arc<RwLock<HashMap<String, RedactionConfig>>>,
+    limits: Arc<RwLock<HashMap<String, SizeLimits>>>,
 }
 
 impl ConfigStore {
@@ -30,6 +32,8 @@ impl ConfigStore {
 
         Self {
             quotas: Arc::new(RwLock::new(quotas)),
+            redaction: Arc::new(RwLock::new(HashMap::new())),
+            limits: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -42,6 +46,29 @@ impl ConfigStore {
         info!("Updating quota for {}: {} logs/sec", config.app_name, config.logs_per_second);
         quotas.insert(config.app_name.clone(), config);
     }
+
+    async fn get_redaction(&self) -> Vec<RedactionConfig> {
+        self.redaction.read().await.values().cloned().collect()
+    }
+
+    async fn update_redaction(&self, config: RedactionConfig) {
+        let mut redaction = self.redaction.write().await;
+        info!("Updating redaction rules for {}: {} rules", config.app_name, config.rules.len());
+        redaction.insert(config.app_name.clone(), config);
+    }
+
+    async fn get_limits(&self) -> Vec<SizeLimits> {
+        self.limits.read().await.values().cloned().collect()
+    }
+
+    async fn update_limits(&self, config: SizeLimits) {
+        let mut limits = self.limits.write().await;
+        info!(
+            "Updating size limits for {}: message {} bytes, {} attributes",
+            config.app_name, config.message_bytes.max, config.attribute_count.max
+        );
+        limits.insert(config.app_name.clone(), config);
+    }
 }
 
 #[tokio::main]
@@ -53,6 +80,10 @@ async fn main() {
     let app = Router::new()
         .route("/quotas", get(get_quotas))
         .route("/quotas", post(update_quota))
+        .route("/redaction", get(get_redaction))
+        .route("/redaction", post(update_redaction))
+        .route("/limits", get(get_limits))
+        .route("/limits", post(update_limits))
         .with_state(store);
 
     info!("Config service starting on :8003");
@@ -67,8 +98,42 @@ async fn get_quotas(State(store): State<Arc<ConfigStore>>) -> impl IntoResponse
 
 async fn update_quota(
     State(store): State<Arc<ConfigStore>>,
-    Json(config): Json<QuotaConfig>,
-) -> impl IntoResponse {
+    payload: Result<Json<QuotaConfig>, JsonRejection>,
+) -> Result<StatusCode, LogSystemError> {
+    let Json(config) = payload?;
     store.update_quota(config).await;
-    StatusCode::OK
-}
\ No newline at end of file
+    Ok(StatusCode::OK)
+}
+
+async fn get_redaction(State(store): State<Arc<ConfigStore>>) -> impl IntoResponse {
+    let configs = store.get_redaction().await;
+    (StatusCode::OK, Json(configs))
+}
+
+async fn update_redaction(
+    State(store): State<Arc<ConfigStore>>,
+    payload: Result<Json<RedactionConfig>, JsonRejection>,
+) -> Result<StatusCode, LogSystemError> {
+    let Json(config) = payload?;
+    if let Err(e) = Redactor::from_config(&config) {
+        warn!("Rejected redaction rules for {}: {}", config.app_name, e);
+        return Err(e);
+    }
+
+    store.update_redaction(config).await;
+    Ok(StatusCode::OK)
+}
+
+async fn get_limits(State(store): State<Arc<ConfigStore>>) -> impl IntoResponse {
+    let limits = store.get_limits().await;
+    (StatusCode::OK, Json(limits))
+}
+
+async fn update_limits(
+    State(store): State<Arc<ConfigStore>>,
+    payload: Result<Json<SizeLimits>, JsonRejection>,
+) -> Result<StatusCode, LogSystemError> {
+    let Json(config) = payload?;
+    store.update_limits(config).await;
+    Ok(StatusCode::OK)
+}
@@ -1,18 +1,30 @@
 use axum::{
 	extract::State,
-	http::StatusCode,
-	response::IntoResponse,
+	http::{
+		header::{CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER},
+		HeaderMap, HeaderName,
+	},
+	response::{IntoResponse, Response},
 	routing::post,
 	Json, Router,
 };
-use common::{LogBatch, LogSystemError, QuotaConfig};
+use common::wire::{self, WireFormat};
+use common::{
+	LimitOutcome, LogBatch, LogSystemError, Pseudonymizer, QuotaConfig, RedactionConfig, Redactor, SizeLimits,
+	RATE_LIMIT_LIMIT_HEADER, RATE_LIMIT_REMAINING_HEADER,
+};
 use flate2::read::GzDecoder;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
 use tower_http::trace::TraceLayer;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+mod pseudonyms;
+mod syslog;
 
 struct RateLimiter {
 	quotas: Arc<RwLock<HashMap<String, QuotaConfig>>>,
@@ -27,7 +39,7 @@ impl RateLimiter {
 			}
 	}
 
-	async fn check_rate(&self, app_name: &str, count: u64) -> Result<(), LogSystemError> {
+	async fn check_rate(&self, app_name: &str, count: u64) -> Result<(), Rejection> {
 			let quotas = self.quotas.read().await;
 			let limit = quotas
 					.get(app_name)
@@ -45,11 +57,23 @@ impl RateLimiter {
 			let elapsed = now.duration_since(last_update).as_secs_f64();
 			let new_tokens = (available as f64 + elapsed * limit as f64).min(limit as f64) as u64;
 
-			if new_tokens >= count {
-					tokens.insert(app_name.to_string(), (new_tokens - count, now));
+			// A batch larger than the bucket could never be admitted, so it is let
+			// through once the bucket is full, emptying it.
+			if new_tokens >= count || (limit > 0 && new_tokens == limit) {
+					tokens.insert(app_name.to_string(), (new_tokens.saturating_sub(count), now));
 					Ok(())
 			} else {
-					Err(LogSystemError::RateLimitExceeded(app_name.to_string()))
+					// Tokens refill at `limit` per second.
+					let missing = count.min(limit).saturating_sub(new_tokens);
+					let retry_after = (missing as f64 / limit.max(1) as f64).ceil().max(1.0) as u64;
+					Err(Rejection {
+							error: LogSystemError::RateLimitExceeded(app_name.to_string()),
+							quota: Some(QuotaExceeded {
+									limit,
+									remaining: new_tokens,
+									retry_after: Duration::from_secs(retry_after),
+							}),
+					})
 			}
 	}
 
@@ -91,8 +115,231 @@ impl Clone for RateLimiter {
 	}
 }
 
+struct RedactionRules {
+	redactors: Arc<RwLock<HashMap<String, Redactor>>>,
+	/// `None` without a `PSEUDONYM_KEY`; pseudonymize rules then mask.
+	pseudonymizer: Option<Arc<Pseudonymizer>>,
+}
+
+impl RedactionRules {
+	fn new(pseudonymizer: Option<Arc<Pseudonymizer>>) -> Self {
+			Self {
+					redactors: Arc::new(RwLock::new(HashMap::new())),
+					pseudonymizer,
+			}
+	}
+
+	/// Swaps in freshly compiled rule sets, keeping an app's previous set if its new rules fail to compile.
+	async fn update_rules(&self, configs: Vec<RedactionConfig>) {
+			let mut redactors = self.redactors.write().await;
+			let mut updated = HashMap::new();
+
+			for config in configs {
+					match Redactor::from_config(&config) {
+							Ok(redactor) => {
+									let redactor = match &self.pseudonymizer {
+											Some(pseudonymizer) => redactor.with_pseudonymizer(pseudonymizer.clone()),
+											None => {
+													if config.pseudonymizes() {
+															warn!("PSEUDONYM_KEY is not set, masking instead of pseudonymizing for {}", config.app_name);
+													}
+													redactor
+											}
+									};
+									updated.insert(config.app_name, redactor);
+							}
+							Err(e) => {
+									warn!("Keeping previous redaction rules for {}: {}", config.app_name, e);
+									if let Some(previous) = redactors.remove(&config.app_name) {
+											updated.insert(config.app_name, previous);
+									}
+							}
+					}
+			}
+
+			*redactors = updated;
+	}
+
+	async fn load_rules_from_config(&self, config_url: &str) {
+			let rules = self.clone();
+			let url = config_url.to_string();
+
+			tokio::spawn(async move {
+					loop {
+							tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+
+					match fetch_redaction_rules(&url).await {
+							Ok(configs) => rules.update_rules(configs).await,
+							Err(e) => error!("Failed to fetch redaction rules: {}", e),
+					}
+					}
+			});
+	}
+}
+
+async fn fetch_redaction_rules(config_url: &str) -> reqwest::Result<Vec<RedactionConfig>> {
+	reqwest::get(format!("{}/redaction", config_url)).await?.json().await
+}
+
+impl Clone for RedactionRules {
+	fn clone(&self) -> Self {
+			Self {
+					redactors: self.redactors.clone(),
+					pseudonymizer: self.pseudonymizer.clone(),
+			}
+	}
+}
+
+struct SizeLimiter {
+	limits: Arc<RwLock<HashMap<String, SizeLimits>>>,
+}
+
+impl SizeLimiter {
+	fn new() -> Self {
+			Self {
+					limits: Arc::new(RwLock::new(HashMap::new())),
+			}
+	}
+
+	async fn load_limits_from_config(&self, config_url: &str) {
+			let limits = self.limits.clone();
+			let url = config_url.to_string();
+
+			tokio::spawn(async move {
+					loop {
+							tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+
+					match reqwest::get(format!("{}/limits", url)).await {
+							Ok(resp) => {
+									if let Ok(configs) = resp.json::<Vec<SizeLimits>>().await {
+													*limits.write().await = configs
+															.into_iter()
+															.map(|config| (config.app_name.clone(), config))
+															.collect();
+											}
+									}
+									Err(e) => error!("Failed to fetch size limits: {}", e),
+							}
+					}
+			});
+	}
+}
+
+/// Remembers recently ingested batch and entry ids so that agent retries are not stored twice.
+///
+/// Ids are claimed before a batch is forwarded, committed once storage accepts it and
+/// released if storage fails, so a retry racing the original request is not lost.
+struct Deduplicator {
+	seen: Arc<Mutex<RecentIds>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Claim {
+	New,
+	InFlight,
+	Stored,
+	/// The id came up earlier in the same call, which claimed it already.
+	Repeated,
+}
+
+struct RecentIds {
+	/// Id to whether it has been stored, and the generation of its claim.
+	ids: HashMap<String, (bool, u64)>,
+	/// Claims in the order they were made. A released id keeps its slot until
+	/// it reaches the front; the generation tells it from a later claim.
+	order: VecDeque<(Instant, u64, String)>,
+	next_generation: u64,
+	ttl: Duration,
+	capacity: usize,
+}
+
+impl RecentIds {
+	fn evict(&mut self, now: Instant) {
+			while let Some((seen_at, generation, id)) = self.order.front() {
+					let live = self.ids.get(id).is_some_and(|(_, claimed)| claimed == generation);
+					if live && now.duration_since(*seen_at) < self.ttl && self.ids.len() <= self.capacity {
+							break;
+					}
+					if let Some((_, _, id)) = self.order.pop_front() {
+							if live {
+									self.ids.remove(&id);
+							}
+					}
+			}
+	}
+}
+
+impl Deduplicator {
+	fn new(ttl: Duration, capacity: usize) -> Self {
+			Self {
+					seen: Arc::new(Mutex::new(RecentIds {
+							ids: HashMap::new(),
+							order: VecDeque::new(),
+							next_generation: 0,
+							ttl,
+							capacity,
+					})),
+			}
+	}
+
+	async fn claim<'a>(&self, ids: impl IntoIterator<Item = &'a str>) -> Vec<Claim> {
+			let mut seen = self.seen.lock().await;
+			let now = Instant::now();
+			seen.evict(now);
+
+			let mut claimed = HashSet::new();
+			ids.into_iter()
+					.map(|id| match seen.ids.get(id) {
+							_ if !claimed.insert(id) => Claim::Repeated,
+							Some((true, _)) => Claim::Stored,
+							Some((false, _)) => Claim::InFlight,
+							None => {
+									let generation = seen.next_generation;
+									seen.next_generation += 1;
+									seen.ids.insert(id.to_string(), (false, generation));
+									seen.order.push_back((now, generation, id.to_string()));
+									Claim::New
+							}
+					})
+					.collect()
+	}
+
+	async fn commit<'a>(&self, ids: impl IntoIterator<Item = &'a str>) {
+			let mut seen = self.seen.lock().await;
+			for id in ids {
+					if let Some((stored, _)) = seen.ids.get_mut(id) {
+							*stored = true;
+					}
+			}
+	}
+
+	/// Forgets claimed ids of a batch that was not stored. Their queue slots are
+	/// skipped once they reach the front.
+	async fn release<'a>(&self, ids: impl IntoIterator<Item = &'a str>) {
+			let mut seen = self.seen.lock().await;
+			for id in ids {
+					seen.ids.remove(id);
+			}
+	}
+}
+
+#[derive(Default)]
+struct Metrics {
+	redactions: RwLock<HashMap<String, u64>>,
+	duplicate_entries: AtomicU64,
+	truncated_entries: AtomicU64,
+	rejected_entries: AtomicU64,
+	syslog_entries: AtomicU64,
+	syslog_dropped_entries: AtomicU64,
+}
+
 struct AppState {
 	rate_limiter: RateLimiter,
+	redaction_rules: RedactionRules,
+	size_limiter: SizeLimiter,
+	deduplicator: Deduplicator,
+	metrics: Metrics,
+	reidentification: pseudonyms::Reidentification,
 	storage_url: String,
 }
 
@@ -103,14 +350,66 @@ async fn main() {
 	let rate_limiter = RateLimiter::new();
 	rate_limiter.load_quotas_from_config("http://localhost:8003").await;
 
+	let vault_path = std::env::var("PSEUDONYM_VAULT_PATH").unwrap_or_else(|_| "pseudonym_vault.jsonl".to_string());
+	let vault = match pseudonyms::FileVault::open(vault_path.as_ref()) {
+			Ok(vault) => Arc::new(vault),
+			Err(e) => {
+					error!("Failed to open pseudonym vault {}: {}", vault_path, e);
+					std::process::exit(1);
+			}
+	};
+	let pseudonymizer = match std::env::var("PSEUDONYM_KEY") {
+			Ok(key) if !key.is_empty() => Some(Arc::new(Pseudonymizer::new(key).with_vault(vault.clone()))),
+			_ => None,
+	};
+
+	let audit_log_path = std::env::var("REIDENTIFY_AUDIT_LOG").unwrap_or_else(|_| "reidentify_audit.jsonl".to_string());
+	let operators = std::env::var("REIDENTIFY_OPERATORS").unwrap_or_default();
+	let reidentification = match pseudonyms::Reidentification::new(vault.clone(), &operators, audit_log_path.as_ref()) {
+			Ok(reidentification) => reidentification,
+			Err(e) => {
+					error!("Failed to open re-identification audit log {}: {}", audit_log_path, e);
+					std::process::exit(1);
+			}
+	};
+
+	let redaction_rules = RedactionRules::new(pseudonymizer);
+	// Tokens from a throwaway key could never be correlated or re-identified
+	// after a restart, so a configured pseudonymize rule needs the key.
+	match fetch_redaction_rules("http://localhost:8003").await {
+			Ok(configs) => {
+					if redaction_rules.pseudonymizer.is_none() {
+							if let Some(config) = configs.iter().find(|config| config.pseudonymizes()) {
+									error!("Redaction rules of {} pseudonymize values, but PSEUDONYM_KEY is not set", config.app_name);
+									std::process::exit(1);
+							}
+					}
+					redaction_rules.update_rules(configs).await;
+			}
+			Err(e) => error!("Failed to fetch redaction rules: {}", e),
+	}
+	redaction_rules.load_rules_from_config("http://localhost:8003").await;
+
+	let size_limiter = SizeLimiter::new();
+	size_limiter.load_limits_from_config("http://localhost:8003").await;
+
 	let state = Arc::new(AppState {
 			rate_limiter,
+			redaction_rules,
+			size_limiter,
+			deduplicator: Deduplicator::new(Duration::from_secs(600), 500_000),
+			metrics: Metrics::default(),
+			reidentification,
 			storage_url: "http://localhost:8002".to_string(),
 	});
 
+	syslog::start(state.clone()).await;
+
 	let app = Router::new()
 			.route("/ingest", post(ingest_logs))
 			.route("/health", axum::routing::get(|| async { "OK" }))
+			.route("/metrics", axum::routing::get(get_metrics))
+			.route("/reidentify", post(pseudonyms::reidentify))
 			.layer(TraceLayer::new_for_http())
 			.with_state(state);
 
@@ -121,57 +420,397 @@ async fn main() {
 
 async fn ingest_logs(
 	State(state): State<Arc<AppState>>,
+	headers: HeaderMap,
 	body: axum::body::Bytes,
-) -> impl IntoResponse {
+) -> Result<Json<serde_json::Value>, Rejection> {
+	let format = match headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+			None => WireFormat::Json,
+			Some(content_type) => WireFormat::from_content_type(content_type)
+					.ok_or_else(|| LogSystemError::UnsupportedMediaType(content_type.to_string()))?,
+	};
+	let gzipped = headers
+			.get(CONTENT_ENCODING)
+			.and_then(|v| v.to_str().ok())
+			.is_some_and(|v| v.eq_ignore_ascii_case("gzip"));
+
 	// Распаковка gzip
-	let mut decoder = GzDecoder::new(&body[..]);
-	let mut decompressed = Vec::new();
-	if let Err(e) = decoder.read_to_end(&mut decompressed) {
-			error!("Decompression error: {}", e);
-			return (StatusCode::BAD_REQUEST, "Invalid gzip").into_response();
+	let decompressed = if gzipped {
+			let mut decoder = GzDecoder::new(&body[..]);
+			let mut decompressed = Vec::new();
+			if let Err(e) = decoder.read_to_end(&mut decompressed) {
+					error!("Decompression error: {}", e);
+					return Err(LogSystemError::InvalidPayload(format!("invalid gzip: {}", e)).into());
+			}
+			decompressed
+	} else {
+			body.to_vec()
+	};
+
+	let batch: LogBatch = wire::decode_batch(&decompressed, format).inspect_err(|e| {
+			warn!("Rejected batch: {}", e);
+	})?;
+
+	process_batch(&state, batch).await.map(Json)
+}
+
+/// Quota state sent with a 429, so clients know when to send again.
+#[derive(Debug)]
+struct QuotaExceeded {
+	limit: u64,
+	remaining: u64,
+	retry_after: Duration,
+}
+
+/// Why a batch was not ingested.
+#[derive(Debug)]
+struct Rejection {
+	error: LogSystemError,
+	quota: Option<QuotaExceeded>,
+}
+
+impl From<LogSystemError> for Rejection {
+	fn from(error: LogSystemError) -> Self {
+			Self { error, quota: None }
 	}
+}
 
-	let mut batch: LogBatch = match serde_json::from_slice(&decompressed) {
-			Ok(b) => b,
-			Err(e) => {
-					error!("JSON parse error: {}", e);
-					return (StatusCode::BAD_REQUEST, "Invalid JSON").into_response();
+impl IntoResponse for Rejection {
+	/// The error's response, with `Retry-After` and the remaining quota when rate limited.
+	fn into_response(self) -> Response {
+			let mut response = self.error.into_response();
+			if let Some(quota) = self.quota {
+					let headers = response.headers_mut();
+					headers.insert(RETRY_AFTER, quota.retry_after.as_secs().into());
+					headers.insert(HeaderName::from_static(RATE_LIMIT_LIMIT_HEADER), quota.limit.into());
+					headers.insert(HeaderName::from_static(RATE_LIMIT_REMAINING_HEADER), quota.remaining.into());
 			}
-	};
+			response
+	}
+}
+
+/// Deduplicates, rate limits, redacts, size limits and stores a batch. Returns
+/// the response body of `/ingest`.
+async fn process_batch(state: &AppState, mut batch: LogBatch) -> Result<serde_json::Value, Rejection> {
+	match state.deduplicator.claim([batch.batch_id.as_str()]).await[0] {
+			Claim::New => {}
+			Claim::Repeated => unreachable!("a single id cannot repeat"),
+			Claim::InFlight => {
+					warn!("Batch {} is already being ingested", batch.batch_id);
+					return Err(LogSystemError::Conflict(format!("batch {} is already being ingested", batch.batch_id)).into());
+			}
+			Claim::Stored => {
+					info!("Skipped duplicate batch {}", batch.batch_id);
+					let duplicates = batch.logs.len();
+					state.metrics.duplicate_entries.fetch_add(duplicates as u64, Ordering::Relaxed);
+					return Ok(serde_json::json!({"status": "ok", "accepted": 0, "duplicates": duplicates}));
+			}
+	}
+
+	let claims = state.deduplicator.claim(batch.logs.iter().map(|log| log.id.as_str())).await;
+	if claims.contains(&Claim::InFlight) {
+			// Another request is storing some of these entries. Should it fail they
+			// must not be lost, so the sender has to retry rather than drop them.
+			let claimed = batch.logs.iter().zip(&claims).filter(|(_, claim)| **claim == Claim::New);
+			state
+					.deduplicator
+					.release(std::iter::once(batch.batch_id.as_str()).chain(claimed.map(|(log, _)| log.id.as_str())))
+					.await;
+			warn!("Batch {} has entries that are already being ingested", batch.batch_id);
+			return Err(LogSystemError::Conflict(format!(
+					"batch {} has entries that are already being ingested",
+					batch.batch_id
+			))
+			.into());
+	}
+	let received = batch.logs.len();
+	let mut claims = claims.into_iter();
+	batch.logs.retain(|_| claims.next() == Some(Claim::New));
+	let duplicates = received - batch.logs.len();
+	if duplicates > 0 {
+			info!("Dropped {} duplicate entries from batch {}", duplicates, batch.batch_id);
+			state.metrics.duplicate_entries.fetch_add(duplicates as u64, Ordering::Relaxed);
+	}
+	if batch.logs.is_empty() {
+			state.deduplicator.commit([batch.batch_id.as_str()]).await;
+			return Ok(serde_json::json!({"status": "ok", "accepted": 0, "duplicates": duplicates}));
+	}
 
 	// Проверка квоты
-	if !batch.logs.is_empty() {
+	{
 			let app_name = &batch.logs[0].app_name;
 			let count = batch.logs.len() as u64;
 
 			if let Err(e) = state.rate_limiter.check_rate(app_name, count).await {
-					error!("Rate limit exceeded: {}", e);
-					return (StatusCode::TOO_MANY_REQUESTS, format!("{}", e)).into_response();
+					error!("Rate limit exceeded: {}", e.error);
+					release_batch(state, &batch).await;
+					return Err(e);
+			}
+	}
+
+	{
+			let redactors = state.redaction_rules.redactors.read().await;
+			let mut fired_counts: HashMap<String, u64> = HashMap::new();
+
+			for log in &mut batch.logs {
+					let redactor = redactors
+							.get(&log.app_name)
+							.unwrap_or(Redactor::builtin());
+					for rule in redactor.redact(log) {
+							*fired_counts.entry(rule.to_string()).or_default() += 1;
+					}
+			}
+
+			if !fired_counts.is_empty() {
+					let mut redactions = state.metrics.redactions.write().await;
+					for (rule, count) in fired_counts {
+							*redactions.entry(rule).or_default() += count;
+					}
+			}
+	}
+
+	// Limits apply after redaction, so a truncated secret cannot slip past a rule.
+	let mut truncated = 0;
+	let mut rejected = Vec::new();
+	{
+			let limits = state.size_limiter.limits.read().await;
+			let mut kept = Vec::with_capacity(batch.logs.len());
+
+			for mut log in batch.logs.drain(..) {
+					let app_limits = limits.get(&log.app_name).unwrap_or(SizeLimits::builtin());
+					match app_limits.enforce(&mut log) {
+							LimitOutcome::Within => kept.push(log),
+							LimitOutcome::Truncated => {
+									truncated += 1;
+									kept.push(log);
+							}
+							LimitOutcome::Rejected(limit) => {
+									warn!("Rejected entry {} from {}: exceeds {} limit", log.id, log.app_name, limit);
+									rejected.push(log.id);
+							}
+					}
 			}
+
+			batch.logs = kept;
+	}
+	state.metrics.truncated_entries.fetch_add(truncated as u64, Ordering::Relaxed);
+	if !rejected.is_empty() {
+			state.metrics.rejected_entries.fetch_add(rejected.len() as u64, Ordering::Relaxed);
+			state.deduplicator.release(rejected.iter().map(String::as_str)).await;
+	}
+	if batch.logs.is_empty() {
+			state.deduplicator.commit([batch.batch_id.as_str()]).await;
+			return Ok(serde_json::json!({
+					"status": "ok",
+					"accepted": 0,
+					"duplicates": duplicates,
+					"truncated": truncated,
+					"rejected": rejected.len(),
+			}));
 	}
 
-	for log in &mut batch.logs {
-			log.mask_secrets();
+	if let Err(e) = store_batch(state, &batch).await {
+			error!("Failed to store batch {}: {}", batch.batch_id, e);
+			release_batch(state, &batch).await;
+			return Err(e.into());
 	}
 
+	info!("Stored batch {} with {} logs", batch.batch_id, batch.logs.len());
+	state
+			.deduplicator
+			.commit(std::iter::once(batch.batch_id.as_str()).chain(batch.logs.iter().map(|log| log.id.as_str())))
+			.await;
+	Ok(serde_json::json!({
+			"status": "ok",
+			"accepted": batch.logs.len(),
+			"duplicates": duplicates,
+			"truncated": truncated,
+			"rejected": rejected.len(),
+	}))
+}
+
+async fn store_batch(state: &AppState, batch: &LogBatch) -> Result<(), LogSystemError> {
 	let client = reqwest::Client::new();
-	match client
-			.post(&format!("{}/store", state.storage_url))
-			.json(&batch)
+	let resp = client
+			.post(format!("{}/store", state.storage_url))
+			.json(batch)
 			.send()
 			.await
-	{
-			Ok(resp) if resp.status().is_success() => {
-					info!("Stored batch {} with {} logs", batch.batch_id, batch.logs.len());
-					(StatusCode::OK, Json(serde_json::json!({"status": "ok"}))).into_response()
-			}
-			Ok(resp) => {
-					error!("Storage returned {}", resp.status());
-					(StatusCode::INTERNAL_SERVER_ERROR, "Storage error").into_response()
-			}
-			Err(e) => {
-					error!("Failed to send to storage: {}", e);
-					(StatusCode::INTERNAL_SERVER_ERROR, "Storage unavailable").into_response()
-			}
+			.map_err(|e| LogSystemError::NetworkError(format!("storage unavailable: {}", e)))?;
+
+	if resp.status().is_success() {
+			return Ok(());
+	}
+
+	let status = resp.status().as_u16();
+	let body = resp.bytes().await.unwrap_or_default();
+	Err(LogSystemError::from_response_body(status, &body))
+}
+
+/// Lets a retry of `batch` through after it failed to be stored.
+async fn release_batch(state: &AppState, batch: &LogBatch) {
+	state
+			.deduplicator
+			.release(std::iter::once(batch.batch_id.as_str()).chain(batch.logs.iter().map(|log| log.id.as_str())))
+			.await;
+}
+
+async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+	let redactions = state.metrics.redactions.read().await.clone();
+	Json(serde_json::json!({
+			"redactions": redactions,
+			"duplicate_entries": state.metrics.duplicate_entries.load(Ordering::Relaxed),
+			"truncated_entries": state.metrics.truncated_entries.load(Ordering::Relaxed),
+			"rejected_entries": state.metrics.rejected_entries.load(Ordering::Relaxed),
+			"syslog_entries": state.metrics.syslog_entries.load(Ordering::Relaxed),
+			"syslog_dropped_entries": state.metrics.syslog_dropped_entries.load(Ordering::Relaxed),
+	}))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use axum::http::StatusCode;
+
+	#[tokio::test]
+	async fn test_rate_limit_headers_and_oversized_batches() {
+		let limiter = RateLimiter::new();
+		limiter
+				.update_quota(QuotaConfig {
+						app_name: "web".to_string(),
+						logs_per_second: 100,
+				})
+				.await;
+
+		assert!(limiter.check_rate("web", 250).await.is_ok(), "a full bucket admits an oversized batch");
+		let rejection = limiter.check_rate("web", 250).await.unwrap_err();
+		assert_eq!(rejection.error.code(), "rate_limit_exceeded");
+
+		let response = rejection.into_response();
+		assert_eq!(response.status(), 429);
+		let header = |name: &str| response.headers()[name].to_str().unwrap().to_string();
+		assert_eq!(header("retry-after"), "1");
+		assert_eq!(header(RATE_LIMIT_LIMIT_HEADER), "100");
+		assert!(header(RATE_LIMIT_REMAINING_HEADER).parse::<u64>().unwrap() < 100);
 	}
-}
\ No newline at end of file
+
+	#[tokio::test]
+	async fn test_claim_commit_release() {
+		let deduplicator = Deduplicator::new(Duration::from_secs(600), 100);
+		assert_eq!(deduplicator.claim(["a", "b"]).await, vec![Claim::New, Claim::New]);
+		assert_eq!(deduplicator.claim(["a"]).await, vec![Claim::InFlight]);
+
+		deduplicator.commit(["a"]).await;
+		deduplicator.release(["b"]).await;
+		assert_eq!(deduplicator.claim(["a", "b"]).await, vec![Claim::Stored, Claim::New]);
+	}
+
+	#[tokio::test]
+	async fn test_released_slots_do_not_evict_later_claims() {
+		let deduplicator = Deduplicator::new(Duration::from_secs(600), 2);
+		for _ in 0..5 {
+			deduplicator.claim(["a"]).await;
+			deduplicator.release(["a"]).await;
+		}
+		deduplicator.claim(["a"]).await;
+		deduplicator.commit(["a"]).await;
+		deduplicator.claim(["b"]).await;
+
+		assert_eq!(deduplicator.claim(["a", "b"]).await, vec![Claim::Stored, Claim::InFlight]);
+		assert_eq!(deduplicator.seen.lock().await.order.len(), 2);
+	}
+
+	#[tokio::test]
+	async fn test_evicts_by_age_and_capacity() {
+		let deduplicator = Deduplicator::new(Duration::from_secs(600), 2);
+		deduplicator.claim(["a", "b", "c"]).await;
+		assert_eq!(deduplicator.claim(["a"]).await, vec![Claim::New]);
+		assert_eq!(deduplicator.claim(["c"]).await, vec![Claim::InFlight]);
+
+		let deduplicator = Deduplicator::new(Duration::ZERO, 100);
+		deduplicator.claim(["a"]).await;
+		deduplicator.commit(["a"]).await;
+		assert_eq!(deduplicator.claim(["a"]).await, vec![Claim::New]);
+	}
+
+	/// Stands in for storage, answering every batch with 200.
+	async fn serve_storage() -> String {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		let app = Router::new().route("/store", post(|| async { StatusCode::OK }));
+		tokio::spawn(async move { axum::serve(listener, app).await });
+		url
+	}
+
+	/// State with an empty vault and no operators, keeping its files in `dir`.
+	fn test_state(storage_url: String, dir: &std::path::Path) -> AppState {
+		std::fs::create_dir_all(dir).unwrap();
+		let vault = Arc::new(pseudonyms::FileVault::open(&dir.join("vault.jsonl")).unwrap());
+		AppState {
+				rate_limiter: RateLimiter::new(),
+				redaction_rules: RedactionRules::new(None),
+				size_limiter: SizeLimiter::new(),
+				deduplicator: Deduplicator::new(Duration::from_secs(600), 100),
+				metrics: Metrics::default(),
+				reidentification: pseudonyms::Reidentification::new(vault, "", &dir.join("audit.jsonl")).unwrap(),
+				storage_url,
+		}
+	}
+
+	#[tokio::test]
+	async fn test_size_limits_apply_per_app() {
+		let dir = std::env::temp_dir().join(format!("ingestion-limits-{}", std::process::id()));
+		let state = test_state(serve_storage().await, &dir);
+		state.size_limiter.limits.write().await.insert(
+				"strict".to_string(),
+				SizeLimits {
+						message_bytes: common::Limit::reject(16),
+						attribute_count: common::Limit::truncate(1),
+						..SizeLimits::new("strict")
+				},
+		);
+
+		let log = |app: &str, message: &str, attributes: usize| {
+				let attributes = (0..attributes).map(|i| (format!("key{}", i), common::AttributeValue::Int(i as i64))).collect();
+				common::LogEntry::new(app.to_string(), common::LogLevel::Info, message.to_string(), attributes)
+		};
+		let long_message = "a message over sixteen bytes";
+		let batch = LogBatch::new(vec![
+				log("web", long_message, 2),
+				log("strict", long_message, 0),
+				log("strict", "short", 2),
+				log("strict", "short", 1),
+		]);
+
+		let response = process_batch(&state, batch).await.unwrap();
+		assert_eq!(response["accepted"], 3);
+		assert_eq!(response["truncated"], 1);
+		assert_eq!(response["rejected"], 1);
+		assert_eq!(state.metrics.truncated_entries.load(Ordering::Relaxed), 1);
+		assert_eq!(state.metrics.rejected_entries.load(Ordering::Relaxed), 1);
+
+		drop(state);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_repeated_entry_ids_count_as_duplicates() {
+		let dir = std::env::temp_dir().join(format!("ingestion-repeated-{}", std::process::id()));
+		let state = test_state(serve_storage().await, &dir);
+		assert_eq!(
+				state.deduplicator.claim(["a", "a"]).await,
+				vec![Claim::New, Claim::Repeated]
+		);
+		state.deduplicator.release(["a"]).await;
+
+		let log = common::LogEntry::new("web".to_string(), common::LogLevel::Info, "hello".to_string(), HashMap::new());
+		let batch = LogBatch::new(vec![log.clone(), log]);
+
+		let response = process_batch(&state, batch).await.unwrap();
+		assert_eq!(response["accepted"], 1);
+		assert_eq!(response["duplicates"], 1);
+		assert_eq!(state.metrics.duplicate_entries.load(Ordering::Relaxed), 1);
+
+		drop(state);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+}
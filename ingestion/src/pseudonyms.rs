@@ -0,0 +1,373 @@
+//! Pseudonym vault and the audited re-identification endpoint.
+//!
+//! Every token the redactors hand out is appended to the vault file together
+//! with the original value. Operators listed in `REIDENTIFY_OPERATORS`
+//! (`name:secret` pairs, comma-separated) can resolve tokens back through
+//! `POST /reidentify` with `Authorization: Bearer <secret>` and a reason.
+//! Every attempt, granted or not, is appended to the audit log.
+//!
+//! The vault file is written by a thread of its own, so redacting an entry
+//! never waits on the disk; tokens handed out right before a crash may be
+//! missing from it. Both files are readable by the service's user only.
+
+use crate::AppState;
+use axum::{
+	extract::{rejection::JsonRejection, State},
+	http::{header::AUTHORIZATION, HeaderMap},
+	Json,
+};
+use chrono::Utc;
+use common::{LogSystemError, PseudonymVault};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use tracing::{error, info, warn};
+
+const MAX_TOKENS_PER_REQUEST: usize = 100;
+
+#[derive(Serialize, Deserialize)]
+struct VaultRecord {
+	token: String,
+	original: String,
+}
+
+/// Token to original value, kept in memory and appended to a JSON lines file.
+pub struct FileVault {
+	tokens: RwLock<HashMap<String, String>>,
+	/// New records for the writer thread, which appends them to the file.
+	records: Option<mpsc::Sender<VaultRecord>>,
+	writer: Option<JoinHandle<()>>,
+}
+
+impl FileVault {
+	/// Opens or creates the vault at `path`, loading the tokens already in it.
+	pub fn open(path: &Path) -> std::io::Result<Self> {
+		let mut tokens = HashMap::new();
+		if path.exists() {
+			for line in BufReader::new(File::open(path)?).lines() {
+				match serde_json::from_str::<VaultRecord>(&line?) {
+					Ok(record) => {
+						tokens.insert(record.token, record.original);
+					}
+					Err(e) => warn!("Skipping unreadable pseudonym vault line: {}", e),
+				}
+			}
+		}
+
+		let mut file = open_private(path)?;
+		let (records, received) = mpsc::channel::<VaultRecord>();
+		let writer = std::thread::Builder::new().name("pseudonym-vault".to_string()).spawn(move || {
+			for record in received {
+				let mut line = serde_json::to_vec(&record).unwrap_or_default();
+				line.push(b'\n');
+				if let Err(e) = file.write_all(&line) {
+					error!("Failed to persist pseudonym {}: {}", record.token, e);
+				}
+			}
+		})?;
+
+		Ok(Self {
+			tokens: RwLock::new(tokens),
+			records: Some(records),
+			writer: Some(writer),
+		})
+	}
+
+	fn lookup(&self, token: &str) -> Option<String> {
+		self.tokens.read().ok()?.get(token).cloned()
+	}
+}
+
+impl PseudonymVault for FileVault {
+	fn record(&self, token: &str, original: &str) {
+		if self.tokens.read().is_ok_and(|tokens| tokens.contains_key(token)) {
+			return;
+		}
+		let Ok(mut tokens) = self.tokens.write() else {
+			return;
+		};
+		if tokens.insert(token.to_string(), original.to_string()).is_some() {
+			return;
+		}
+		drop(tokens);
+
+		let record = VaultRecord {
+			token: token.to_string(),
+			original: original.to_string(),
+		};
+		if let Some(records) = &self.records {
+			let _ = records.send(record);
+		}
+	}
+}
+
+impl Drop for FileVault {
+	/// Waits for the writer thread to append the records still queued.
+	fn drop(&mut self) {
+		drop(self.records.take());
+		if let Some(writer) = self.writer.take() {
+			let _ = writer.join();
+		}
+	}
+}
+
+/// Operators allowed to re-identify, and the audit trail of their requests.
+pub struct Reidentification {
+	vault: Arc<FileVault>,
+	/// Operator name and bearer secret.
+	operators: Vec<(String, String)>,
+	audit_log: Mutex<File>,
+}
+
+impl Reidentification {
+	pub fn new(vault: Arc<FileVault>, operators: &str, audit_log_path: &Path) -> std::io::Result<Self> {
+		let operators = operators
+			.split(',')
+			.filter_map(|pair| pair.split_once(':'))
+			.map(|(name, secret)| (name.trim().to_string(), secret.trim().to_string()))
+			.filter(|(name, secret)| !name.is_empty() && !secret.is_empty())
+			.collect();
+
+		Ok(Self {
+			vault,
+			operators,
+			audit_log: Mutex::new(open_private(audit_log_path)?),
+		})
+	}
+
+	fn authenticate(&self, headers: &HeaderMap) -> Result<&str, LogSystemError> {
+		if self.operators.is_empty() {
+			return Err(LogSystemError::Forbidden("re-identification is disabled".to_string()));
+		}
+
+		let secret = headers
+			.get(AUTHORIZATION)
+			.and_then(|v| v.to_str().ok())
+			.and_then(|v| v.strip_prefix("Bearer "))
+			.ok_or_else(|| LogSystemError::Unauthorized("operator bearer token required".to_string()))?;
+
+		self.operators
+			.iter()
+			.find(|(_, expected)| constant_time_eq(expected.as_bytes(), secret.trim().as_bytes()))
+			.map(|(name, _)| name.as_str())
+			.ok_or_else(|| LogSystemError::Unauthorized("unknown operator token".to_string()))
+	}
+
+	fn audit(&self, operator: Option<&str>, request: Option<&ReidentifyRequest>, outcome: &str) {
+		let record = serde_json::json!({
+			"timestamp": Utc::now().to_rfc3339(),
+			"operator": operator,
+			"reason": request.map(|r| r.reason.as_str()),
+			"tokens": request.map(|r| r.tokens.as_slice()),
+			"outcome": outcome,
+		});
+		info!(target: "audit", "re-identification by {:?}: {}", operator, outcome);
+
+		let Ok(mut file) = self.audit_log.lock() else {
+			return;
+		};
+		if let Err(e) = writeln!(file, "{}", record) {
+			error!("Failed to write re-identification audit record: {}", e);
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReidentifyRequest {
+	tokens: Vec<String>,
+	/// Why the operator needs the values, e.g. a ticket reference.
+	reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReidentifyResponse {
+	operator: String,
+	/// Original value per token; `null` for tokens the vault does not know.
+	values: HashMap<String, Option<String>>,
+}
+
+pub async fn reidentify(
+	State(state): State<Arc<AppState>>,
+	headers: HeaderMap,
+	payload: Result<Json<ReidentifyRequest>, JsonRejection>,
+) -> Result<Json<ReidentifyResponse>, LogSystemError> {
+	let reidentification = &state.reidentification;
+
+	let operator = match reidentification.authenticate(&headers) {
+		Ok(operator) => operator,
+		Err(e) => {
+			reidentification.audit(None, payload.as_ref().ok().map(|Json(r)| r), &format!("denied: {}", e));
+			return Err(e);
+		}
+	};
+
+	let request = match validate(payload) {
+		Ok(request) => request,
+		Err(e) => {
+			reidentification.audit(Some(operator), None, &format!("rejected: {}", e));
+			return Err(e);
+		}
+	};
+
+	let values: HashMap<String, Option<String>> = request
+		.tokens
+		.iter()
+		.map(|token| (token.clone(), reidentification.vault.lookup(token)))
+		.collect();
+	let resolved = values.values().filter(|value| value.is_some()).count();
+	reidentification.audit(
+		Some(operator),
+		Some(&request),
+		&format!("granted: {} of {} tokens resolved", resolved, values.len()),
+	);
+
+	Ok(Json(ReidentifyResponse {
+		operator: operator.to_string(),
+		values,
+	}))
+}
+
+fn validate(payload: Result<Json<ReidentifyRequest>, JsonRejection>) -> Result<ReidentifyRequest, LogSystemError> {
+	let Json(request) = payload?;
+	if request.reason.trim().is_empty() {
+		return Err(LogSystemError::InvalidPayload("a reason is required".to_string()));
+	}
+	if request.tokens.is_empty() || request.tokens.len() > MAX_TOKENS_PER_REQUEST {
+		return Err(LogSystemError::InvalidPayload(format!(
+			"between 1 and {} tokens per request",
+			MAX_TOKENS_PER_REQUEST
+		)));
+	}
+	Ok(request)
+}
+
+/// Opens `path` for appending, creating it readable by the owner only.
+fn open_private(path: &Path) -> std::io::Result<File> {
+	let mut options = OpenOptions::new();
+	options.create(true).append(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+	options.open(path)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use axum::http::HeaderValue;
+	use std::fs;
+	use std::path::PathBuf;
+
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("ingestion-pseudonyms-{}-{}", std::process::id(), name));
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	fn bearer(secret: &str) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", secret)).unwrap());
+		headers
+	}
+
+	fn request(tokens: &[&str], reason: &str) -> Result<Json<ReidentifyRequest>, JsonRejection> {
+		Ok(Json(ReidentifyRequest {
+			tokens: tokens.iter().map(|token| token.to_string()).collect(),
+			reason: reason.to_string(),
+		}))
+	}
+
+	#[cfg(unix)]
+	fn mode(path: &Path) -> u32 {
+		std::os::unix::fs::PermissionsExt::mode(&fs::metadata(path).unwrap().permissions()) & 0o777
+	}
+
+	#[test]
+	fn test_vault_reloads_recorded_tokens() {
+		let dir = temp_dir("vault");
+		let path = dir.join("vault.jsonl");
+
+		let vault = FileVault::open(&path).unwrap();
+		vault.record("psd_1", "alice@example.com");
+		vault.record("psd_1", "alice@example.com");
+		vault.record("psd_2", "bob@example.com");
+		assert_eq!(vault.lookup("psd_2").as_deref(), Some("bob@example.com"));
+		drop(vault);
+
+		assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+		#[cfg(unix)]
+		assert_eq!(mode(&path), 0o600);
+		let vault = FileVault::open(&path).unwrap();
+		assert_eq!(vault.lookup("psd_1").as_deref(), Some("alice@example.com"));
+		assert_eq!(vault.lookup("psd_2").as_deref(), Some("bob@example.com"));
+		assert_eq!(vault.lookup("psd_3"), None);
+
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_operator_authentication() {
+		let dir = temp_dir("auth");
+		let vault = Arc::new(FileVault::open(&dir.join("vault.jsonl")).unwrap());
+
+		let reidentification =
+			Reidentification::new(vault.clone(), "alice:s3cret, bob : hunter2,broken,:nameless", &dir.join("audit.jsonl")).unwrap();
+		assert_eq!(reidentification.operators.len(), 2);
+		assert_eq!(reidentification.authenticate(&bearer("s3cret")).unwrap(), "alice");
+		assert_eq!(reidentification.authenticate(&bearer("hunter2")).unwrap(), "bob");
+		assert_eq!(reidentification.authenticate(&bearer("nameless")).unwrap_err().code(), "unauthorized");
+		assert_eq!(reidentification.authenticate(&HeaderMap::new()).unwrap_err().code(), "unauthorized");
+
+		let disabled = Reidentification::new(vault, "", &dir.join("audit.jsonl")).unwrap();
+		assert_eq!(disabled.authenticate(&bearer("s3cret")).unwrap_err().code(), "forbidden");
+
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_request_validation() {
+		assert!(validate(request(&["psd_1"], "INC-42")).is_ok());
+		assert_eq!(validate(request(&["psd_1"], "  ")).unwrap_err().code(), "invalid_payload");
+		assert_eq!(validate(request(&[], "INC-42")).unwrap_err().code(), "invalid_payload");
+		let tokens = vec!["psd_1"; MAX_TOKENS_PER_REQUEST + 1];
+		assert_eq!(validate(request(&tokens, "INC-42")).unwrap_err().code(), "invalid_payload");
+	}
+
+	#[test]
+	fn test_audit_lines() {
+		let dir = temp_dir("audit");
+		let audit_path = dir.join("audit.jsonl");
+		let vault = Arc::new(FileVault::open(&dir.join("vault.jsonl")).unwrap());
+		let reidentification = Reidentification::new(vault, "alice:s3cret", &audit_path).unwrap();
+
+		reidentification.audit(None, None, "denied: unknown operator token");
+		let Ok(Json(request)) = request(&["psd_1", "psd_2"], "INC-42") else {
+			unreachable!()
+		};
+		reidentification.audit(Some("alice"), Some(&request), "granted: 1 of 2 tokens resolved");
+
+		#[cfg(unix)]
+		assert_eq!(mode(&audit_path), 0o600);
+		let lines: Vec<serde_json::Value> = fs::read_to_string(&audit_path)
+			.unwrap()
+			.lines()
+			.map(|line| serde_json::from_str(line).unwrap())
+			.collect();
+		assert_eq!(lines.len(), 2);
+		assert_eq!(lines[0]["operator"], serde_json::Value::Null);
+		assert_eq!(lines[0]["outcome"], "denied: unknown operator token");
+		assert_eq!(lines[1]["operator"], "alice");
+		assert_eq!(lines[1]["reason"], "INC-42");
+		assert_eq!(lines[1]["tokens"], serde_json::json!(["psd_1", "psd_2"]));
+		assert!(lines[1]["timestamp"].is_string());
+
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,229 @@
+//! Syslog receiver.
+//!
+//! Listens on UDP and TCP at `SYSLOG_UDP_ADDR` and `SYSLOG_TCP_ADDR` (both
+//! `0.0.0.0:5514` by default, empty to disable). Messages are parsed with
+//! [`common::syslog`], batched per app and host, and go through the same
+//! deduplication, quotas, redaction and size limits as `/ingest`.
+
+use crate::AppState;
+use chrono::Utc;
+use common::syslog::{Framer, SyslogMessage, MAX_FRAME_BYTES};
+use common::{LogBatch, LogEntry, Resource};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+const DEFAULT_ADDR: &str = "0.0.0.0:5514";
+/// App name of messages without an app-name or tag.
+const DEFAULT_APP: &str = "syslog";
+const CHANNEL_CAPACITY: usize = 10_000;
+const MAX_BATCH_ENTRIES: usize = 500;
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// App name and hostname.
+type GroupKey = (String, Option<String>);
+
+struct Received {
+	hostname: Option<String>,
+	entry: LogEntry,
+}
+
+/// Binds the configured listeners and starts forwarding what they receive.
+/// A listener that fails to bind is logged and left out.
+pub async fn start(state: Arc<AppState>) {
+	let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+
+	if let Some(addr) = address("SYSLOG_UDP_ADDR") {
+		match UdpSocket::bind(&addr).await {
+			Ok(socket) => {
+				info!("Syslog receiver listening on udp {}", addr);
+				tokio::spawn(receive_udp(socket, sender.clone()));
+			}
+			Err(e) => error!("Failed to bind syslog udp {}: {}", addr, e),
+		}
+	}
+	if let Some(addr) = address("SYSLOG_TCP_ADDR") {
+		match TcpListener::bind(&addr).await {
+			Ok(listener) => {
+				info!("Syslog receiver listening on tcp {}", addr);
+				tokio::spawn(accept_tcp(listener, sender.clone()));
+			}
+			Err(e) => error!("Failed to bind syslog tcp {}: {}", addr, e),
+		}
+	}
+
+	tokio::spawn(forward(state, receiver));
+}
+
+fn address(var: &str) -> Option<String> {
+	match std::env::var(var) {
+		Ok(addr) if addr.is_empty() => None,
+		Ok(addr) => Some(addr),
+		Err(_) => Some(DEFAULT_ADDR.to_string()),
+	}
+}
+
+fn parse(text: &str) -> Received {
+	let mut message = SyslogMessage::parse(text, Utc::now());
+	Received {
+		hostname: message.hostname.take(),
+		entry: message.into_entry(DEFAULT_APP),
+	}
+}
+
+async fn receive_udp(socket: UdpSocket, sender: mpsc::Sender<Received>) {
+	let mut buf = vec![0u8; 65_536];
+	loop {
+		let len = match socket.recv_from(&mut buf).await {
+			Ok((len, _)) => len,
+			Err(e) => {
+				warn!("Syslog udp receive failed: {}", e);
+				continue;
+			}
+		};
+		let text = common::syslog::decode(&buf[..len]);
+		if text.is_empty() {
+			continue;
+		}
+		if sender.send(parse(&text)).await.is_err() {
+			return;
+		}
+	}
+}
+
+async fn accept_tcp(listener: TcpListener, sender: mpsc::Sender<Received>) {
+	loop {
+		match listener.accept().await {
+			Ok((stream, peer)) => {
+				let sender = sender.clone();
+				tokio::spawn(async move {
+					if let Err(e) = receive_tcp(stream, sender).await {
+						warn!("Closed syslog connection from {}: {}", peer, e);
+					}
+				});
+			}
+			Err(e) => warn!("Syslog tcp accept failed: {}", e),
+		}
+	}
+}
+
+async fn receive_tcp(mut stream: TcpStream, sender: mpsc::Sender<Received>) -> anyhow::Result<()> {
+	let mut framer = Framer::default();
+	let mut buf = vec![0u8; MAX_FRAME_BYTES];
+	loop {
+		let len = stream.read(&mut buf).await?;
+		if len == 0 {
+			if let Some(frame) = framer.finish() {
+				sender.send(parse(&frame)).await?;
+			}
+			return Ok(());
+		}
+		framer.push(&buf[..len]);
+		while let Some(frame) = framer.next_frame()? {
+			sender.send(parse(&frame)).await?;
+		}
+	}
+}
+
+/// Collects messages for up to [`MAX_BATCH_DELAY`] and processes them as one
+/// batch per app and host, so quotas and redaction rules apply per app.
+async fn forward(state: Arc<AppState>, mut receiver: mpsc::Receiver<Received>) {
+	while let Some(first) = receiver.recv().await {
+		let deadline = tokio::time::Instant::now() + MAX_BATCH_DELAY;
+		let mut received = vec![first];
+		while received.len() < MAX_BATCH_ENTRIES {
+			match tokio::time::timeout_at(deadline, receiver.recv()).await {
+				Ok(Some(next)) => received.push(next),
+				Ok(None) | Err(_) => break,
+			}
+		}
+		state.metrics.syslog_entries.fetch_add(received.len() as u64, Ordering::Relaxed);
+
+		for batch in batches(received) {
+			let count = batch.logs.len();
+			let app_name = batch.logs[0].app_name.clone();
+			if let Err(e) = crate::process_batch(&state, batch).await {
+				warn!("Dropped {} syslog messages from {}: {}", count, app_name, e.error);
+				state.metrics.syslog_dropped_entries.fetch_add(count as u64, Ordering::Relaxed);
+			}
+		}
+	}
+}
+
+/// One batch per app and host, in the order each pair was first received.
+/// The hostname goes in the batch's resource.
+fn batches(received: Vec<Received>) -> Vec<LogBatch> {
+	let mut groups: Vec<(GroupKey, Vec<LogEntry>)> = Vec::new();
+	let mut index: HashMap<GroupKey, usize> = HashMap::new();
+	for Received { hostname, entry } in received {
+		let key = (entry.app_name.clone(), hostname);
+		match index.get(&key) {
+			Some(&i) => groups[i].1.push(entry),
+			None => {
+				index.insert(key.clone(), groups.len());
+				groups.push((key, vec![entry]));
+			}
+		}
+	}
+
+	groups
+		.into_iter()
+		.map(|((_, hostname), logs)| {
+			let batch = LogBatch::new(logs);
+			match hostname {
+				Some(hostname) => batch.with_resource(Resource {
+					host_name: Some(hostname),
+					..Resource::default()
+				}),
+				None => batch,
+			}
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_batches_group_by_app_and_host() {
+		let received = [
+			"<13>1 - web1 nginx - - - a",
+			"<13>1 - web2 nginx - - - b",
+			"<13>1 - web1 sshd - - - c",
+			"<13>1 - web1 nginx - - - d",
+			"<13>no header",
+		]
+		.into_iter()
+		.map(parse)
+		.collect();
+
+		let batches: Vec<(String, Option<String>, Vec<String>)> = batches(received)
+			.into_iter()
+			.map(|batch| {
+				(
+					batch.logs[0].app_name.clone(),
+					batch.resource.and_then(|resource| resource.host_name),
+					batch.logs.into_iter().map(|log| log.message).collect(),
+				)
+			})
+			.collect();
+		let expected = |app: &str, host: Option<&str>, messages: &[&str]| {
+			(app.to_string(), host.map(str::to_string), messages.iter().map(|m| m.to_string()).collect::<Vec<_>>())
+		};
+		assert_eq!(
+			batches,
+			vec![
+				expected("nginx", Some("web1"), &["a", "d"]),
+				expected("nginx", Some("web2"), &["b"]),
+				expected("sshd", Some("web1"), &["c"]),
+				expected("syslog", None, &["no header"]),
+			]
+		);
+	}
+}
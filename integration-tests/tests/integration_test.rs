@@ -1,5 +1,5 @@
 use agent::LogAgent;
-use common::{LogEntry, LogLevel, SearchQuery};
+use common::{AttributeRange, ErrorInfo, LogBatch, LogEntry, LogLevel, SearchQuery, StackFrame};
 use serde::Deserialize;
 use std::collections::HashMap;
 
@@ -23,8 +23,9 @@ async fn test_full_flow() {
 
     for i in 0..50 {
         let mut attrs = HashMap::new();
-        attrs.insert("test_id".to_string(), format!("test-{}", i));
-        attrs.insert("batch".to_string(), "integration-test".to_string());
+        attrs.insert("test_id".to_string(), format!("test-{}", i).into());
+        attrs.insert("batch".to_string(), "integration-test".into());
+        attrs.insert("duration_ms".to_string(), (i * 20).into());
 
         let log = LogEntry::new(
             "test-app".to_string(),
@@ -125,6 +126,50 @@ async fn test_search_by_level() {
     );
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_search_by_min_level() {
+    let agent = LogAgent::new("http://localhost:8001".to_string(), 6);
+    agent.start_flush_loop().await;
+
+    for (i, level) in LogLevel::ALL.iter().enumerate() {
+        let log = LogEntry::new(
+            "min-level-test-app".to_string(),
+            *level,
+            format!("Min level test log #{}", i),
+            HashMap::new(),
+        );
+        agent.log(log).await;
+    }
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(7)).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:8004/search?app_name=min-level-test-app&min_level=warn&limit=100")
+        .send()
+        .await
+        .expect("Failed to connect to search API");
+
+    assert!(response.status().is_success());
+
+    let search_result: SearchResponse = response
+        .json()
+        .await
+        .expect("Failed to parse search response");
+
+    assert!(
+        search_result.logs.len() >= 3,
+        "Expected at least 3 logs at Warn or above, got {}",
+        search_result.logs.len()
+    );
+    for log in &search_result.logs {
+        assert!(log.level >= LogLevel::Warn, "Found {} log in min_level=warn results", log.level);
+    }
+
+    println!(" Found {} logs at Warn or above", search_result.logs.len());
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_search_with_post() {
@@ -146,10 +191,8 @@ async fn test_search_with_post() {
     let query = SearchQuery {
         app_name: Some("post-test-app".to_string()),
         level: Some(LogLevel::Info),
-        from: None,
-        to: None,
-        attributes: None,
         limit: Some(20),
+        ..Default::default()
     };
 
     let client = reqwest::Client::new();
@@ -176,6 +219,391 @@ async fn test_search_with_post() {
     println!(" Found {} logs via POST search", search_result.logs.len());
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_search_by_attribute_range() {
+    let agent = LogAgent::new("http://localhost:8001".to_string(), 10);
+    agent.start_flush_loop().await;
+
+    for i in 0..10 {
+        let mut attrs = HashMap::new();
+        attrs.insert("duration_ms".to_string(), (i * 100).into());
+
+        let log = LogEntry::new(
+            "range-test-app".to_string(),
+            LogLevel::Info,
+            format!("Range test log #{}", i),
+            attrs,
+        );
+        agent.log(log).await;
+    }
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(7)).await;
+
+    let mut ranges = HashMap::new();
+    ranges.insert(
+        "duration_ms".to_string(),
+        AttributeRange {
+            gt: Some(500.0),
+            ..Default::default()
+        },
+    );
+    let query = SearchQuery {
+        app_name: Some("range-test-app".to_string()),
+        attribute_ranges: Some(ranges),
+        limit: Some(100),
+        ..Default::default()
+    };
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post("http://localhost:8004/search")
+        .json(&query)
+        .send()
+        .await
+        .expect("Failed to connect to search API");
+
+    assert!(response.status().is_success());
+
+    let search_result: SearchResponse = response
+        .json()
+        .await
+        .expect("Failed to parse search response");
+
+    assert!(!search_result.logs.is_empty(), "Expected logs with duration_ms > 500");
+    for log in &search_result.logs {
+        let duration = log.attributes["duration_ms"].as_i64().unwrap();
+        assert!(duration > 500, "Found duration_ms {} in > 500 results", duration);
+    }
+
+    println!(" Found {} logs with duration_ms > 500", search_result.logs.len());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_search_by_message() {
+    let agent = LogAgent::new("http://localhost:8001".to_string(), 10);
+    agent.start_flush_loop().await;
+
+    let messages = [
+        "Upstream connection reset by peer",
+        "Connection pool exhausted",
+        "Request timeout after 30s",
+        "Reset password email sent",
+    ];
+    for message in messages {
+        let log = LogEntry::new(
+            "message-search-app".to_string(),
+            LogLevel::Warn,
+            message.to_string(),
+            HashMap::new(),
+        );
+        agent.log(log).await;
+    }
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(7)).await;
+
+    let client = reqwest::Client::new();
+    let search = |q: &'static str| {
+        let client = client.clone();
+        async move {
+            let response = client
+                .get("http://localhost:8004/search")
+                .query(&[("app_name", "message-search-app"), ("q", q)])
+                .send()
+                .await
+                .expect("Failed to connect to search API");
+            assert!(response.status().is_success());
+            let result: SearchResponse = response.json().await.expect("Failed to parse search response");
+            let mut found: Vec<String> = result.logs.into_iter().map(|log| log.message).collect();
+            found.sort();
+            found
+        }
+    };
+
+    assert_eq!(search("\"connection reset\"").await, vec!["Upstream connection reset by peer"]);
+    assert_eq!(search("timeout").await, vec!["Request timeout after 30s"]);
+    assert_eq!(
+        search("conn*").await,
+        vec!["Connection pool exhausted", "Upstream connection reset by peer"]
+    );
+    assert_eq!(search("pass?ord").await, vec!["Reset password email sent"]);
+
+    println!(" Phrase, term, prefix and wildcard message searches matched");
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_search_with_query_language() {
+    let agent = LogAgent::new("http://localhost:8001".to_string(), 10);
+    agent.start_flush_loop().await;
+
+    let entries = [
+        (LogLevel::Error, "123", "Payment timeout talking to bank"),
+        (LogLevel::Warn, "123", "Retrying after timeout"),
+        (LogLevel::Info, "123", "Payment timeout budget is 30s"),
+        (LogLevel::Error, "456", "Payment timeout talking to bank"),
+        (LogLevel::Error, "123", "Card declined"),
+    ];
+    for (level, user_id, message) in entries {
+        let mut attrs = HashMap::new();
+        attrs.insert("user_id".to_string(), user_id.into());
+        let log = LogEntry::new("ql-test-app".to_string(), level, message.to_string(), attrs);
+        agent.log(log).await;
+    }
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(7)).await;
+
+    let query = SearchQuery {
+        q: Some(r#"app:ql-test-app AND level>=Warn AND attributes.user_id:123 AND "timeout""#.to_string()),
+        limit: Some(100),
+        ..Default::default()
+    };
+    let client = reqwest::Client::new();
+    let response = client
+        .post("http://localhost:8004/search")
+        .json(&query)
+        .send()
+        .await
+        .expect("Failed to connect to search API");
+    assert!(response.status().is_success());
+    let search_result: SearchResponse = response.json().await.expect("Failed to parse search response");
+    assert_eq!(search_result.logs.len(), 2, "Expected the Error and Warn timeout logs of user 123");
+
+    let response = client
+        .get("http://localhost:8004/search")
+        .query(&[("q", "app:ql-test-app (level:error OR retry*) NOT attributes.user_id:456")])
+        .send()
+        .await
+        .expect("Failed to connect to search API");
+    assert!(response.status().is_success());
+    let search_result: SearchResponse = response.json().await.expect("Failed to parse search response");
+    assert_eq!(search_result.logs.len(), 3);
+
+    let response = client
+        .get("http://localhost:8004/search")
+        .query(&[("q", "app:ql-test-app AND (level:error")])
+        .send()
+        .await
+        .expect("Failed to connect to search API");
+    assert_eq!(response.status(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "invalid_query");
+    assert_eq!(body["error"]["message"], "Invalid query: unclosed '(' at position 20");
+
+    println!(" Query language searches matched and reported parse errors");
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_search_by_error() {
+    let agent = LogAgent::new("http://localhost:8001".to_string(), 10);
+    agent.start_flush_loop().await;
+
+    let raised_in = |file: &str| {
+        let mut error = ErrorInfo::new("TimeoutError", "query timed out after 5s");
+        error.frames.push(StackFrame {
+            function: "billing::db::query".to_string(),
+            file: Some(file.to_string()),
+            line: Some(42),
+            column: Some(9),
+        });
+        error
+    };
+    for file in ["src/db.rs", "src/http.rs"] {
+        let log = LogEntry::new("error-test-app".to_string(), LogLevel::Error, "Query failed".to_string(), HashMap::new())
+            .with_error(raised_in(file));
+        agent.log(log).await;
+    }
+    let io_error = std::io::Error::new(std::io::ErrorKind::TimedOut, "connection reset");
+    let log = LogEntry::new("error-test-app".to_string(), LogLevel::Error, "Query failed".to_string(), HashMap::new())
+        .with_error(agent::error::capture(&io_error));
+    agent.log(log).await;
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(7)).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:8004/search")
+        .query(&[("q", "app:error-test-app error.type:TimeoutError error.file:db.rs")])
+        .send()
+        .await
+        .expect("Failed to connect to search API");
+    assert!(response.status().is_success());
+    let search_result: SearchResponse = response.json().await.expect("Failed to parse search response");
+    assert_eq!(search_result.logs.len(), 1, "Expected only the TimeoutError raised in db.rs");
+    let error = search_result.logs[0].error.as_ref().expect("error field is returned");
+    assert_eq!(error.top_frame().and_then(|frame| frame.line), Some(42));
+
+    println!(" Found TimeoutError from db.rs by exception type and top frame");
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_search_by_trace() {
+    let agent = LogAgent::new("http://localhost:8001".to_string(), 5);
+    agent.start_flush_loop().await;
+
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    for i in 0..5 {
+        let mut attrs = HashMap::new();
+        attrs.insert(
+            "traceparent".to_string(),
+            format!("00-{}-00f067aa0ba902b7-01", trace_id).into(),
+        );
+
+        let log = LogEntry::new(
+            "trace-test-app".to_string(),
+            LogLevel::Info,
+            format!("Trace test log #{}", i),
+            attrs,
+        );
+        agent.log(log).await;
+    }
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(7)).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://localhost:8004/traces/{}", trace_id))
+        .send()
+        .await
+        .expect("Failed to connect to search API");
+
+    assert!(response.status().is_success());
+
+    let search_result: SearchResponse = response
+        .json()
+        .await
+        .expect("Failed to parse search response");
+
+    assert!(
+        search_result.logs.len() >= 5,
+        "Expected at least 5 logs for trace, got {}",
+        search_result.logs.len()
+    );
+    for pair in search_result.logs.windows(2) {
+        assert!(pair[0].timestamp <= pair[1].timestamp, "Trace logs are not in timestamp order");
+    }
+    for log in &search_result.logs {
+        assert_eq!(log.trace_id.as_deref(), Some(trace_id));
+    }
+
+    println!(" Found {} logs for trace {}", search_result.logs.len(), trace_id);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_duplicate_batch_is_not_stored_twice() {
+    let logs = (0..5)
+        .map(|i| {
+            LogEntry::new(
+                "dedup-test-app".to_string(),
+                LogLevel::Info,
+                format!("Dedup test log #{}", i),
+                HashMap::new(),
+            )
+        })
+        .collect();
+    let batch = LogBatch::new(logs);
+
+    let client = reqwest::Client::new();
+    let mut responses = Vec::new();
+    for _ in 0..2 {
+        let response = client
+            .post("http://localhost:8001/ingest")
+            .json(&batch)
+            .send()
+            .await
+            .expect("Failed to connect to ingestion");
+        assert!(response.status().is_success());
+        responses.push(response.json::<serde_json::Value>().await.unwrap());
+    }
+
+    assert_eq!(responses[0]["accepted"], 5);
+    assert_eq!(responses[0]["duplicates"], 0);
+    assert_eq!(responses[1]["accepted"], 0);
+    assert_eq!(responses[1]["duplicates"], 5);
+
+    println!(" Retried batch reported {} duplicates", responses[1]["duplicates"]);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_size_limits() {
+    let client = reqwest::Client::new();
+    let limits = serde_json::json!({
+        "app_name": "limits-test-app",
+        "message_bytes": { "max": 32, "action": "truncate" },
+        "attribute_count": { "max": 2, "action": "reject" }
+    });
+    let response = client
+        .post("http://localhost:8003/limits")
+        .json(&limits)
+        .send()
+        .await
+        .expect("Failed to connect to config");
+    assert!(response.status().is_success());
+
+    // Ingestion polls the config service every 10 seconds.
+    tokio::time::sleep(tokio::time::Duration::from_secs(11)).await;
+
+    let mut attrs = HashMap::new();
+    for key in ["a", "b", "c"] {
+        attrs.insert(key.to_string(), "value".into());
+    }
+    let batch = LogBatch::new(vec![
+        LogEntry::new(
+            "limits-test-app".to_string(),
+            LogLevel::Info,
+            "A message that is well over the thirty-two byte limit".to_string(),
+            HashMap::new(),
+        ),
+        LogEntry::new("limits-test-app".to_string(), LogLevel::Info, "Too many attributes".to_string(), attrs),
+        LogEntry::new("limits-test-app".to_string(), LogLevel::Info, "Within limits".to_string(), HashMap::new()),
+    ]);
+
+    let response = client
+        .post("http://localhost:8001/ingest")
+        .json(&batch)
+        .send()
+        .await
+        .expect("Failed to connect to ingestion");
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    assert_eq!(body["accepted"], 2);
+    assert_eq!(body["truncated"], 1);
+    assert_eq!(body["rejected"], 1);
+
+    println!(" Size limits truncated {} and rejected {} entries", body["truncated"], body["rejected"]);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_reidentify_requires_operator_token() {
+    let client = reqwest::Client::new();
+    let response = client
+        .post("http://localhost:8001/reidentify")
+        .bearer_auth("not-an-operator")
+        .json(&serde_json::json!({ "tokens": ["psd_0000000000000000"], "reason": "integration test" }))
+        .send()
+        .await
+        .expect("Failed to connect to ingestion");
+
+    // 403 when no operators are configured, 401 for an unknown token otherwise.
+    assert!(
+        response.status() == 401 || response.status() == 403,
+        "Expected re-identification to be refused, got {}",
+        response.status()
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(["unauthorized", "forbidden"].contains(&body["error"]["code"].as_str().unwrap()));
+    assert_eq!(body["error"]["retryable"], false);
+
+    println!(" Re-identification refused without a valid operator token");
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_health_endpoints() {
@@ -1,12 +1,14 @@
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Path, Query, State,
+    },
     routing::{get, post},
     Json, Router,
 };
-use common::{LogEntry, SearchQuery};
+use common::{query, LogEntry, LogLevel, LogSystemError, SearchQuery, SortOrder};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tower_http::trace::TraceLayer;
 use tracing::{error, info};
@@ -26,6 +28,7 @@ async fn main() {
     let app = Router::new()
         .route("/search", post(search_logs))
         .route("/search", get(search_logs_get))
+        .route("/traces/:trace_id", get(get_trace))
         .route("/health", get(|| async { "OK" }))
         .layer(TraceLayer::new_for_http())
         .with_state(state);
@@ -39,106 +42,121 @@ async fn main() {
 
 async fn search_logs(
     State(state): State<Arc<AppState>>,
-    Json(query): Json<SearchQuery>,
-) -> impl IntoResponse {
+    payload: Result<Json<SearchQuery>, JsonRejection>,
+) -> Result<Json<SearchResponse>, LogSystemError> {
+    let Json(query) = payload?;
     info!("Received search request: {:?}", query);
 
-    let client = reqwest::Client::new();
-    match client
-        .post(&format!("{}/search", state.storage_url))
-        .json(&query)
-        .send()
-        .await
-    {
-        Ok(resp) if resp.status().is_success() => {
-            match resp.json::<Vec<LogEntry>>().await {
-                Ok(logs) => {
-                    info!("Found {} logs", logs.len());
-                    (StatusCode::OK, Json(SearchResponse { logs })).into_response()
-                }
-                Err(e) => {
-                    error!("Failed to parse response: {}", e);
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "Failed to parse results",
-                    )
-                        .into_response()
-                }
-            }
-        }
-        Ok(resp) => {
-            error!("Storage returned {}", resp.status());
-            (StatusCode::INTERNAL_SERVER_ERROR, "Storage error").into_response()
-        }
-        Err(e) => {
-            error!("Failed to connect to storage: {}", e);
-            (StatusCode::SERVICE_UNAVAILABLE, "Storage unavailable").into_response()
-        }
-    }
+    let logs = query_storage(&state, &query).await?;
+    info!("Found {} logs", logs.len());
+    Ok(Json(SearchResponse { logs }))
 }
 
 #[derive(Debug, Deserialize)]
 struct SearchQueryParams {
     app_name: Option<String>,
     level: Option<String>,
+    min_level: Option<String>,
+    host_name: Option<String>,
+    environment: Option<String>,
+    service_version: Option<String>,
+    /// Query language expression, e.g. `q=level>=warn AND "connection reset" conn*`.
+    q: Option<String>,
     limit: Option<usize>,
 }
 
 async fn search_logs_get(
     State(state): State<Arc<AppState>>,
-    Query(params): Query<SearchQueryParams>,
-) -> impl IntoResponse {
+    params: Result<Query<SearchQueryParams>, QueryRejection>,
+) -> Result<Json<SearchResponse>, LogSystemError> {
+    let Query(params) = params?;
     info!("Received GET search request: {:?}", params);
 
-    let level = params.level.and_then(|l| match l.as_str() {
-        "Debug" => Some(common::LogLevel::Debug),
-        "Info" => Some(common::LogLevel::Info),
-        "Warn" => Some(common::LogLevel::Warn),
-        "Error" => Some(common::LogLevel::Error),
-        _ => None,
-    });
+    let level = params.level.as_deref().map(str::parse::<LogLevel>).transpose()?;
+    let min_level = params.min_level.as_deref().map(str::parse::<LogLevel>).transpose()?;
+
+    let resource: HashMap<String, String> = [
+        ("host_name", params.host_name),
+        ("environment", params.environment),
+        ("service_version", params.service_version),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|v| (key.to_string(), v)))
+    .collect();
 
     let query = SearchQuery {
         app_name: params.app_name,
         level,
-        from: None,
-        to: None,
-        attributes: None,
+        min_level,
+        resource: (!resource.is_empty()).then_some(resource),
+        q: params.q.filter(|q| !q.trim().is_empty()),
         limit: params.limit,
+        ..Default::default()
     };
 
+    let logs = query_storage(&state, &query).await?;
+    info!("Found {} logs", logs.len());
+    Ok(Json(SearchResponse { logs }))
+}
+
+#[derive(Debug, Deserialize)]
+struct TraceParams {
+    limit: Option<usize>,
+}
+
+/// All logs of one trace, oldest first.
+async fn get_trace(
+    State(state): State<Arc<AppState>>,
+    trace_id: Result<Path<String>, PathRejection>,
+    params: Result<Query<TraceParams>, QueryRejection>,
+) -> Result<Json<SearchResponse>, LogSystemError> {
+    let Path(trace_id) = trace_id?;
+    let Query(params) = params?;
+    info!("Received trace request: {}", trace_id);
+
+    let query = SearchQuery {
+        trace_id: Some(trace_id.to_ascii_lowercase()),
+        limit: Some(params.limit.unwrap_or(1000)),
+        sort: Some(SortOrder::Asc),
+        ..Default::default()
+    };
+
+    let logs = query_storage(&state, &query).await?;
+    info!("Found {} logs for trace {}", logs.len(), trace_id);
+    Ok(Json(SearchResponse { logs }))
+}
+
+async fn query_storage(state: &AppState, query: &SearchQuery) -> Result<Vec<LogEntry>, LogSystemError> {
+    // Reject malformed expressions here so the caller gets the error position without a storage round trip.
+    if let Some(q) = &query.q {
+        query::parse(q)?;
+    }
+
     let client = reqwest::Client::new();
-    match client
-        .post(&format!("{}/search", state.storage_url))
-        .json(&query)
+    let resp = client
+        .post(format!("{}/search", state.storage_url))
+        .json(query)
         .send()
         .await
-    {
-        Ok(resp) if resp.status().is_success() => {
-            match resp.json::<Vec<LogEntry>>().await {
-                Ok(logs) => {
-                    info!("Found {} logs", logs.len());
-                    (StatusCode::OK, Json(SearchResponse { logs })).into_response()
-                }
-                Err(e) => {
-                    error!("Failed to parse response: {}", e);
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "Failed to parse results",
-                    )
-                        .into_response()
-                }
-            }
-        }
-        Ok(resp) => {
-            error!("Storage returned {}", resp.status());
-            (StatusCode::INTERNAL_SERVER_ERROR, "Storage error").into_response()
-        }
-        Err(e) => {
+        .map_err(|e| {
             error!("Failed to connect to storage: {}", e);
-            (StatusCode::SERVICE_UNAVAILABLE, "Storage unavailable").into_response()
-        }
+            LogSystemError::NetworkError(format!("storage unavailable: {}", e))
+        })?;
+
+    let status = resp.status();
+    let body = resp
+        .bytes()
+        .await
+        .map_err(|e| LogSystemError::NetworkError(format!("storage response: {}", e)))?;
+    if !status.is_success() {
+        error!("Storage returned {}", status);
+        return Err(LogSystemError::from_response_body(status.as_u16(), &body));
     }
+
+    serde_json::from_slice(&body).map_err(|e| {
+        error!("Failed to parse response: {}", e);
+        LogSystemError::StorageError(format!("unreadable search results: {}", e))
+    })
 }
 
 #[derive(Debug, Serialize)]
@@ -0,0 +1,221 @@
+//! Compiles query language expressions into Elasticsearch query DSL.
+
+use common::query::{Expr, Field, Op, Value};
+use common::{LogLevel, MessageMatch};
+use serde_json::{json, Value as Json};
+
+pub fn compile(expr: &Expr) -> Json {
+    match expr {
+        Expr::And(terms) => json!({ "bool": { "must": terms.iter().map(compile).collect::<Vec<_>>() } }),
+        Expr::Or(terms) => json!({
+            "bool": {
+                "should": terms.iter().map(compile).collect::<Vec<_>>(),
+                "minimum_should_match": 1
+            }
+        }),
+        Expr::Not(inner) => json!({ "bool": { "must_not": [compile(inner)] } }),
+        Expr::Message(message_match) => message_clause(message_match),
+        Expr::Compare { field, op, value } => compare_clause(field, *op, value),
+    }
+}
+
+/// Clause for a match on the analyzed `message` field.
+pub fn message_clause(message_match: &MessageMatch) -> Json {
+    match message_match {
+        MessageMatch::Text(text) => json!({
+            "match": { "message": { "query": text, "operator": "and" } }
+        }),
+        MessageMatch::Phrase(phrase) => json!({ "match_phrase": { "message": phrase } }),
+        MessageMatch::Prefix(prefix) => json!({ "match_phrase_prefix": { "message": prefix } }),
+        MessageMatch::Wildcard(pattern) => json!({
+            "wildcard": { "message": { "value": pattern, "case_insensitive": true } }
+        }),
+    }
+}
+
+/// Clause comparing the entry level with `level`.
+pub fn level_clause(op: Op, level: LogLevel) -> Json {
+    if op == Op::Eq {
+        return json!({ "term": { "level": level.as_str() } });
+    }
+
+    // Documents indexed before severity_number existed only carry the level name.
+    let level_names: Vec<&str> = LogLevel::ALL
+        .iter()
+        .filter(|candidate| match op {
+            Op::Gt => **candidate > level,
+            Op::Gte => **candidate >= level,
+            Op::Lt => **candidate < level,
+            Op::Lte | Op::Eq => **candidate <= level,
+        })
+        .map(|candidate| candidate.as_str())
+        .collect();
+    json!({
+        "bool": {
+            "should": [
+                { "range": { "severity_number": { range_key(op): level.severity_number() } } },
+                { "terms": { "level": level_names } }
+            ],
+            "minimum_should_match": 1
+        }
+    })
+}
+
+fn compare_clause(field: &Field, op: Op, value: &Value) -> Json {
+    let path = match field {
+        Field::Level => {
+            if let Value::Level(level) = value {
+                return level_clause(op, *level);
+            }
+            "level".to_string()
+        }
+        Field::AppName => "app_name".to_string(),
+        Field::Timestamp => "timestamp".to_string(),
+        Field::TraceId => "trace_id".to_string(),
+        Field::SpanId => "span_id".to_string(),
+        Field::Id => "id".to_string(),
+        Field::ErrorType => "error.type".to_string(),
+        Field::ErrorFunction => "error.top_frame.function".to_string(),
+        Field::ErrorFile => {
+            // `db.rs` matches the file name, `src/db.rs` or a wildcard the full path.
+            return json!({
+                "bool": {
+                    "should": [
+                        path_clause("error.top_frame.file_name", op, value),
+                        path_clause("error.top_frame.file", op, value)
+                    ],
+                    "minimum_should_match": 1
+                }
+            });
+        }
+        Field::Attribute(key) => format!("attributes.{}", key),
+        Field::Resource(key) => format!("resource.{}", key),
+    };
+
+    path_clause(&path, op, value)
+}
+
+fn path_clause(path: &str, op: Op, value: &Value) -> Json {
+    match (op, value) {
+        (_, Value::Any) => json!({ "exists": { "field": path } }),
+        (_, Value::Wildcard(pattern)) => json!({
+            "wildcard": { path: { "value": pattern, "case_insensitive": true } }
+        }),
+        (Op::Eq, value) => json!({ "term": { path: literal(value) } }),
+        (op, value) => json!({ "range": { path: { range_key(op): literal(value) } } }),
+    }
+}
+
+fn literal(value: &Value) -> Json {
+    match value {
+        Value::Level(level) => json!(level.as_str()),
+        Value::Int(i) => json!(i),
+        Value::Float(f) => json!(f),
+        Value::Bool(b) => json!(b),
+        Value::String(s) | Value::Wildcard(s) => json!(s),
+        Value::Any => Json::Null,
+    }
+}
+
+fn range_key(op: Op) -> &'static str {
+    match op {
+        Op::Gt => "gt",
+        Op::Gte => "gte",
+        Op::Lt => "lt",
+        Op::Lte | Op::Eq => "lte",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::query::parse;
+
+    fn dsl(query: &str) -> Json {
+        compile(&parse(query).unwrap())
+    }
+
+    #[test]
+    fn test_boolean_operators() {
+        assert_eq!(
+            dsl(r#"app:checkout AND (attributes.retries>3 OR NOT "timed out")"#),
+            json!({ "bool": { "must": [
+                { "term": { "app_name": "checkout" } },
+                { "bool": {
+                    "should": [
+                        { "range": { "attributes.retries": { "gt": 3 } } },
+                        { "bool": { "must_not": [{ "match_phrase": { "message": "timed out" } }] } }
+                    ],
+                    "minimum_should_match": 1
+                } }
+            ] } })
+        );
+    }
+
+    #[test]
+    fn test_messages_wildcards_and_exists() {
+        assert_eq!(
+            dsl("timeout"),
+            json!({ "match": { "message": { "query": "timeout", "operator": "and" } } })
+        );
+        assert_eq!(dsl("conn*"), json!({ "match_phrase_prefix": { "message": "conn" } }));
+        assert_eq!(
+            dsl("resource.host_name:web-?"),
+            json!({ "wildcard": { "resource.host_name": { "value": "web-?", "case_insensitive": true } } })
+        );
+        assert_eq!(dsl("trace_id:*"), json!({ "exists": { "field": "trace_id" } }));
+        assert_eq!(
+            dsl("timestamp<=2024-03-01"),
+            json!({ "range": { "timestamp": { "lte": "2024-03-01" } } })
+        );
+    }
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(dsl("level:Error"), json!({ "term": { "level": "Error" } }));
+        assert_eq!(
+            dsl("level>=Warn"),
+            json!({ "bool": {
+                "should": [
+                    { "range": { "severity_number": { "gte": 13 } } },
+                    { "terms": { "level": ["Warn", "Error", "Fatal"] } }
+                ],
+                "minimum_should_match": 1
+            } })
+        );
+        assert_eq!(
+            dsl("level<Info"),
+            json!({ "bool": {
+                "should": [
+                    { "range": { "severity_number": { "lt": 9 } } },
+                    { "terms": { "level": ["Trace", "Debug"] } }
+                ],
+                "minimum_should_match": 1
+            } })
+        );
+    }
+
+    #[test]
+    fn test_error_file_matches_name_or_path() {
+        assert_eq!(
+            dsl("error.file:db.rs"),
+            json!({ "bool": {
+                "should": [
+                    { "term": { "error.top_frame.file_name": "db.rs" } },
+                    { "term": { "error.top_frame.file": "db.rs" } }
+                ],
+                "minimum_should_match": 1
+            } })
+        );
+        assert_eq!(
+            dsl("error.file:src/*"),
+            json!({ "bool": {
+                "should": [
+                    { "wildcard": { "error.top_frame.file_name": { "value": "src/*", "case_insensitive": true } } },
+                    { "wildcard": { "error.top_frame.file": { "value": "src/*", "case_insensitive": true } } }
+                ],
+                "minimum_should_match": 1
+            } })
+        );
+    }
+}
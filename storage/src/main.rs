@@ -1,6 +1,12 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::StatusCode,
+    routing::post,
+    Json, Router,
+};
 use chrono::{DateTime, Duration, Utc};
-use common::{LogBatch, LogEntry, LogLevel, SearchQuery};
+use common::query::{self, Op};
+use common::{AttributeValue, ErrorInfo, LogBatch, LogEntry, LogLevel, LogSystemError, SearchQuery, SortOrder};
 use elasticsearch::{
     http::transport::{SingleNodeConnectionPool, TransportBuilder},
     Elasticsearch, SearchParts, DeleteByQueryParts, BulkOperation,
@@ -10,6 +16,8 @@ use std::sync::Arc;
 use tracing::{error, info, warn};
 use url::Url;
 
+mod dsl;
+
 const HOT_INDEX: &str = "logs-hot";
 const COLD_INDEX: &str = "logs-cold";
 
@@ -72,10 +80,48 @@ impl LogStorage {
                     "refresh_interval": "5s"
                 },
                 "mappings": {
+                    "dynamic_templates": [
+                        {
+                            "resource_attribute_strings": {
+                                "path_match": "resource.attributes.*",
+                                "match_mapping_type": "string",
+                                "mapping": { "type": "keyword", "ignore_above": 1024 }
+                            }
+                        },
+                        {
+                            "attribute_strings": {
+                                "path_match": "attributes.*",
+                                "match_mapping_type": "string",
+                                "mapping": { "type": "keyword", "ignore_above": 1024 }
+                            }
+                        },
+                        {
+                            "attribute_integers": {
+                                "path_match": "attributes.*",
+                                "match_mapping_type": "long",
+                                "mapping": { "type": "long" }
+                            }
+                        },
+                        {
+                            "attribute_floats": {
+                                "path_match": "attributes.*",
+                                "match_mapping_type": "double",
+                                "mapping": { "type": "double" }
+                            }
+                        },
+                        {
+                            "attribute_booleans": {
+                                "path_match": "attributes.*",
+                                "match_mapping_type": "boolean",
+                                "mapping": { "type": "boolean" }
+                            }
+                        }
+                    ],
                     "properties": {
                         "id": { "type": "keyword" },
                         "app_name": { "type": "keyword" },
                         "level": { "type": "keyword" },
+                        "severity_number": { "type": "integer" },
                         "timestamp": { "type": "date" },
                         "message": { 
                             "type": "text",
@@ -83,7 +129,41 @@ impl LogStorage {
                                 "keyword": { "type": "keyword", "ignore_above": 256 }
                             }
                         },
-                        "attributes": { "type": "object" }
+                        "attributes": { "type": "object" },
+                        "trace_id": { "type": "keyword" },
+                        "span_id": { "type": "keyword" },
+                        "truncated": { "type": "boolean" },
+                        "error": {
+                            "properties": {
+                                "type": { "type": "keyword" },
+                                "message": { "type": "text" },
+                                "frames": { "type": "object", "enabled": false },
+                                "causes": {
+                                    "properties": {
+                                        "type": { "type": "keyword" },
+                                        "message": { "type": "text" }
+                                    }
+                                },
+                                "top_frame": {
+                                    "properties": {
+                                        "function": { "type": "keyword" },
+                                        "file": { "type": "keyword" },
+                                        "file_name": { "type": "keyword" },
+                                        "line": { "type": "integer" }
+                                    }
+                                }
+                            }
+                        },
+                        "resource": {
+                            "properties": {
+                                "service_name": { "type": "keyword" },
+                                "service_version": { "type": "keyword" },
+                                "host_name": { "type": "keyword" },
+                                "process_id": { "type": "long" },
+                                "environment": { "type": "keyword" },
+                                "attributes": { "type": "object" }
+                            }
+                        }
                     }
                 }
             }))
@@ -99,17 +179,23 @@ impl LogStorage {
         Ok(())
     }
 
-    async fn store(&self, batch: LogBatch) {
+    async fn store(&self, batch: LogBatch) -> Result<(), LogSystemError> {
         let mut operations: Vec<BulkOperation<_>> = Vec::new();
 
         for log in &batch.logs {
             let doc = json!({
                 "id": log.id,
                 "app_name": log.app_name,
-                "level": format!("{:?}", log.level),
+                "level": log.level.as_str(),
+                "severity_number": log.level.severity_number(),
                 "timestamp": log.timestamp.to_rfc3339(),
                 "message": log.message,
-                "attributes": log.attributes
+                "attributes": log.attributes,
+                "trace_id": log.trace_id,
+                "span_id": log.span_id,
+                "truncated": log.truncated,
+                "error": log.error.as_ref().map(error_document),
+                "resource": batch.resource
             });
             
             operations.push(BulkOperation::index(doc).id(&log.id).into());
@@ -120,21 +206,35 @@ impl LogStorage {
             .bulk(elasticsearch::BulkParts::Index(HOT_INDEX))
             .body(operations)
             .send()
-            .await;
-
-        match response {
-            Ok(resp) => {
-                if resp.status_code().is_success() {
-                    info!("Stored batch {} with {} logs to Elasticsearch", batch.batch_id, batch.logs.len());
-                } else {
-                    error!("Failed to store batch: {:?}", resp.status_code());
-                }
-            }
-            Err(e) => error!("Elasticsearch error: {}", e),
+            .await
+            .map_err(|e| {
+                error!("Elasticsearch error: {}", e);
+                LogSystemError::StorageError(e.to_string())
+            })?;
+
+        if !response.status_code().is_success() {
+            error!("Failed to store batch: {:?}", response.status_code());
+            return Err(LogSystemError::StorageError(format!(
+                "bulk request failed with {}",
+                response.status_code()
+            )));
+        }
+
+        // A bulk request succeeds as a whole even when single documents are rejected.
+        let body = response.json::<Value>().await.unwrap_or_default();
+        if body["errors"].as_bool().unwrap_or(false) {
+            let items = body["items"].as_array().map(Vec::as_slice).unwrap_or_default();
+            let failure = bulk_failure(items)
+                .unwrap_or_else(|| LogSystemError::StorageError("bulk item rejected: unknown reason".to_string()));
+            error!("Failed to store some logs of batch {}: {}", batch.batch_id, failure);
+            return Err(failure);
         }
+
+        info!("Stored batch {} with {} logs to Elasticsearch", batch.batch_id, batch.logs.len());
+        Ok(())
     }
 
-    async fn search(&self, query: SearchQuery) -> Vec<LogEntry> {
+    async fn search(&self, query: SearchQuery) -> Result<Vec<LogEntry>, LogSystemError> {
         let mut must_clauses: Vec<Value> = Vec::new();
 
         if let Some(app_name) = &query.app_name {
@@ -142,7 +242,11 @@ impl LogStorage {
         }
 
         if let Some(level) = &query.level {
-            must_clauses.push(json!({ "term": { "level": format!("{:?}", level) } }));
+            must_clauses.push(json!({ "term": { "level": level.as_str() } }));
+        }
+
+        if let Some(min_level) = query.min_level {
+            must_clauses.push(dsl::level_clause(Op::Gte, min_level));
         }
 
         if query.from.is_some() || query.to.is_some() {
@@ -156,6 +260,18 @@ impl LogStorage {
             must_clauses.push(json!({ "range": { "timestamp": range } }));
         }
 
+        if let Some(trace_id) = &query.trace_id {
+            must_clauses.push(json!({ "term": { "trace_id": trace_id } }));
+        }
+
+        if let Some(resource) = &query.resource {
+            for (key, value) in resource {
+                must_clauses.push(json!({
+                    "term": { format!("resource.{}", key): value }
+                }));
+            }
+        }
+
         if let Some(attributes) = &query.attributes {
             for (key, value) in attributes {
                 must_clauses.push(json!({
@@ -164,6 +280,35 @@ impl LogStorage {
             }
         }
 
+        if let Some(ranges) = &query.attribute_ranges {
+            for (key, bounds) in ranges {
+                let mut range = json!({});
+                if let Some(gt) = bounds.gt {
+                    range["gt"] = json!(gt);
+                }
+                if let Some(gte) = bounds.gte {
+                    range["gte"] = json!(gte);
+                }
+                if let Some(lt) = bounds.lt {
+                    range["lt"] = json!(lt);
+                }
+                if let Some(lte) = bounds.lte {
+                    range["lte"] = json!(lte);
+                }
+                must_clauses.push(json!({ "range": { format!("attributes.{}", key): range } }));
+            }
+        }
+
+        if let Some(matches) = &query.message {
+            for message_match in matches {
+                must_clauses.push(dsl::message_clause(message_match));
+            }
+        }
+
+        if let Some(q) = &query.q {
+            must_clauses.push(dsl::compile(&query::parse(q)?));
+        }
+
         let search_body = json!({
             "query": {
                 "bool": {
@@ -175,7 +320,10 @@ impl LogStorage {
                 }
             },
             "size": query.limit.unwrap_or(100),
-            "sort": [{ "timestamp": { "order": "desc" } }]
+            "sort": [
+                { "timestamp": { "order": query.sort.unwrap_or(SortOrder::Desc).as_str() } },
+                { "id": { "order": query.sort.unwrap_or(SortOrder::Desc).as_str() } }
+            ]
         });
 
         let response = self
@@ -183,45 +331,43 @@ impl LogStorage {
             .search(SearchParts::Index(&[HOT_INDEX, COLD_INDEX]))
             .body(search_body)
             .send()
-            .await;
-
-        match response {
-            Ok(resp) => {
-                if let Ok(body) = resp.json::<Value>().await {
-                    let hits = body["hits"]["hits"].as_array();
-                    
-                    if let Some(hits) = hits {
-                        let logs: Vec<LogEntry> = hits
-                            .iter()
-                            .filter_map(|hit| {
-                                let source = &hit["_source"];
-                                self.parse_log_entry(source)
-                            })
-                            .collect();
-
-                        info!("Found {} logs matching query", logs.len());
-                        return logs;
-                    }
-                }
-                error!("Failed to parse search response");
-                Vec::new()
-            }
-            Err(e) => {
+            .await
+            .map_err(|e| {
                 error!("Search error: {}", e);
-                Vec::new()
-            }
+                LogSystemError::StorageError(e.to_string())
+            })?;
+
+        if !response.status_code().is_success() {
+            error!("Search failed: {:?}", response.status_code());
+            return Err(LogSystemError::StorageError(format!(
+                "search request failed with {}",
+                response.status_code()
+            )));
         }
+
+        let body = response.json::<Value>().await.map_err(|e| {
+            error!("Failed to parse search response: {}", e);
+            LogSystemError::StorageError(e.to_string())
+        })?;
+        let hits = body["hits"]["hits"].as_array().ok_or_else(|| {
+            error!("Failed to parse search response");
+            LogSystemError::StorageError("search response without hits".to_string())
+        })?;
+
+        let logs: Vec<LogEntry> = hits
+            .iter()
+            .filter_map(|hit| {
+                let source = &hit["_source"];
+                self.parse_log_entry(source)
+            })
+            .collect();
+
+        info!("Found {} logs matching query", logs.len());
+        Ok(logs)
     }
 
     fn parse_log_entry(&self, source: &Value) -> Option<LogEntry> {
-        let level_str = source["level"].as_str()?;
-        let level = match level_str {
-            "Debug" => LogLevel::Debug,
-            "Info" => LogLevel::Info,
-            "Warn" => LogLevel::Warn,
-            "Error" => LogLevel::Error,
-            _ => return None,
-        };
+        let level: LogLevel = source["level"].as_str()?.parse().ok()?;
 
         let timestamp_str = source["timestamp"].as_str()?;
         let timestamp = DateTime::parse_from_rfc3339(timestamp_str)
@@ -233,7 +379,9 @@ impl LogStorage {
             .map(|obj| {
                 obj.iter()
                     .filter_map(|(k, v)| {
-                        v.as_str().map(|s| (k.clone(), s.to_string()))
+                        serde_json::from_value::<AttributeValue>(v.clone())
+                            .ok()
+                            .map(|value| (k.clone(), value))
                     })
                     .collect()
             })
@@ -246,6 +394,10 @@ impl LogStorage {
             timestamp,
             message: source["message"].as_str()?.to_string(),
             attributes,
+            trace_id: source["trace_id"].as_str().map(str::to_string),
+            span_id: source["span_id"].as_str().map(str::to_string),
+            truncated: source["truncated"].as_bool().unwrap_or(false),
+            error: serde_json::from_value(source["error"].clone()).ok(),
         })
     }
 
@@ -340,6 +492,51 @@ impl LogStorage {
     }
 }
 
+/// The entry's error with its top frame pulled out, so that the exception type
+/// and the place it was raised can be searched together.
+fn error_document(error: &ErrorInfo) -> Value {
+    let mut doc = json!(error);
+    if let Some(frame) = error.top_frame() {
+        doc["top_frame"] = json!({
+            "function": frame.function,
+            "file": frame.file,
+            "file_name": frame.file_name(),
+            "line": frame.line,
+        });
+    }
+    doc
+}
+
+/// The error for the items a bulk request failed to index. Overload and server
+/// errors are worth retrying, since documents are indexed under their ids;
+/// anything else means the documents themselves were rejected.
+fn bulk_failure(items: &[Value]) -> Option<LogSystemError> {
+    let failed: Vec<&Value> = items
+        .iter()
+        .map(|item| &item["index"])
+        .filter(|item| !item["error"].is_null())
+        .collect();
+    let first = failed.first()?;
+
+    let retryable = |item: &&Value| {
+        let status = item["status"].as_u64().unwrap_or(500);
+        let mapping = item["error"]["type"].as_str() == Some("mapper_parsing_exception");
+        (status == 429 || status >= 500) && !mapping
+    };
+    if let Some(item) = failed.iter().copied().find(retryable) {
+        let reason = item["error"]["reason"].as_str().unwrap_or("unknown reason");
+        return Some(LogSystemError::StorageError(format!("bulk item rejected: {}", reason)));
+    }
+
+    let ids: Vec<&str> = failed.iter().filter_map(|item| item["_id"].as_str()).collect();
+    let reason = first["error"]["reason"].as_str().unwrap_or("unknown reason");
+    Some(LogSystemError::InvalidPayload(format!(
+        "rejected logs {}: {}",
+        ids.join(", "),
+        reason
+    )))
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
@@ -373,16 +570,56 @@ async fn main() {
 
 async fn store_logs(
     State(storage): State<Arc<LogStorage>>,
-    Json(batch): Json<LogBatch>,
-) -> impl IntoResponse {
-    storage.store(batch).await;
-    StatusCode::OK
+    payload: Result<Json<LogBatch>, JsonRejection>,
+) -> Result<StatusCode, LogSystemError> {
+    let Json(batch) = payload?;
+    storage.store(batch).await?;
+    Ok(StatusCode::OK)
 }
 
 async fn search_logs(
     State(storage): State<Arc<LogStorage>>,
-    Json(query): Json<SearchQuery>,
-) -> impl IntoResponse {
-    let results = storage.search(query).await;
-    (StatusCode::OK, Json(results))
+    payload: Result<Json<SearchQuery>, JsonRejection>,
+) -> Result<Json<Vec<LogEntry>>, LogSystemError> {
+    let Json(query) = payload?;
+    let results = storage.search(query).await?;
+    Ok(Json(results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, status: u64, error_type: &str) -> Value {
+        json!({
+            "index": {
+                "_id": id,
+                "status": status,
+                "error": { "type": error_type, "reason": format!("{} failed", id) }
+            }
+        })
+    }
+
+    #[test]
+    fn test_bulk_failure_classifies_items() {
+        let stored = json!({ "index": { "_id": "a", "status": 201 } });
+        assert!(bulk_failure(std::slice::from_ref(&stored)).is_none());
+
+        let failure = bulk_failure(&[
+            stored.clone(),
+            item("b", 400, "mapper_parsing_exception"),
+            item("c", 409, "version_conflict_engine_exception"),
+        ])
+        .unwrap();
+        assert!(!failure.retryable());
+        assert_eq!(failure.to_string(), "Invalid payload: rejected logs b, c: b failed");
+
+        let failure = bulk_failure(&[
+            item("b", 400, "mapper_parsing_exception"),
+            item("d", 429, "es_rejected_execution_exception"),
+        ])
+        .unwrap();
+        assert!(failure.retryable());
+        assert_eq!(failure.to_string(), "Storage error: bulk item rejected: d failed");
+    }
 }